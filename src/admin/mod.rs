@@ -0,0 +1,121 @@
+use crate::backend::{BackendPools, OverrideMode};
+use crate::gossip::GossipLayer;
+use anyhow::{Result, anyhow, bail};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info, warn};
+
+// a line based control socket for operators, one command per line:
+//
+//   drain <pool> <backend> [ttl_ms]
+//   maint <pool> <backend> [ttl_ms]
+//   enable <pool> <backend> [ttl_ms]
+//   clear <pool> <backend>
+//
+// every line is answered with "ok" or "error: <reason>". overrides are
+// gossiped, so whichever node gets the command, every node applies it
+pub struct AdminServer {
+    listener: TcpListener,
+    gossip: GossipLayer,
+    backend_pools: BackendPools,
+}
+
+impl AdminServer {
+    pub async fn bind(
+        addr: SocketAddr,
+        gossip: GossipLayer,
+        backend_pools: BackendPools,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        Ok(Self {
+            listener,
+            gossip,
+            backend_pools,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    pub async fn run(self) {
+        loop {
+            match self.listener.accept().await {
+                Ok((stream, peer)) => {
+                    debug!("Admin connection from {}", peer);
+                    let gossip = self.gossip.clone();
+                    let backend_pools = self.backend_pools.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = serve(stream, &gossip, &backend_pools).await {
+                            debug!("Admin connection from {} failed: {}", peer, e);
+                        }
+                    });
+                }
+                Err(e) => warn!("Failed to accept an admin connection: {}", e),
+            }
+        }
+    }
+}
+
+async fn serve(
+    stream: TcpStream,
+    gossip: &GossipLayer,
+    backend_pools: &BackendPools,
+) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match execute(&line, gossip, backend_pools).await {
+            Ok(()) => {
+                info!("Admin command: {}", line.trim());
+                "ok\n".to_string()
+            }
+            Err(e) => format!("error: {}\n", e),
+        };
+        writer.write_all(reply.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+async fn execute(line: &str, gossip: &GossipLayer, backend_pools: &BackendPools) -> Result<()> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (command, pool, backend, ttl) = match words[..] {
+        [command, pool, backend] => (command, pool, backend, None),
+        [command, pool, backend, ttl] if command != "clear" => {
+            let ttl_ms: u64 = ttl
+                .parse()
+                .map_err(|_| anyhow!("'{}' is not a ttl in milliseconds", ttl))?;
+            (command, pool, backend, Some(Duration::from_millis(ttl_ms)))
+        }
+        _ => {
+            bail!("usage: drain|maint|enable <pool> <backend> [ttl_ms], or clear <pool> <backend>")
+        }
+    };
+    let addr: SocketAddr = backend
+        .parse()
+        .map_err(|_| anyhow!("'{}' is not a backend address", backend))?;
+    let Some(backend_pool) = backend_pools.get(pool) else {
+        bail!("no pool named '{}'", pool);
+    };
+    if !backend_pool
+        .read()
+        .await
+        .get_all_backends()
+        .iter()
+        .any(|b| b.addr == addr)
+    {
+        bail!("pool '{}' has no backend {}", pool, addr);
+    }
+
+    if command == "clear" {
+        gossip.clear_backend_override(pool, addr).await;
+        return Ok(());
+    }
+    let mode: OverrideMode = command.parse()?;
+    gossip.set_backend_override(pool, addr, mode, ttl).await
+}
@@ -4,4 +4,23 @@ use std::net::SocketAddr;
 pub struct Backend {
     pub addr: SocketAddr,
     pub weight: u32,
+    // across the whole cluster, None for no limit
+    pub max_connections: Option<u32>,
+    // locality, e.g. an availability zone. None is in no zone in particular
+    pub zone: Option<String>,
+    // failover tier, lower numbers take traffic first
+    pub priority: u32,
+}
+
+impl Backend {
+    // in no zone, in the first tier and without a connection limit
+    pub fn new(addr: SocketAddr, weight: u32) -> Self {
+        Self {
+            addr,
+            weight,
+            max_connections: None,
+            zone: None,
+            priority: 0,
+        }
+    }
 }
@@ -1,6 +1,10 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::time::Instant;
 
 use super::Backend;
+use super::overrides::{BackendOverride, OverrideMode};
+use crate::gossip::{HybridTimestamp, MemberId};
 
 #[derive(Debug, Clone, PartialEq)]
 pub(super) enum HealthStatus {
@@ -11,22 +15,65 @@ pub(super) enum HealthStatus {
 #[derive(Debug)]
 pub(super) struct BackendHealth {
     pub(super) backend: Backend,
+    // cluster-wide view, last writer wins
     pub(super) status: HealthStatus,
+    pub(super) version: HybridTimestamp,
+    pub(super) origin: MemberId,
+    // what our own health checks currently say
+    pub(super) local_status: HealthStatus,
     pub(super) consecutive_failures: u32,
     pub(super) consecutive_successes: u32,
     pub(super) last_check: Instant,
-    pub(super) last_local_check: Instant,
+    // operator overrides replicated through the gossip key/value store
+    pub(super) operator_override: Option<BackendOverride>,
+    pub(super) weight_override: Option<u32>,
+    // connections this node has open to the backend right now
+    pub(super) active_connections: Arc<AtomicU32>,
+    // how many of the backend's max_connections this node may use, until
+    // the next rebalance. None without a limit
+    pub(super) connection_budget: Option<u32>,
 }
 
 impl BackendHealth {
-    pub(super) fn new(backend: Backend) -> Self {
+    pub(super) fn new(backend: Backend, origin: MemberId) -> Self {
         Self {
+            // as if we were alone, until the first rebalance
+            connection_budget: backend.max_connections,
             backend,
             status: HealthStatus::Healthy,
+            version: HybridTimestamp::default(),
+            origin,
+            local_status: HealthStatus::Healthy,
             consecutive_successes: 0,
             consecutive_failures: 0,
             last_check: Instant::now(),
-            last_local_check: Instant::now(),
+            operator_override: None,
+            weight_override: None,
+            active_connections: Arc::new(AtomicU32::new(0)),
+        }
+    }
+
+    pub(super) fn weight(&self) -> u32 {
+        self.weight_override.unwrap_or(self.backend.weight)
+    }
+
+    pub(super) fn active_override(&self) -> Option<OverrideMode> {
+        self.operator_override
+            .filter(BackendOverride::is_active)
+            .map(|o| o.mode)
+    }
+
+    pub(super) fn has_capacity(&self) -> bool {
+        self.connection_budget
+            .is_none_or(|budget| self.active_connections.load(Ordering::Relaxed) < budget)
+    }
+
+    // an operator override wins over the health checks while it lasts
+    pub(super) fn in_rotation(&self) -> bool {
+        match self.active_override() {
+            Some(OverrideMode::Drain | OverrideMode::Maint) => false,
+            Some(OverrideMode::Enable) => true,
+            None => self.status == HealthStatus::Healthy,
         }
     }
 }
@@ -1,6 +1,13 @@
+#[allow(clippy::module_inception)]
 mod backend;
 mod health;
+mod overrides;
 mod pool;
+mod pools;
+mod sticky;
 
 pub use backend::Backend;
-pub use pool::{BackendPool, SharedBackendPool};
\ No newline at end of file
+pub use overrides::{BackendOverride, OverrideMode};
+pub use pool::{ActiveConnection, BackendPool, SharedBackendPool};
+pub use pools::{BackendPools, DEFAULT_POOL};
+pub use sticky::StickyPin;
@@ -0,0 +1,92 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverrideMode {
+    // no new connections, health checks carry on
+    Drain,
+    // no new connections and no health checks, the backend is being worked on
+    Maint,
+    // new connections whatever the health checks say
+    Enable,
+}
+
+impl fmt::Display for OverrideMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OverrideMode::Drain => "drain",
+            OverrideMode::Maint => "maint",
+            OverrideMode::Enable => "enable",
+        })
+    }
+}
+
+impl FromStr for OverrideMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drain" => Ok(OverrideMode::Drain),
+            "maint" => Ok(OverrideMode::Maint),
+            "enable" => Ok(OverrideMode::Enable),
+            _ => Err(anyhow::anyhow!("Unknown override mode '{}'", s)),
+        }
+    }
+}
+
+// an operator's call on a backend, replicated to every node through the
+// gossip key/value store. it wins over health checks until it expires or
+// is cleared. expiry is wall clock time, so nodes agree on it as far as
+// their clocks do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackendOverride {
+    pub mode: OverrideMode,
+    // unix milliseconds, None lasts until cleared
+    pub expires_at_ms: Option<u64>,
+}
+
+pub(super) fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+impl BackendOverride {
+    pub fn new(mode: OverrideMode, ttl: Option<Duration>) -> Self {
+        Self {
+            mode,
+            expires_at_ms: ttl.map(|ttl| unix_ms() + ttl.as_millis() as u64),
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.expires_at_ms.is_none_or(|at| unix_ms() < at)
+    }
+
+    // "drain", or "maint 1760000000000" with an expiry
+    pub fn encode(&self) -> Vec<u8> {
+        match self.expires_at_ms {
+            Some(at) => format!("{} {}", self.mode, at),
+            None => self.mode.to_string(),
+        }
+        .into_bytes()
+    }
+
+    pub fn decode(value: &str) -> Option<Self> {
+        let mut parts = value.split_whitespace();
+        let mode = parts.next()?.parse().ok()?;
+        let expires_at_ms = match parts.next() {
+            Some(at) => Some(at.parse().ok()?),
+            None => None,
+        };
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Self {
+            mode,
+            expires_at_ms,
+        })
+    }
+}
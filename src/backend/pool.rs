@@ -1,69 +1,370 @@
 use super::backend::Backend;
 use super::health::{BackendHealth, HealthStatus};
-use dashmap::DashMap;
-use std::net::SocketAddr;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
-use tokio::sync::RwLock;
+use super::overrides::{BackendOverride, OverrideMode};
+use super::sticky::{StickyPin, StickyTable};
+use crate::config::Strategy;
+use crate::gossip::{HybridClock, MemberId};
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{Notify, RwLock};
 use tracing::{debug, info, warn};
 
+// active_tier while no priority group has enough backends available
+const ALL_TIERS: u64 = u64::MAX;
+
 pub struct BackendPool {
     backends: Vec<BackendHealth>,
     current_index: Arc<AtomicUsize>,
+    local_id: MemberId,
+    clock: Arc<HybridClock>,
+    // client -> backend pins, None with sticky sessions off
+    sticky: Option<Mutex<StickyTable>>,
+    // backends in our zone get the traffic, unless less than
+    // min_zone_capacity_percent of their weight is available
+    zone: Option<String>,
+    min_zone_capacity_percent: u32,
+    spilling_over: AtomicBool,
+    // the lowest backend priority with at least min_healthy_backends
+    // available takes the traffic
+    min_healthy_backends: usize,
+    active_tier: AtomicU64,
 }
 
 impl BackendPool {
-    pub fn new(backends: Vec<Backend>) -> Self {
-        let backends = backends.into_iter().map(BackendHealth::new).collect();
+    pub fn new(backends: Vec<Backend>, local_id: MemberId, clock: Arc<HybridClock>) -> Self {
+        let first_tier = backends.iter().map(|b| b.priority).min().unwrap_or(0);
+        let backends = backends
+            .into_iter()
+            .map(|backend| BackendHealth::new(backend, local_id.clone()))
+            .collect();
 
         Self {
             backends,
             current_index: Arc::new(AtomicUsize::new(0)),
+            local_id,
+            clock,
+            sticky: None,
+            zone: None,
+            min_zone_capacity_percent: 0,
+            spilling_over: AtomicBool::new(false),
+            min_healthy_backends: 1,
+            active_tier: AtomicU64::new(first_tier as u64),
         }
     }
 
+    // how many backends of a priority group have to be available for it to
+    // take traffic, 1 by default
+    pub fn set_min_healthy_backends(&mut self, min_healthy_backends: usize) {
+        self.min_healthy_backends = min_healthy_backends;
+    }
+
+    // routes to backends in `zone` only, spilling over to the other zones
+    // while less than `min_capacity_percent` of its backend weight is in
+    // rotation and has connections to spare
+    pub fn prefer_zone(&mut self, zone: String, min_capacity_percent: u32) {
+        self.zone = Some(zone);
+        self.min_zone_capacity_percent = min_capacity_percent;
+    }
+
+    // clients go back to the backend they were last sent to, on any node,
+    // for `ttl` after their last connection. this node pins at most
+    // `max_pins` clients at a time
+    pub fn enable_sticky_sessions(&mut self, ttl: Duration, max_pins: usize) {
+        self.sticky = Some(Mutex::new(StickyTable::new(ttl, max_pins)));
+    }
+
+    pub fn clock(&self) -> Arc<HybridClock> {
+        self.clock.clone()
+    }
+
+    // weighted round robin over the backends in rotation: healthy ones,
+    // unless an operator override says otherwise, that have connections
+    // to spare. only the first priority group with enough of those gets
+    // traffic, and within it our own zone if we have one
     pub fn select_backend(&self) -> Option<Backend> {
-        if self.backends.is_empty() {
+        self.pick(Strategy::RoundRobin)
+            .map(|backend_health| backend_health.backend.clone())
+    }
+
+    // select_backend, counting the connection against the backend's limit
+    // until the returned guard is dropped
+    pub fn acquire_backend(&self) -> Option<ActiveConnection> {
+        self.pick(Strategy::RoundRobin).map(ActiveConnection::new)
+    }
+
+    // acquire_backend, but a client that is pinned to a backend goes back
+    // to it as long as it is in rotation. a full backend takes the
+    // connection elsewhere without moving the pin
+    pub fn acquire_backend_for(&self, client: IpAddr) -> Option<ActiveConnection> {
+        self.acquire_backend_with(client, Strategy::RoundRobin)
+    }
+
+    // acquire_backend_for, choosing among the candidates with `strategy`
+    pub fn acquire_backend_with(
+        &self,
+        client: IpAddr,
+        strategy: Strategy,
+    ) -> Option<ActiveConnection> {
+        let Some(sticky) = &self.sticky else {
+            return self.pick(strategy).map(ActiveConnection::new);
+        };
+        let mut sticky = sticky.lock().unwrap();
+
+        let tier = self.active_tier(&self.available());
+        let pinned = sticky.get(client).and_then(|pin| {
+            self.backends.iter().find(|b| {
+                b.backend.addr == pin.backend
+                    && b.in_rotation()
+                    && b.weight() > 0
+                    && tier.is_none_or(|tier| b.backend.priority == tier)
+            })
+        });
+        let backend_health = match pinned {
+            Some(backend_health) if backend_health.has_capacity() => backend_health,
+            Some(_) => return self.pick(strategy).map(ActiveConnection::new),
+            None => self.pick(strategy)?,
+        };
+        sticky.pin(client, backend_health.backend.addr);
+        Some(ActiveConnection::new(backend_health))
+    }
+
+    // where `client` is pinned, if anywhere
+    pub fn sticky_pin(&self, client: IpAddr) -> Option<StickyPin> {
+        self.sticky.as_ref()?.lock().unwrap().get(client)
+    }
+
+    // a pin written to the store by `writer`, or deleted from it
+    pub fn apply_sticky_pin(&self, client: IpAddr, pin: Option<StickyPin>, writer: &MemberId) {
+        if let Some(sticky) = &self.sticky {
+            sticky
+                .lock()
+                .unwrap()
+                .apply(client, pin, *writer == self.local_id);
+        }
+    }
+
+    // notified when there are pins to publish, None with sticky sessions off
+    pub fn sticky_changes(&self) -> Option<Arc<Notify>> {
+        Some(self.sticky.as_ref()?.lock().unwrap().changed())
+    }
+
+    // pins made here since the last call, and our expired ones to delete
+    pub fn take_sticky_updates(&self) -> Vec<(IpAddr, Option<StickyPin>)> {
+        match &self.sticky {
+            Some(sticky) => sticky.lock().unwrap().take_updates(),
+            None => Vec::new(),
+        }
+    }
+
+    fn unpin_backend(&mut self, addr: SocketAddr) {
+        if let Some(sticky) = &mut self.sticky {
+            let unpinned = sticky.get_mut().unwrap().unpin_backend(addr);
+            if unpinned > 0 {
+                info!("Unpinned {} clients from backend {}", unpinned, addr);
+            }
+        }
+    }
+
+    fn available(&self) -> Vec<&BackendHealth> {
+        self.backends
+            .iter()
+            .filter(|b| b.in_rotation() && b.weight() > 0 && b.has_capacity())
+            .collect()
+    }
+
+    fn pick(&self, strategy: Strategy) -> Option<&BackendHealth> {
+        let available = self.available();
+        let tier = self.active_tier(&available);
+        let in_tier = available
+            .into_iter()
+            .filter(|b| tier.is_none_or(|tier| b.backend.priority == tier))
+            .collect();
+        let candidates = self.zone_candidates(in_tier, tier);
+
+        let total_weight: u64 = candidates.iter().map(|b| b.weight() as u64).sum();
+        if total_weight == 0 {
+            warn!("No healthy backends available!");
             return None;
         }
 
-        let start_index = self.current_index.fetch_add(1, Ordering::Relaxed) % self.backends.len();
-        for _ in 0..(self.backends.len()) {
-            let backend_health = &self.backends[start_index];
+        let turn = self.current_index.fetch_add(1, Ordering::Relaxed);
+        if strategy == Strategy::LeastConnections {
+            // fewest open connections per unit of weight, ties take turns
+            let count = candidates.len();
+            return (0..count)
+                .map(|i| candidates[(turn + i) % count])
+                .min_by(|a, b| {
+                    let load =
+                        |b: &BackendHealth| b.active_connections.load(Ordering::Relaxed) as u64;
+                    (load(a) * b.weight() as u64).cmp(&(load(b) * a.weight() as u64))
+                });
+        }
 
-            if backend_health.status == HealthStatus::Healthy {
-                return Some(backend_health.backend.clone());
+        let mut pick = turn as u64 % total_weight;
+        for backend_health in candidates {
+            let weight = backend_health.weight() as u64;
+            if pick < weight {
+                return Some(backend_health);
             }
+            pick -= weight;
         }
-        warn!("No healthy backends available!");
         None
     }
 
-    pub fn update_health(&mut self, addr: SocketAddr, is_healthy: bool) {
-        if let Some(backend_health) = self.backends.iter_mut().find(|b| b.backend.addr == addr) {
-            backend_health.last_check = Instant::now();
-            backend_health.last_local_check = Instant::now();
-
-            if is_healthy {
-                backend_health.consecutive_successes += 1;
-                backend_health.consecutive_failures = 0;
-
-                if backend_health.consecutive_successes >= 2
-                    && backend_health.status == HealthStatus::Unhealthy
-                {
-                    info!("Backend {} is now HEALTHY", addr);
-                    backend_health.status = HealthStatus::Healthy;
+    // the lowest priority with enough available backends, None if there is
+    // none and every priority has to help out
+    fn active_tier(&self, available: &[&BackendHealth]) -> Option<u32> {
+        let mut counts = BTreeMap::new();
+        for backend_health in available {
+            *counts.entry(backend_health.backend.priority).or_insert(0) += 1;
+        }
+        let tier = counts
+            .into_iter()
+            .find(|(_, count)| *count >= self.min_healthy_backends)
+            .map(|(priority, _)| priority);
+
+        let current = tier.map_or(ALL_TIERS, u64::from);
+        let previous = self.active_tier.swap(current, Ordering::Relaxed);
+        if previous != current {
+            match tier {
+                Some(_) if current < previous => {
+                    info!("Traffic is back on priority {} backends", current)
                 }
+                Some(_) => warn!(
+                    "Fewer than {} priority {} backends available, failing over to priority {}",
+                    self.min_healthy_backends, previous, current
+                ),
+                None => warn!(
+                    "No backend priority has {} backends available, using every priority",
+                    self.min_healthy_backends
+                ),
+            }
+        }
+        tier
+    }
+
+    // the available backends in our zone, or all of them if too few are
+    fn zone_candidates<'a>(
+        &self,
+        available: Vec<&'a BackendHealth>,
+        tier: Option<u32>,
+    ) -> Vec<&'a BackendHealth> {
+        let Some(zone) = &self.zone else {
+            return available;
+        };
+        let in_zone = |b: &BackendHealth| {
+            b.backend.zone.as_ref() == Some(zone)
+                && tier.is_none_or(|tier| b.backend.priority == tier)
+        };
+
+        let capacity: u64 = self
+            .backends
+            .iter()
+            .filter(|b| in_zone(b))
+            .map(|b| b.weight() as u64)
+            .sum();
+        if capacity == 0 {
+            return available;
+        }
+        let healthy: u64 = available
+            .iter()
+            .filter(|b| in_zone(b))
+            .map(|b| b.weight() as u64)
+            .sum();
+
+        let spill_over =
+            healthy == 0 || healthy * 100 < capacity * self.min_zone_capacity_percent as u64;
+        if self.spilling_over.swap(spill_over, Ordering::Relaxed) != spill_over {
+            if spill_over {
+                warn!(
+                    "Only {}% of zone {} is available, spilling over to other zones",
+                    healthy * 100 / capacity,
+                    zone
+                );
             } else {
-                backend_health.consecutive_successes = 0;
-                backend_health.consecutive_failures += 1;
-
-                if backend_health.consecutive_failures >= 2
-                    && backend_health.status == HealthStatus::Healthy
-                {
-                    warn!("Backend {} is now UNHEALTHY", addr);
-                    backend_health.status = HealthStatus::Unhealthy;
+                info!("Zone {} has recovered, routing within it again", zone);
+            }
+        }
+
+        if spill_over {
+            available
+        } else {
+            available.into_iter().filter(|b| in_zone(b)).collect()
+        }
+    }
+
+    // None clears the override, the health checks decide again
+    pub fn set_override(&mut self, addr: SocketAddr, operator_override: Option<BackendOverride>) {
+        let Some(backend_health) = self.backends.iter_mut().find(|b| b.backend.addr == addr) else {
+            return;
+        };
+        if backend_health.operator_override != operator_override {
+            match operator_override {
+                Some(o) => info!("Backend {} is in {} mode", addr, o.mode),
+                None => info!("Backend {} override cleared", addr),
+            }
+            backend_health.operator_override = operator_override;
+        }
+    }
+
+    pub fn get_override(&self, addr: SocketAddr) -> Option<BackendOverride> {
+        self.backends
+            .iter()
+            .find(|b| b.backend.addr == addr)?
+            .operator_override
+    }
+
+    // None goes back to the configured weight
+    pub fn set_weight(&mut self, addr: SocketAddr, weight: Option<u32>) {
+        let Some(backend_health) = self.backends.iter_mut().find(|b| b.backend.addr == addr) else {
+            return;
+        };
+        if backend_health.weight_override != weight {
+            backend_health.weight_override = weight;
+            info!("Backend {} weight is now {}", addr, backend_health.weight());
+        }
+    }
+
+    pub fn update_health(&mut self, addr: SocketAddr, is_healthy: bool) {
+        let Some(backend_health) = self.backends.iter_mut().find(|b| b.backend.addr == addr) else {
+            return;
+        };
+        backend_health.last_check = Instant::now();
+
+        let observed = if is_healthy {
+            backend_health.consecutive_successes += 1;
+            backend_health.consecutive_failures = 0;
+
+            (backend_health.consecutive_successes >= 2
+                && backend_health.local_status == HealthStatus::Unhealthy)
+                .then_some(HealthStatus::Healthy)
+        } else {
+            backend_health.consecutive_successes = 0;
+            backend_health.consecutive_failures += 1;
+
+            (backend_health.consecutive_failures >= 2
+                && backend_health.local_status == HealthStatus::Healthy)
+                .then_some(HealthStatus::Unhealthy)
+        };
+
+        // only transitions of our own observation are published, so nodes that
+        // disagree about a backend don't keep overwriting each other
+        if let Some(status) = observed {
+            backend_health.local_status = status.clone();
+            backend_health.version = self.clock.now();
+            backend_health.origin = self.local_id.clone();
+
+            if backend_health.status != status {
+                match status {
+                    HealthStatus::Healthy => info!("Backend {} is now HEALTHY", addr),
+                    HealthStatus::Unhealthy => warn!("Backend {} is now UNHEALTHY", addr),
+                }
+                backend_health.status = status;
+                if backend_health.status == HealthStatus::Unhealthy {
+                    self.unpin_backend(addr);
                 }
             }
         }
@@ -73,82 +374,158 @@ impl BackendPool {
         self.backends.iter().map(|bh| bh.backend.clone()).collect()
     }
 
-    pub fn get_backend_health_updates(&self) -> Vec<crate::gossip::BackendUpdate> {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+    // backends in maintenance aren't health checked
+    pub fn get_checked_backends(&self) -> Vec<Backend> {
+        self.backends
+            .iter()
+            .filter(|bh| bh.active_override() != Some(OverrideMode::Maint))
+            .map(|bh| bh.backend.clone())
+            .collect()
+    }
+
+    // our own open connections per backend, for the rest of the cluster.
+    // only backends with a max_connections, nobody needs the others' counts
+    pub fn local_connections(&self) -> Vec<(SocketAddr, u32)> {
+        self.backends
+            .iter()
+            .filter(|bh| bh.backend.max_connections.is_some())
+            .map(|bh| {
+                (
+                    bh.backend.addr,
+                    bh.active_connections.load(Ordering::Relaxed),
+                )
+            })
+            .collect()
+    }
+
+    // splits what is left of each backend's max_connections between the
+    // `nodes` flux nodes, given how many connections the other nodes
+    // reported. busier nodes get a bigger share of the headroom, and every
+    // node some, so an idle one can still pick up traffic. shares are
+    // rounded up, the limit can be overshot by a connection per node
+    pub fn rebalance(&mut self, remote_connections: &HashMap<SocketAddr, u64>, nodes: usize) {
+        for backend_health in &mut self.backends {
+            let Some(max_connections) = backend_health.backend.max_connections else {
+                continue;
+            };
+            let local = backend_health.active_connections.load(Ordering::Relaxed) as u64;
+            let remote = remote_connections
+                .get(&backend_health.backend.addr)
+                .copied()
+                .unwrap_or(0);
+
+            let headroom = (max_connections as u64).saturating_sub(local + remote);
+            let share = (headroom * (local + 1)).div_ceil(local + remote + nodes.max(1) as u64);
+            let budget = (local + share).min(u32::MAX as u64) as u32;
+
+            if backend_health.connection_budget != Some(budget) {
+                debug!(
+                    "Backend {} budget is now {} ({} local, {} remote, limit {})",
+                    backend_health.backend.addr, budget, local, remote, max_connections
+                );
+                backend_health.connection_budget = Some(budget);
+            }
+        }
+    }
+
+    // our health records, as gossiped for the pool named `pool`
+    pub fn get_backend_health_updates(&self, pool: &str) -> Vec<crate::gossip::BackendUpdate> {
         self.backends
             .iter()
+            .filter(|backend_health| !backend_health.version.is_zero())
             .map(|backend_health| crate::gossip::BackendUpdate {
+                pool: pool.to_string(),
                 backend_addr: backend_health.backend.addr,
                 is_healthy: backend_health.status == HealthStatus::Healthy,
-                from_member: crate::gossip::MemberId("local".to_string()),
-                timestamp,
+                from_member: backend_health.origin.clone(),
+                version: backend_health.version,
             })
             .collect()
     }
 
+    // last-writer-wins merge: the record with the highest (version, origin)
+    // wins, so every node ends up with the same status whatever order updates
+    // arrive in
     pub fn apply_backend_update(&mut self, update: &crate::gossip::BackendUpdate) {
-        if let Some(backend_health) = self
+        if !self.clock.observe(update.version) {
+            return;
+        }
+
+        let Some(backend_health) = self
             .backends
             .iter_mut()
             .find(|b| b.backend.addr == update.backend_addr)
+        else {
+            return;
+        };
+
+        if (update.version, &update.from_member) <= (backend_health.version, &backend_health.origin)
         {
-            let time_since_local_check = backend_health.last_local_check.elapsed();
-            let trust_local = time_since_local_check < Duration::from_secs(6);
+            return;
+        }
+
+        let new_status = if update.is_healthy {
+            HealthStatus::Healthy
+        } else {
+            HealthStatus::Unhealthy
+        };
 
-            let should_apply = if trust_local {
+        let went_unhealthy =
+            backend_health.status != new_status && new_status == HealthStatus::Unhealthy;
+        if backend_health.status != new_status {
+            info!(
+                "Gossip update: Backend {} is now {} (from {})",
+                update.backend_addr,
                 if update.is_healthy {
-                    false
+                    "HEALTHY"
                 } else {
-                    backend_health.status == HealthStatus::Healthy
-                        && backend_health.consecutive_failures == 0
-                }
-            } else {
-                true
-            };
-
-            if !should_apply {
-                debug!(
-                    "Ignoring gossip about {} - we checked locally {}s ago",
-                    update.backend_addr,
-                    time_since_local_check.as_secs()
-                );
-                return;
-            }
+                    "UNHEALTHY"
+                },
+                update.from_member.0
+            );
+            backend_health.status = new_status;
+        } else {
+            debug!(
+                "Gossip update: Backend {} version {:?} from {}",
+                update.backend_addr, update.version, update.from_member.0
+            );
+        }
 
-            let new_status = if update.is_healthy {
-                HealthStatus::Healthy
-            } else {
-                HealthStatus::Unhealthy
-            };
+        backend_health.version = update.version;
+        backend_health.origin = update.from_member.clone();
+        if went_unhealthy {
+            self.unpin_backend(update.backend_addr);
+        }
+    }
+}
 
-            if backend_health.status != new_status {
-                info!(
-                    "Gossip update: Backend {} is now {} (from {})",
-                    update.backend_addr,
-                    if update.is_healthy {
-                        "HEALTHY"
-                    } else {
-                        "UNHEALTHY"
-                    },
-                    update.from_member.0
-                );
-                backend_health.status = new_status;
+pub type SharedBackendPool = Arc<RwLock<BackendPool>>;
 
-                if update.is_healthy {
-                    backend_health.consecutive_successes = 2;
-                    backend_health.consecutive_failures = 0;
-                } else {
-                    backend_health.consecutive_failures = 2;
-                    backend_health.consecutive_successes = 0;
-                }
-            }
+// a connection to a backend, counted against its limit while it lives
+#[derive(Debug)]
+pub struct ActiveConnection {
+    backend: Backend,
+    counter: Arc<AtomicU32>,
+}
 
-            backend_health.last_check = Instant::now();
+impl ActiveConnection {
+    fn new(backend_health: &BackendHealth) -> Self {
+        backend_health
+            .active_connections
+            .fetch_add(1, Ordering::Relaxed);
+        Self {
+            backend: backend_health.backend.clone(),
+            counter: backend_health.active_connections.clone(),
         }
     }
+
+    pub fn backend(&self) -> &Backend {
+        &self.backend
+    }
 }
 
-pub type SharedBackendPool = Arc<RwLock<BackendPool>>;
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        self.counter.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,68 @@
+use super::pool::SharedBackendPool;
+use crate::gossip::{BackendUpdate, HybridClock};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+// the pool a plain [[backends]] list makes up
+pub const DEFAULT_POOL: &str = "default";
+
+// every backend pool of this process by name. health, overrides, sticky
+// pins and load are gossiped per pool, so a backend that is in two pools is
+// tracked separately in each
+#[derive(Clone, Default)]
+pub struct BackendPools {
+    pools: BTreeMap<String, SharedBackendPool>,
+}
+
+impl BackendPools {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, pool: SharedBackendPool) {
+        self.pools.insert(name.into(), pool);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SharedBackendPool> {
+        self.pools.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &SharedBackendPool)> {
+        self.pools.iter().map(|(name, pool)| (name.as_str(), pool))
+    }
+
+    // the pools of a process share its clock, a fresh one if there are none
+    pub async fn clock(&self) -> Arc<HybridClock> {
+        match self.pools.values().next() {
+            Some(pool) => pool.read().await.clock(),
+            None => Arc::new(HybridClock::new()),
+        }
+    }
+
+    // our health records of every pool
+    pub async fn backend_health_updates(&self) -> Vec<BackendUpdate> {
+        let mut updates = Vec::new();
+        for (name, pool) in &self.pools {
+            updates.extend(pool.read().await.get_backend_health_updates(name));
+        }
+        updates
+    }
+
+    // updates for pools we don't have are dropped
+    pub async fn apply_backend_updates(&self, updates: &[BackendUpdate]) {
+        for update in updates {
+            if let Some(pool) = self.pools.get(&update.pool) {
+                pool.write().await.apply_backend_update(update);
+            }
+        }
+    }
+}
+
+// a process with a single pool, named DEFAULT_POOL
+impl From<SharedBackendPool> for BackendPools {
+    fn from(pool: SharedBackendPool) -> Self {
+        let mut pools = Self::new();
+        pools.insert(DEFAULT_POOL, pool);
+        pools
+    }
+}
@@ -0,0 +1,174 @@
+use super::overrides::unix_ms;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+// a client pinned to a backend, replicated to every node through the gossip
+// key/value store so its next connection lands on the same backend whichever
+// node takes it. expiry is wall clock time, like overrides
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StickyPin {
+    pub backend: SocketAddr,
+    // unix milliseconds
+    pub expires_at_ms: u64,
+}
+
+impl StickyPin {
+    pub fn new(backend: SocketAddr, ttl: Duration) -> Self {
+        Self {
+            backend,
+            expires_at_ms: unix_ms() + ttl.as_millis() as u64,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        unix_ms() < self.expires_at_ms
+    }
+
+    // "127.0.0.1:3000 1760000000000"
+    pub fn encode(&self) -> Vec<u8> {
+        format!("{} {}", self.backend, self.expires_at_ms).into_bytes()
+    }
+
+    pub fn decode(value: &str) -> Option<Self> {
+        let (backend, expires_at_ms) = value.split_once(' ')?;
+        Some(Self {
+            backend: backend.parse().ok()?,
+            expires_at_ms: expires_at_ms.parse().ok()?,
+        })
+    }
+}
+
+struct Entry {
+    pin: StickyPin,
+    // written by this node, which deletes it from the store once it expires
+    local: bool,
+}
+
+// the pins this node knows of, and the ones it made that still have to be
+// written to the store
+pub(super) struct StickyTable {
+    ttl: Duration,
+    // every pin is a key in the store that each node keeps and push-pull
+    // syncs until it expires, so a node holds at most this many of its own
+    max_pins: usize,
+    pins: HashMap<IpAddr, Entry>,
+    unpublished: Vec<(IpAddr, Option<StickyPin>)>,
+    changed: Arc<Notify>,
+}
+
+impl StickyTable {
+    pub(super) fn new(ttl: Duration, max_pins: usize) -> Self {
+        Self {
+            ttl,
+            max_pins,
+            pins: HashMap::new(),
+            unpublished: Vec::new(),
+            changed: Arc::new(Notify::new()),
+        }
+    }
+
+    pub(super) fn changed(&self) -> Arc<Notify> {
+        self.changed.clone()
+    }
+
+    pub(super) fn get(&self, client: IpAddr) -> Option<StickyPin> {
+        self.pins
+            .get(&client)
+            .map(|entry| entry.pin)
+            .filter(StickyPin::is_active)
+    }
+
+    // pins `client` to `backend`, or extends the pin once half its ttl is
+    // used up, so busy clients stay where they are
+    pub(super) fn pin(&mut self, client: IpAddr, backend: SocketAddr) {
+        let renew_at = unix_ms() + self.ttl.as_millis() as u64 / 2;
+        if self
+            .get(client)
+            .is_some_and(|pin| pin.backend == backend && pin.expires_at_ms > renew_at)
+        {
+            return;
+        }
+        if !self.pins.get(&client).is_some_and(|entry| entry.local) {
+            self.make_room();
+        }
+        let pin = StickyPin::new(backend, self.ttl);
+        self.pins.insert(client, Entry { pin, local: true });
+        self.unpublished.push((client, Some(pin)));
+        self.changed.notify_one();
+    }
+
+    // at the limit, the pin of ours that expires first is deleted to make
+    // room for a new one. that client just gets a new pin next time
+    fn make_room(&mut self) {
+        // ours are among all the pins, counting them only pays off near it
+        if self.pins.len() < self.max_pins {
+            return;
+        }
+        let local = self.pins.values().filter(|entry| entry.local).count();
+        if local < self.max_pins {
+            return;
+        }
+        let oldest = self
+            .pins
+            .iter()
+            .filter(|(_, entry)| entry.local)
+            .min_by_key(|(_, entry)| entry.pin.expires_at_ms)
+            .map(|(client, _)| *client);
+        if let Some(client) = oldest {
+            self.pins.remove(&client);
+            self.unpublished.push((client, None));
+        }
+    }
+
+    // a pin from the store, None if it was deleted
+    pub(super) fn apply(&mut self, client: IpAddr, pin: Option<StickyPin>, local: bool) {
+        match pin {
+            Some(pin) => {
+                self.pins.insert(client, Entry { pin, local });
+            }
+            None => {
+                self.pins.remove(&client);
+            }
+        }
+    }
+
+    // every node drops the pins of a backend that went unhealthy, there is
+    // no need to gossip that. ours are only expired, so the next sweep
+    // deletes them from the store too. the clients get a new pin on their
+    // next connection
+    pub(super) fn unpin_backend(&mut self, backend: SocketAddr) -> usize {
+        let before = self.pins.len();
+        self.pins
+            .retain(|_, entry| entry.pin.backend != backend || entry.local);
+        let mut unpinned = before - self.pins.len();
+        for entry in self.pins.values_mut() {
+            if entry.pin.backend == backend && entry.pin.is_active() {
+                entry.pin.expires_at_ms = 0;
+                unpinned += 1;
+            }
+        }
+        if unpinned > 0 {
+            self.changed.notify_one();
+        }
+        unpinned
+    }
+
+    // new pins to write to the store, and deletions of our own pins that
+    // have expired
+    pub(super) fn take_updates(&mut self) -> Vec<(IpAddr, Option<StickyPin>)> {
+        let mut updates = std::mem::take(&mut self.unpublished);
+        self.pins.retain(|client, entry| {
+            if entry.pin.is_active() {
+                return true;
+            }
+            if entry.local {
+                updates.push((*client, None));
+            }
+            false
+        });
+        updates
+    }
+}
@@ -18,12 +18,12 @@ async fn main() {
         let counter = counter.clone();
         let task = tokio::spawn(async move {
             while start.elapsed().as_secs() < duration_secs {
-                if let Ok(mut stream) = TcpStream::connect("127.0.0.1:8080").await {
-                    if stream.write_all(b"test\n").await.is_ok() {
-                        let mut buf = [0u8; 1024];
-                        if stream.read(&mut buf).await.is_ok() {
-                            counter.fetch_add(1, Ordering::Relaxed);
-                        }
+                if let Ok(mut stream) = TcpStream::connect("127.0.0.1:8080").await
+                    && stream.write_all(b"test\n").await.is_ok()
+                {
+                    let mut buf = [0u8; 1024];
+                    if stream.read(&mut buf).await.is_ok() {
+                        counter.fetch_add(1, Ordering::Relaxed);
                     }
                 }
             }
@@ -1,26 +1,367 @@
+use crate::backend::DEFAULT_POOL;
+use anyhow::{Result, anyhow};
 use serde::Deserialize;
-use std::net::SocketAddr;
+use std::collections::{BTreeMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
+    #[serde(default)]
     pub server: ServerConfig,
     pub gossip: GossipConfig,
+    // a single service: [server] listen_addr in front of these backends,
+    // with the top level rate_limit and sticky_sessions. they become the
+    // frontend and pool named "default"
+    #[serde(default)]
     pub backends: Vec<Backend>,
+    // many services: every frontend routes to one of the named pools
+    #[serde(default)]
+    pub pools: Vec<PoolConfig>,
+    #[serde(default)]
+    pub frontends: Vec<FrontendConfig>,
+    // the default for pools without a health_check of their own
     pub health_check: HealthCheckConfig,
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    #[serde(default)]
+    pub sticky_sessions: Option<StickySessionConfig>,
+    #[serde(default)]
+    pub virtual_ips: Vec<VirtualIpConfig>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct GossipConfig {
+    // nodes only gossip with nodes that have the same cluster name
+    #[serde(default = "default_cluster_name")]
+    pub cluster_name: String,
     pub bind_addr: SocketAddr,
+    // address peers should gossip to if it differs from bind_addr (NAT,
+    // containers). detected for wildcard binds from the interface that
+    // routes to 8.8.8.8, loopback if there is no such route
+    #[serde(default)]
+    pub advertise_addr: Option<SocketAddr>,
     pub gossip_interval_ms: u64,
     pub ping_timeout_ms: u64,
     pub suspect_timeout_ms: u64,
+    #[serde(default)]
     pub seed_nodes: Vec<SocketAddr>,
+    // dynamic seeds, looked up again on every join attempt and reconnect round
+    #[serde(default)]
+    pub seed_sources: Vec<SeedSource>,
+    // nameservers for dns/srv seed sources, defaults to /etc/resolv.conf
+    #[serde(default)]
+    pub dns_servers: Vec<SocketAddr>,
+    // Lifeguard: upper bound of the local health multiplier applied to probe
+    // interval and timeouts
+    #[serde(default = "default_awareness_max_multiplier")]
+    pub awareness_max_multiplier: u32,
+    // Lifeguard: suspicion starts at suspect_timeout_ms * this and shrinks
+    // towards suspect_timeout_ms as other members confirm it
+    #[serde(default = "default_suspicion_max_timeout_mult")]
+    pub suspicion_max_timeout_mult: u32,
+    #[serde(default = "default_suspicion_confirmations")]
+    pub suspicion_confirmations: u32,
+    // how often we try to reach dead members and unreachable seeds again,
+    // and how long dead members are remembered for that
+    #[serde(default = "default_reconnect_interval_ms")]
+    pub reconnect_interval_ms: u64,
+    #[serde(default = "default_dead_member_timeout_ms")]
+    pub dead_member_timeout_ms: u64,
+    // lz4-compress large messages for peers that can decompress them
+    #[serde(default = "default_true")]
+    pub compression: bool,
+    // send messages that don't fit in a UDP packet over TCP to peers that
+    // accept it, instead of dropping piggybacked updates
+    #[serde(default = "default_true")]
+    pub stream_fallback: bool,
+    // user events: how many recent lamport times are remembered to drop
+    // duplicates, and how many events may be queued for gossip
+    #[serde(default = "default_event_buffer_size")]
+    pub event_buffer_size: usize,
+    // how often the key/value store is fully synced with a random member,
+    // and how long deleted keys are remembered so stale copies can't revive them
+    #[serde(default = "default_push_pull_interval_ms")]
+    pub push_pull_interval_ms: u64,
+    #[serde(default = "default_kv_tombstone_ttl_ms")]
+    pub kv_tombstone_ttl_ms: u64,
+    // where membership and backend health are saved, so a restarted node
+    // can rejoin through its old peers and resume its incarnation
+    #[serde(default)]
+    pub state_file: Option<PathBuf>,
+    #[serde(default = "default_snapshot_interval_ms")]
+    pub snapshot_interval_ms: u64,
+    // how often active connection counts are shared with the cluster and
+    // per-node shares of backend connection limits recomputed
+    #[serde(default = "default_load_report_interval_ms")]
+    pub load_report_interval_ms: u64,
+    // how long the lowest-id alive member waits before it starts leading,
+    // so the leader it replaces has time to step down
+    #[serde(default = "default_leader_lease_ms")]
+    pub leader_lease_ms: u64,
+    // key/value metadata advertised to the rest of the cluster, e.g.
+    // [gossip.tags] zone = "eu-west-1a"
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+}
+
+// [[gossip.seed_sources]]
+// type = "dns"
+// name = "flux.internal"
+// port = 7946
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SeedSource {
+    // A/AAAA records, all with the same gossip port
+    Dns {
+        name: String,
+        port: u16,
+    },
+    // SRV records, targets are resolved to A/AAAA
+    Srv {
+        name: String,
+    },
+    // one host:port per line
+    File {
+        path: PathBuf,
+    },
+    // a command printing one host:port per line
+    Command {
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_event_buffer_size() -> usize {
+    512
+}
+
+fn default_push_pull_interval_ms() -> u64 {
+    30_000
+}
+
+fn default_leader_lease_ms() -> u64 {
+    10_000
+}
+
+fn default_kv_tombstone_ttl_ms() -> u64 {
+    3_600_000
+}
+
+fn default_snapshot_interval_ms() -> u64 {
+    30_000
+}
+
+fn default_load_report_interval_ms() -> u64 {
+    1_000
+}
+
+fn default_cluster_name() -> String {
+    "flux".to_string()
+}
+
+fn default_awareness_max_multiplier() -> u32 {
+    8
+}
+
+fn default_suspicion_max_timeout_mult() -> u32 {
+    6
+}
+
+fn default_suspicion_confirmations() -> u32 {
+    3
+}
+
+fn default_reconnect_interval_ms() -> u64 {
+    10_000
+}
+
+fn default_dead_member_timeout_ms() -> u64 {
+    3_600_000
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct ServerConfig {
+    // the default frontend, if any
+    #[serde(default)]
+    pub listen_addr: Option<SocketAddr>,
+    // proxy address advertised to the cluster as member metadata, the
+    // first frontend's by default
+    #[serde(default)]
+    pub advertise_addr: Option<SocketAddr>,
+    // backends in this zone are preferred, also advertised as the zone tag
+    #[serde(default)]
+    pub zone: Option<String>,
+    // other zones only get traffic while less than this share of our own
+    // zone's backend weight is healthy
+    #[serde(default = "default_min_zone_capacity_percent")]
+    pub min_zone_capacity_percent: u32,
+    // a backend priority group takes traffic while at least this many of
+    // its backends are available, otherwise the next one does. for the
+    // default pool, named pools have their own
+    #[serde(default = "default_min_healthy_backends")]
+    pub min_healthy_backends: usize,
+    // the control socket for drain/maint/enable, off unless set
+    #[serde(default)]
+    pub admin_addr: Option<SocketAddr>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen_addr: None,
+            advertise_addr: None,
+            zone: None,
+            min_zone_capacity_percent: default_min_zone_capacity_percent(),
+            min_healthy_backends: default_min_healthy_backends(),
+            admin_addr: None,
+        }
+    }
+}
+
+// [[pools]]
+// name = "api"
+// [[pools.backends]]
+// addr = "10.0.1.10:8080"
+// weight = 1
+// backends are health checked, connection pooled and gossiped per pool
+#[derive(Debug, Deserialize, Clone)]
+pub struct PoolConfig {
+    // no '/' or whitespace, it is part of gossiped keys
+    pub name: String,
+    pub backends: Vec<Backend>,
+    #[serde(default = "default_min_healthy_backends")]
+    pub min_healthy_backends: usize,
+    #[serde(default)]
+    pub health_check: Option<HealthCheckConfig>,
+    #[serde(default)]
+    pub sticky_sessions: Option<StickySessionConfig>,
+}
+
+// [[frontends]]
+// name = "api"
+// listen_addr = "0.0.0.0:8080"
+// pool = "api"
+#[derive(Debug, Deserialize, Clone)]
+pub struct FrontendConfig {
+    // no '/' or whitespace, it is part of gossiped keys
+    pub name: String,
     pub listen_addr: SocketAddr,
+    pub pool: String,
+    #[serde(default)]
+    pub strategy: Strategy,
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+// how a frontend picks among the backends that may take a connection
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    // in turn, as often as their weight says
+    #[default]
+    RoundRobin,
+    // the one with the fewest open connections for its weight
+    LeastConnections,
+}
+
+fn default_min_healthy_backends() -> usize {
+    1
+}
+
+fn default_min_zone_capacity_percent() -> u32 {
+    50
+}
+
+// new connections per client IP (or per listener) across the whole cluster
+#[derive(Debug, Deserialize, Clone)]
+pub struct RateLimitConfig {
+    #[serde(default)]
+    pub key: RateLimitKey,
+    // tokens per second, and how many can be saved up
+    pub rate: f64,
+    pub burst: u32,
+    #[serde(default)]
+    pub on_limit: OnLimit,
+    // with on_limit = "delay", connections that would wait longer are rejected
+    #[serde(default = "default_max_delay_ms")]
+    pub max_delay_ms: u64,
+    // how often consumption is gossiped and shares are rebalanced
+    #[serde(default = "default_rate_limit_report_interval_ms")]
+    pub report_interval_ms: u64,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKey {
+    #[default]
+    ClientIp,
+    Listener,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnLimit {
+    // close the connection right away
+    #[default]
+    Reject,
+    // hold it until a token frees up
+    Delay,
+}
+
+fn default_max_delay_ms() -> u64 {
+    1_000
+}
+
+fn default_rate_limit_report_interval_ms() -> u64 {
+    1_000
+}
+
+// clients go back to the same backend whichever node they connect to
+#[derive(Debug, Deserialize, Clone)]
+pub struct StickySessionConfig {
+    // how long a client stays pinned after its last connection
+    #[serde(default = "default_sticky_ttl_ms")]
+    pub ttl_ms: u64,
+    // pins this node keeps at a time, the ones closest to expiring make
+    // room for new ones
+    #[serde(default = "default_sticky_max_pins")]
+    pub max_pins: usize,
+}
+
+fn default_sticky_ttl_ms() -> u64 {
+    1_800_000
+}
+
+fn default_sticky_max_pins() -> usize {
+    10_000
+}
+
+// [[virtual_ips]]
+// addr = "10.0.0.100"
+// priority = 150
+// up_command = ["/etc/flux/vip-up.sh", "eth0"]
+// the alive node with the highest priority owns the address and runs
+// up_command, the one that loses it down_command. FLUX_VIP and
+// FLUX_VIP_STATE (up or down) are set in their environment
+#[derive(Debug, Deserialize, Clone)]
+pub struct VirtualIpConfig {
+    pub addr: IpAddr,
+    pub priority: u32,
+    // take the address over from a lower priority owner, instead of only
+    // when it goes away
+    #[serde(default = "default_true")]
+    pub preempt: bool,
+    // program and arguments, nothing is run if empty
+    #[serde(default)]
+    pub up_command: Vec<String>,
+    #[serde(default)]
+    pub down_command: Vec<String>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -33,12 +374,113 @@ pub struct HealthCheckConfig {
 pub struct Backend {
     pub addr: SocketAddr,
     pub weight: u32,
+    // connections all flux nodes together open to this backend at most
+    #[serde(default)]
+    pub max_connections: Option<u32>,
+    #[serde(default)]
+    pub zone: Option<String>,
+    // backends with a higher priority number only get traffic while too few
+    // with a lower one are available
+    #[serde(default)]
+    pub priority: u32,
 }
 
 impl Config {
     pub fn from_file(path: &str) -> anyhow::Result<Self> {
         let content = std::fs::read_to_string(path)?;
         let config: Config = toml::from_str(&content)?;
+        config.validate()?;
         Ok(config)
     }
+
+    // the named pools, plus the default one if there are top level backends
+    pub fn pools(&self) -> Vec<PoolConfig> {
+        let mut pools = Vec::new();
+        if !self.backends.is_empty() {
+            pools.push(PoolConfig {
+                name: DEFAULT_POOL.to_string(),
+                backends: self.backends.clone(),
+                min_healthy_backends: self.server.min_healthy_backends,
+                health_check: None,
+                sticky_sessions: self.sticky_sessions.clone(),
+            });
+        }
+        pools.extend(self.pools.iter().cloned());
+        pools
+    }
+
+    // the named frontends, plus the default one if [server] has a listen_addr
+    pub fn frontends(&self) -> Vec<FrontendConfig> {
+        let mut frontends = Vec::new();
+        if let Some(listen_addr) = self.server.listen_addr {
+            frontends.push(FrontendConfig {
+                name: DEFAULT_POOL.to_string(),
+                listen_addr,
+                pool: DEFAULT_POOL.to_string(),
+                strategy: Strategy::default(),
+                rate_limit: self.rate_limit.clone(),
+            });
+        }
+        frontends.extend(self.frontends.iter().cloned());
+        frontends
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let pools = self.pools();
+        let frontends = self.frontends();
+        if frontends.is_empty() {
+            return Err(anyhow!(
+                "Nothing to listen on, set [server] listen_addr or add [[frontends]]"
+            ));
+        }
+
+        let mut pool_names = HashSet::new();
+        for pool in &pools {
+            check_name("pool", &pool.name)?;
+            if !pool_names.insert(pool.name.as_str()) {
+                return Err(anyhow!("Pool '{}' is defined twice", pool.name));
+            }
+        }
+
+        let mut frontend_names = HashSet::new();
+        for frontend in &frontends {
+            check_name("frontend", &frontend.name)?;
+            if !frontend_names.insert(frontend.name.as_str()) {
+                return Err(anyhow!("Frontend '{}' is defined twice", frontend.name));
+            }
+            if !pool_names.contains(frontend.pool.as_str()) {
+                return Err(anyhow!(
+                    "Frontend '{}' routes to unknown pool '{}'",
+                    frontend.name,
+                    frontend.pool
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// names end up in gossiped keys, and a key plus its value has to fit in a
+// 512 byte entry. a frontend's rate limit report has the longest key,
+// ratelimit/<name>/<member id>, the id up to 63 bytes for a scoped IPv6
+// address, next to a report of up to 400 bytes
+pub const MAX_NAME_LEN: usize = 32;
+
+fn check_name(kind: &str, name: &str) -> Result<()> {
+    if name.is_empty() || name.contains('/') || name.contains(char::is_whitespace) {
+        return Err(anyhow!(
+            "Invalid {} name '{}', it can't be empty or contain '/' or whitespace",
+            kind,
+            name
+        ));
+    }
+    if name.len() > MAX_NAME_LEN {
+        return Err(anyhow!(
+            "Invalid {} name '{}', it can't be longer than {} bytes",
+            kind,
+            name,
+            MAX_NAME_LEN
+        ));
+    }
+    Ok(())
 }
@@ -1,11 +1,11 @@
 use anyhow::Result;
 use dashmap::DashMap;
+use socket2::TcpKeepalive;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpStream;
 use tracing::debug;
-use socket2::{Domain, Protocol, Socket, TcpKeepalive, Type};
-use std::time::Duration;
 
 pub struct ConnectionPool {
     pools: Arc<DashMap<SocketAddr, Vec<TcpStream>>>,
@@ -37,14 +37,14 @@ impl ConnectionPool {
         }
         debug!("Creating new connection to {}", backend);
         let stream = TcpStream::connect(backend).await?;
-        
+
         configure_keepalive(&stream)?;
-        
+
         Ok(stream)
     }
 
     pub async fn return_connection(&self, backend: SocketAddr, stream: TcpStream) {
-        let mut pool = self.pools.entry(backend).or_insert_with(Vec::new);
+        let mut pool = self.pools.entry(backend).or_default();
 
         if pool.len() < self.max_size_per_backend {
             pool.push(stream);
@@ -58,15 +58,13 @@ impl ConnectionPool {
             drop(stream);
         }
     }
-
-   
 }
 
 async fn is_connection_alive(stream: &TcpStream) -> bool {
     let mut buf = [0u8; 1];
     match stream.try_read(&mut buf) {
-        Ok(0) => false,  // EOF = connection closed
-        Ok(_) => true,   // data available = connection alive (shouldn't happen for pooled connections)
+        Ok(0) => false, // EOF = connection closed
+        Ok(_) => true, // data available = connection alive (shouldn't happen for pooled connections)
         Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
             // no data available but connection is still open
             // this is the expected case for idle pooled connections
@@ -78,16 +76,16 @@ async fn is_connection_alive(stream: &TcpStream) -> bool {
 
 fn configure_keepalive(stream: &TcpStream) -> Result<()> {
     let sock_ref = socket2::SockRef::from(stream);
-    
+
     let keepalive = TcpKeepalive::new()
         .with_time(Duration::from_secs(30)) // probe after 30 seconds of idle
         .with_interval(Duration::from_secs(10)); // probe every 10 seconds
-    
+
     sock_ref.set_tcp_keepalive(&keepalive)?;
-    
+
     // enable TCP_NODELAY to reduce latency
     stream.set_nodelay(true)?;
-    
+
     Ok(())
 }
 
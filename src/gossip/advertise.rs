@@ -0,0 +1,49 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use tracing::{info, warn};
+
+// address peers should use to reach something bound to `bind_addr`. a
+// wildcard bind (0.0.0.0 / ::) is replaced by the address of the interface
+// we'd route outbound traffic through, to a public address (8.8.8.8 or its
+// IPv6 equivalent). without a route there, e.g. on an isolated network,
+// loopback is advertised and advertise_addr has to be set
+pub fn advertise_addr(bind_addr: SocketAddr) -> SocketAddr {
+    if !bind_addr.ip().is_unspecified() {
+        return bind_addr;
+    }
+
+    match outbound_ip(bind_addr.is_ipv6()) {
+        Some(ip) => {
+            let addr = SocketAddr::new(ip, bind_addr.port());
+            info!("Advertising {} for wildcard address {}", addr, bind_addr);
+            addr
+        }
+        None => {
+            let loopback = if bind_addr.is_ipv6() {
+                IpAddr::V6(Ipv6Addr::LOCALHOST)
+            } else {
+                IpAddr::V4(Ipv4Addr::LOCALHOST)
+            };
+            let addr = SocketAddr::new(loopback, bind_addr.port());
+            warn!(
+                "Could not detect an outbound interface for wildcard address {}, advertising {} - set advertise_addr",
+                bind_addr, addr
+            );
+            addr
+        }
+    }
+}
+
+fn outbound_ip(ipv6: bool) -> Option<IpAddr> {
+    // connecting a UDP socket sends nothing, it only makes the kernel pick
+    // the route and with it our source address
+    let (bind, probe) = if ipv6 {
+        ("[::]:0", "[2001:4860:4860::8888]:53")
+    } else {
+        ("0.0.0.0:0", "8.8.8.8:53")
+    };
+
+    let socket = UdpSocket::bind(bind).ok()?;
+    socket.connect(probe).ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    (!ip.is_unspecified()).then_some(ip)
+}
@@ -0,0 +1,49 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+// lifeguard's local health multiplier. missed acks and refuted suspicions
+// mean we are likely the slow node, so they stretch our probe timing
+#[derive(Debug)]
+pub struct Awareness {
+    max: u32,
+    score: AtomicU32,
+}
+
+impl Awareness {
+    pub fn new(max_multiplier: u32) -> Self {
+        Self {
+            max: max_multiplier.max(1),
+            score: AtomicU32::new(0),
+        }
+    }
+
+    pub fn apply_delta(&self, delta: i32) {
+        if delta == 0 {
+            return;
+        }
+
+        let max = self.max - 1;
+        let previous = self
+            .score
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |score| {
+                Some((score as i64 + delta as i64).clamp(0, max as i64) as u32)
+            })
+            .unwrap_or_default();
+        let current = self.score();
+
+        if current > previous {
+            warn!("Local health score degraded to {}/{}", current, max);
+        } else if current < previous {
+            debug!("Local health score improved to {}/{}", current, max);
+        }
+    }
+
+    pub fn score(&self) -> u32 {
+        self.score.load(Ordering::Relaxed)
+    }
+
+    pub fn scale_timeout(&self, timeout: Duration) -> Duration {
+        timeout * (self.score() + 1)
+    }
+}
@@ -0,0 +1,206 @@
+use super::messages::MemberId;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+// Vivaldi network coordinates (Dabek et al.) with the height vector, error
+// and adjustment extensions from "Network Coordinates in the Wild". Units
+// are seconds.
+
+const DIMENSIONS: usize = 8;
+const ERROR_MAX: f64 = 1.5;
+// how much a single sample moves our error estimate and our position
+const CE: f64 = 0.25;
+const CC: f64 = 0.25;
+const ADJUSTMENT_WINDOW: usize = 20;
+const HEIGHT_MIN: f64 = 10.0e-6;
+// RTT samples per member we take the median of, to drop one-off spikes
+const LATENCY_FILTER_SIZE: usize = 3;
+// pulls coordinates back towards the origin so the whole system doesn't drift
+const GRAVITY_RHO: f64 = 150.0;
+const ZERO_THRESHOLD: f64 = 1.0e-6;
+// no real network is hours across. anything further out came from a bad
+// peer, and distances to it could overflow a Duration
+const MAX_COORDINATE: f64 = 1.0e4;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Coordinate {
+    pub vec: [f64; DIMENSIONS],
+    // how far off we think this coordinate is, 1.5 = no idea yet
+    pub error: f64,
+    pub adjustment: f64,
+    // the access link, the part of every RTT no position in space explains
+    pub height: f64,
+}
+
+impl Default for Coordinate {
+    fn default() -> Self {
+        Self {
+            vec: [0.0; DIMENSIONS],
+            error: ERROR_MAX,
+            adjustment: 0.0,
+            height: HEIGHT_MIN,
+        }
+    }
+}
+
+impl Coordinate {
+    // estimated round trip time between the two nodes
+    pub fn distance_to(&self, other: &Coordinate) -> Duration {
+        let raw = self.raw_distance_to(other);
+        let adjusted = raw + self.adjustment + other.adjustment;
+        let dist = if adjusted > 0.0 { adjusted } else { raw };
+        Duration::try_from_secs_f64(dist.max(0.0)).unwrap_or(Duration::MAX)
+    }
+
+    fn raw_distance_to(&self, other: &Coordinate) -> f64 {
+        magnitude(&diff(&self.vec, &other.vec)) + self.height + other.height
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.vec
+            .iter()
+            .chain([&self.adjustment, &self.height])
+            .all(|x| x.is_finite() && x.abs() <= MAX_COORDINATE)
+            && self.error.is_finite()
+    }
+
+    // moves away from `other` by `force` seconds (towards it if negative)
+    fn apply_force(&self, force: f64, other: &Coordinate, rng: &mut impl Rng) -> Coordinate {
+        let (unit, mag) = unit_vector_at(&self.vec, &other.vec, rng);
+        let mut moved = self.clone();
+        for (x, u) in moved.vec.iter_mut().zip(unit) {
+            *x += u * force;
+        }
+        if mag > ZERO_THRESHOLD {
+            moved.height =
+                ((self.height + other.height) * force / mag + self.height).max(HEIGHT_MIN);
+        }
+        moved
+    }
+}
+
+fn diff(a: &[f64; DIMENSIONS], b: &[f64; DIMENSIONS]) -> [f64; DIMENSIONS] {
+    std::array::from_fn(|i| a[i] - b[i])
+}
+
+fn magnitude(v: &[f64; DIMENSIONS]) -> f64 {
+    v.iter().map(|x| x * x).sum::<f64>().sqrt()
+}
+
+// unit vector pointing from b to a, and the distance between them. nodes at
+// the same spot get pushed apart in a random direction
+fn unit_vector_at(
+    a: &[f64; DIMENSIONS],
+    b: &[f64; DIMENSIONS],
+    rng: &mut impl Rng,
+) -> ([f64; DIMENSIONS], f64) {
+    let d = diff(a, b);
+    let mag = magnitude(&d);
+    if mag > ZERO_THRESHOLD {
+        return (d.map(|x| x / mag), mag);
+    }
+
+    let random: [f64; DIMENSIONS] = std::array::from_fn(|_| rng.random::<f64>() - 0.5);
+    let mag = magnitude(&random);
+    if mag > ZERO_THRESHOLD {
+        (random.map(|x| x / mag), 0.0)
+    } else {
+        ([0.0; DIMENSIONS], 0.0)
+    }
+}
+
+// our own coordinate, refined with every RTT sample to another member
+pub struct Vivaldi {
+    coord: Coordinate,
+    origin: Coordinate,
+    adjustment_samples: [f64; ADJUSTMENT_WINDOW],
+    adjustment_index: usize,
+    latency_filters: HashMap<MemberId, Vec<f64>>,
+    rng: StdRng,
+}
+
+impl Default for Vivaldi {
+    fn default() -> Self {
+        Self {
+            coord: Coordinate::default(),
+            origin: Coordinate::default(),
+            adjustment_samples: [0.0; ADJUSTMENT_WINDOW],
+            adjustment_index: 0,
+            latency_filters: HashMap::new(),
+            rng: StdRng::from_rng(&mut rand::rng()),
+        }
+    }
+}
+
+impl Vivaldi {
+    pub fn coordinate(&self) -> &Coordinate {
+        &self.coord
+    }
+
+    pub fn update(&mut self, member: &MemberId, other: &Coordinate, rtt: Duration) {
+        if !other.is_valid() {
+            return;
+        }
+
+        let rtt = self.latency_filter(member, rtt.as_secs_f64());
+        self.update_vivaldi(other, rtt);
+        self.update_adjustment(other, rtt);
+        self.update_gravity();
+
+        if !self.coord.is_valid() {
+            self.coord = Coordinate::default();
+        }
+    }
+
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    pub fn forget(&mut self, member: &MemberId) {
+        self.latency_filters.remove(member);
+    }
+
+    fn latency_filter(&mut self, member: &MemberId, rtt: f64) -> f64 {
+        let samples = self.latency_filters.entry(member.clone()).or_default();
+        samples.push(rtt);
+        if samples.len() > LATENCY_FILTER_SIZE {
+            samples.remove(0);
+        }
+
+        let mut sorted = samples.clone();
+        sorted.sort_by(f64::total_cmp);
+        sorted[sorted.len() / 2]
+    }
+
+    fn update_vivaldi(&mut self, other: &Coordinate, rtt: f64) {
+        let rtt = rtt.max(ZERO_THRESHOLD);
+        let dist = self.coord.raw_distance_to(other);
+        let wrongness = (dist - rtt).abs() / rtt;
+
+        let total_error = (self.coord.error + other.error).max(ZERO_THRESHOLD);
+        let weight = self.coord.error / total_error;
+
+        self.coord.error =
+            (CE * weight * wrongness + self.coord.error * (1.0 - CE * weight)).min(ERROR_MAX);
+
+        let force = CC * weight * (rtt - dist);
+        self.coord = self.coord.apply_force(force, other, &mut self.rng);
+    }
+
+    fn update_adjustment(&mut self, other: &Coordinate, rtt: f64) {
+        self.adjustment_samples[self.adjustment_index] = rtt - self.coord.raw_distance_to(other);
+        self.adjustment_index = (self.adjustment_index + 1) % ADJUSTMENT_WINDOW;
+
+        let sum: f64 = self.adjustment_samples.iter().sum();
+        self.coord.adjustment = sum / (2.0 * ADJUSTMENT_WINDOW as f64);
+    }
+
+    fn update_gravity(&mut self) {
+        let dist = self.origin.raw_distance_to(&self.coord);
+        let force = -(dist / GRAVITY_RHO).powi(2);
+        self.coord = self.coord.apply_force(force, &self.origin, &mut self.rng);
+    }
+}
@@ -0,0 +1,133 @@
+use super::messages::UserEvent;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// each update is sent RETRANSMIT_MULT * log10(cluster size) rounds, enough for
+// it to reach everyone with high probability
+const RETRANSMIT_MULT: u32 = 4;
+// budget for the events piggybacked on one round, so they fit in a packet
+// next to a ping
+const MAX_EVENTS_BYTES: u64 = 1024;
+
+pub(super) fn retransmit_limit(cluster_size: usize) -> u32 {
+    RETRANSMIT_MULT * ((cluster_size + 1) as f64).log10().ceil().max(1.0) as u32
+}
+
+// orders user events cluster-wide, every event received moves it forward
+#[derive(Debug, Default)]
+pub struct LamportClock {
+    counter: AtomicU64,
+}
+
+impl LamportClock {
+    pub fn time(&self) -> u64 {
+        self.counter.load(Ordering::SeqCst)
+    }
+
+    pub fn increment(&self) -> u64 {
+        self.counter.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    pub fn witness(&self, time: u64) {
+        self.counter.fetch_max(time, Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Enqueued {
+    Queued,
+    // replaced an older queued event with the same name from the same origin
+    Coalesced,
+    // the queue was full, the event closest to done spreading was dropped
+    Dropped,
+}
+
+struct QueuedEvent {
+    event: UserEvent,
+    transmits: u32,
+}
+
+// remembers which events we have seen over the last `capacity` lamport
+// times, and holds the ones we are still spreading
+pub struct EventBuffer {
+    capacity: usize,
+    seen: BTreeMap<u64, HashSet<u64>>,
+    queue: Vec<QueuedEvent>,
+}
+
+impl EventBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            seen: BTreeMap::new(),
+            queue: Vec::new(),
+        }
+    }
+
+    // true the first time we see an event. events older than the window
+    // can't be told apart from ones we forgot about, so they are never new
+    pub fn observe(&mut self, event: &UserEvent, now: u64) -> bool {
+        let oldest = now.saturating_sub(self.capacity as u64);
+        if event.ltime < oldest {
+            return false;
+        }
+
+        self.seen = self.seen.split_off(&oldest);
+        self.seen.entry(event.ltime).or_default().insert(event.id)
+    }
+
+    pub fn enqueue(&mut self, event: UserEvent) -> Enqueued {
+        if let Some(queued) = self
+            .queue
+            .iter_mut()
+            .find(|q| q.event.origin == event.origin && q.event.name == event.name)
+        {
+            if queued.event.ltime < event.ltime {
+                *queued = QueuedEvent {
+                    event,
+                    transmits: 0,
+                };
+            }
+            return Enqueued::Coalesced;
+        }
+
+        let mut outcome = Enqueued::Queued;
+        if self.queue.len() >= self.capacity
+            && let Some((i, _)) = self
+                .queue
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, q)| q.transmits)
+        {
+            self.queue.swap_remove(i);
+            outcome = Enqueued::Dropped;
+        }
+
+        self.queue.push(QueuedEvent {
+            event,
+            transmits: 0,
+        });
+        outcome
+    }
+
+    // events to gossip this round, least sent first
+    pub fn take(&mut self, cluster_size: usize) -> Vec<UserEvent> {
+        let limit = retransmit_limit(cluster_size);
+
+        self.queue.sort_by_key(|q| q.transmits);
+
+        let mut events = Vec::new();
+        let mut size = 0;
+        for queued in &mut self.queue {
+            size += bincode::serialized_size(&queued.event).unwrap_or(0);
+            if !events.is_empty() && size > MAX_EVENTS_BYTES {
+                break;
+            }
+            queued.transmits += 1;
+            events.push(queued.event.clone());
+        }
+
+        self.queue.retain(|q| q.transmits < limit);
+        events
+    }
+}
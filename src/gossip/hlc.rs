@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+// remote clocks further ahead than this are not allowed to drag ours along
+const MAX_CLOCK_DRIFT: Duration = Duration::from_secs(60);
+
+// wall clock milliseconds plus a logical counter, which orders events within
+// the same millisecond or while our clock lags behind a peer's
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct HybridTimestamp {
+    pub wall_ms: u64,
+    pub logical: u32,
+}
+
+impl HybridTimestamp {
+    pub fn is_zero(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct HybridClock {
+    last: Mutex<HybridTimestamp>,
+}
+
+fn physical_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+impl HybridClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // timestamp for a local event
+    pub fn now(&self) -> HybridTimestamp {
+        let physical = physical_now();
+        let mut last = self.last.lock().unwrap();
+
+        if physical > last.wall_ms {
+            *last = HybridTimestamp {
+                wall_ms: physical,
+                logical: 0,
+            };
+        } else {
+            last.logical += 1;
+        }
+        *last
+    }
+
+    // merge a timestamp received from a peer, so everything we stamp
+    // afterwards orders after it. false if it is too far ahead of our clock,
+    // whatever it stamps has to be dropped then: kept, it would win every
+    // last-writer-wins comparison until real time caught up
+    pub fn observe(&self, remote: HybridTimestamp) -> bool {
+        let physical = physical_now();
+        if remote.wall_ms > physical + MAX_CLOCK_DRIFT.as_millis() as u64 {
+            warn!(
+                "Ignoring timestamp {}ms ahead of our clock",
+                remote.wall_ms - physical
+            );
+            return false;
+        }
+
+        let mut last = self.last.lock().unwrap();
+        let wall_ms = physical.max(last.wall_ms).max(remote.wall_ms);
+
+        let logical = if wall_ms == last.wall_ms && wall_ms == remote.wall_ms {
+            last.logical.max(remote.logical) + 1
+        } else if wall_ms == last.wall_ms {
+            last.logical + 1
+        } else if wall_ms == remote.wall_ms {
+            remote.logical + 1
+        } else {
+            0
+        };
+
+        *last = HybridTimestamp { wall_ms, logical };
+        true
+    }
+}
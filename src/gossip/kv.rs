@@ -0,0 +1,219 @@
+use super::events::retransmit_limit;
+use super::hlc::HybridClock;
+use super::messages::{KvEntry, MemberId};
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+// budget for the updates piggybacked on one round, and for each push-pull
+// slice, so they fit in a packet
+const MAX_UPDATES_BYTES: u64 = 1024;
+
+// keys flux itself keeps in the store: backends/<pool>/<addr>/override,
+// .../weight. pool names can't contain '/' or whitespace
+const BACKEND_KEY_PREFIX: &str = "backends/";
+pub const OVERRIDE_FIELD: &str = "override";
+pub const WEIGHT_FIELD: &str = "weight";
+
+pub fn backend_key(pool: &str, addr: SocketAddr, field: &str) -> String {
+    format!("{}{}/{}/{}", BACKEND_KEY_PREFIX, pool, addr, field)
+}
+
+pub fn parse_backend_key(key: &str) -> Option<(&str, SocketAddr, &str)> {
+    let (pool, rest) = key.strip_prefix(BACKEND_KEY_PREFIX)?.split_once('/')?;
+    let (addr, field) = rest.rsplit_once('/')?;
+    Some((pool, addr.parse().ok()?, field))
+}
+
+// every node's open connections per backend live under load/<member id>,
+// one "pool addr count" line per backend that has any. busiest first, and
+// only as many as fit in MAX_LOAD_BYTES, the rest count as idle elsewhere
+pub const LOAD_KEY_PREFIX: &str = "load/";
+// leaves room for the key within a kv entry
+const MAX_LOAD_BYTES: usize = 400;
+
+pub fn load_key(member: &MemberId) -> String {
+    format!("{}{}", LOAD_KEY_PREFIX, member.0)
+}
+
+pub fn encode_load(connections: &[(String, SocketAddr, u32)]) -> Vec<u8> {
+    let mut busy: Vec<_> = connections
+        .iter()
+        .filter(|(_, _, count)| *count > 0)
+        .collect();
+    busy.sort_by_key(|(_, _, count)| std::cmp::Reverse(*count));
+
+    let mut encoded = String::new();
+    for (pool, addr, count) in busy {
+        let line = format!("{} {} {}\n", pool, addr, count);
+        if encoded.len() + line.len() > MAX_LOAD_BYTES {
+            break;
+        }
+        encoded.push_str(&line);
+    }
+    encoded.into_bytes()
+}
+
+// lines that don't parse are skipped
+pub fn decode_load(value: &[u8]) -> Vec<(String, SocketAddr, u32)> {
+    String::from_utf8_lossy(value)
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split(' ');
+            let pool = parts.next()?.to_string();
+            let addr = parts.next()?.parse().ok()?;
+            let count = parts.next()?.parse().ok()?;
+            Some((pool, addr, count))
+        })
+        .collect()
+}
+
+// every node's rate limiter consumption lives under
+// ratelimit/<frontend>/<member id>, frontends have limiters of their own
+pub const RATE_LIMIT_KEY_PREFIX: &str = "ratelimit/";
+
+pub fn rate_limit_prefix(frontend: &str) -> String {
+    format!("{}{}/", RATE_LIMIT_KEY_PREFIX, frontend)
+}
+
+pub fn rate_limit_key(frontend: &str, member: &MemberId) -> String {
+    format!("{}{}", rate_limit_prefix(frontend), member.0)
+}
+
+// the member a load or rate limit report belongs to
+pub fn report_writer(key: &str) -> Option<&str> {
+    if let Some(member) = key.strip_prefix(LOAD_KEY_PREFIX) {
+        return Some(member);
+    }
+    let (_, member) = key.strip_prefix(RATE_LIMIT_KEY_PREFIX)?.split_once('/')?;
+    Some(member)
+}
+
+// sticky session pins live under sticky/<pool>/<client ip>
+pub const STICKY_KEY_PREFIX: &str = "sticky/";
+
+pub fn sticky_key(pool: &str, client: IpAddr) -> String {
+    format!("{}{}/{}", STICKY_KEY_PREFIX, pool, client)
+}
+
+pub fn parse_sticky_key(key: &str) -> Option<(&str, IpAddr)> {
+    let (pool, client) = key.strip_prefix(STICKY_KEY_PREFIX)?.split_once('/')?;
+    Some((pool, client.parse().ok()?))
+}
+
+// replicated string -> bytes map. every key is a last-writer-wins register,
+// deletions are kept as tombstones for a while so they win over stale copies
+pub struct KvStore {
+    local_id: MemberId,
+    clock: Arc<HybridClock>,
+    entries: BTreeMap<String, KvEntry>,
+    // keys still being gossiped, and how often they were sent
+    pending: HashMap<String, u32>,
+}
+
+impl KvStore {
+    pub fn new(local_id: MemberId, clock: Arc<HybridClock>) -> Self {
+        Self {
+            local_id,
+            clock,
+            entries: BTreeMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.entries.get(key)?.value.as_deref()
+    }
+
+    pub fn scan(&self, prefix: &str) -> Vec<(String, Vec<u8>)> {
+        self.entries
+            .range(prefix.to_string()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .filter_map(|(key, entry)| Some((key.clone(), entry.value.clone()?)))
+            .collect()
+    }
+
+    // a local write, None deletes the key
+    pub fn set(&mut self, key: &str, value: Option<Vec<u8>>) -> KvEntry {
+        let entry = KvEntry {
+            key: key.to_string(),
+            value,
+            version: self.clock.now(),
+            writer: self.local_id.clone(),
+        };
+        self.merge(entry.clone());
+        entry
+    }
+
+    // true if the entry is newer than what we had
+    pub fn merge(&mut self, entry: KvEntry) -> bool {
+        if !self.clock.observe(entry.version) {
+            return false;
+        }
+
+        if let Some(current) = self.entries.get(&entry.key)
+            && (entry.version, &entry.writer) <= (current.version, &current.writer)
+        {
+            return false;
+        }
+
+        self.pending.insert(entry.key.clone(), 0);
+        self.entries.insert(entry.key.clone(), entry);
+        true
+    }
+
+    // recent changes to gossip this round, least sent first
+    pub fn take_updates(&mut self, cluster_size: usize) -> Vec<KvEntry> {
+        let limit = retransmit_limit(cluster_size);
+
+        let mut keys: Vec<(&String, &mut u32)> = self.pending.iter_mut().collect();
+        keys.sort_by_key(|(_, transmits)| **transmits);
+
+        let mut updates = Vec::new();
+        let mut size = 0;
+        for (key, transmits) in keys {
+            let Some(entry) = self.entries.get(key) else {
+                continue;
+            };
+            size += bincode::serialized_size(entry).unwrap_or(0);
+            if !updates.is_empty() && size > MAX_UPDATES_BYTES {
+                break;
+            }
+            *transmits += 1;
+            updates.push(entry.clone());
+        }
+
+        self.pending.retain(|_, transmits| *transmits < limit);
+        updates
+    }
+
+    // the whole store, tombstones included, in packet-sized slices
+    pub fn snapshot(&self) -> Vec<Vec<KvEntry>> {
+        let mut slices = Vec::new();
+        let mut slice = Vec::new();
+        let mut size = 0;
+
+        for entry in self.entries.values() {
+            let entry_size = bincode::serialized_size(entry).unwrap_or(0);
+            if !slice.is_empty() && size + entry_size > MAX_UPDATES_BYTES {
+                slices.push(std::mem::take(&mut slice));
+                size = 0;
+            }
+            size += entry_size;
+            slice.push(entry.clone());
+        }
+        if !slice.is_empty() || slices.is_empty() {
+            slices.push(slice);
+        }
+
+        slices
+    }
+
+    pub fn prune_tombstones(&mut self, ttl: Duration) {
+        let now = self.clock.now().wall_ms;
+        let ttl = ttl.as_millis() as u64;
+        self.entries
+            .retain(|_, entry| entry.value.is_some() || entry.version.wall_ms + ttl > now);
+    }
+}
@@ -1,667 +1,845 @@
-use super::member_list::{MemberList, SharedMemberList};
-use super::messages::{BackendUpdate, GossipMessage, Member, MemberId, MemberState, MemberUpdate};
-use super::states::IndirectPingState;
-use crate::backend::SharedBackendPool;
+use super::advertise::advertise_addr;
+use super::coordinate::Coordinate;
+use super::kv;
+use super::machine::{GossipMachine, Output};
+use super::member_list::MemberList;
+use super::messages::{
+    FEATURE_COMPOUND, FEATURE_EVENTS, FEATURE_KV, FEATURE_LZ4, FEATURE_STREAM, FEATURES_TAG,
+    GossipMessage, KvEntry, MAX_CLUSTER_NAME_LEN, MAX_UDP_PACKET_SIZE, Member, MemberId,
+    MemberState, Tags, UserEvent,
+};
+use super::metrics::GossipMetrics;
+use super::seeds::SeedResolver;
+use super::snapshot::Snapshot;
+use super::transport::{Transport, UdpTransport};
+use crate::backend::{BackendOverride, BackendPools, OverrideMode, SharedBackendPool, StickyPin};
+use crate::config::GossipConfig;
+use crate::leader::LeaderElection;
+use crate::ratelimit::{self, RateLimiter};
+use crate::vip::{self, VirtualIps};
 use anyhow::Result;
-use std::collections::HashMap;
-use std::net::SocketAddr;
-use std::sync::Arc;
-use std::time::{Duration, Instant};
-use tokio::net::UdpSocket;
-use tokio::sync::{Mutex, RwLock};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::sync::{Notify, broadcast};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
 use tracing::{debug, error, info, warn};
 
+// a subscriber lagging further behind than this misses events
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+const JOIN_ATTEMPTS: u32 = 3;
+const JOIN_RETRY_DELAY: Duration = Duration::from_millis(500);
+// how long we give the seeds to answer a join
+const JOIN_WAIT: Duration = Duration::from_millis(1000);
+// how often expired sticky session pins are looked for
+const STICKY_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+// how often the leader looks for reports of members that are gone
+const REAP_INTERVAL: Duration = Duration::from_secs(60);
+
+// messages queued during a gossip round, sent together by `send_batch`
+pub type Outbox = Vec<(SocketAddr, GossipMessage)>;
+
+// drives a GossipMachine: feeds it packets and timeouts, and carries out
+// what it asks for. the machine sits behind a plain mutex that is only ever
+// held for a synchronous call, never across an await
+#[derive(Clone)]
 pub struct GossipLayer {
-    member_list: SharedMemberList,
-    socket: Arc<UdpSocket>,
-    pending_pings: Arc<Mutex<HashMap<MemberId, Instant>>>,
-    pending_indirect_pings: Arc<Mutex<HashMap<MemberId, IndirectPingState>>>,
-    backend_pool: SharedBackendPool,
+    config: GossipConfig,
+    machine: Arc<Mutex<GossipMachine>>,
+    transport: Arc<dyn Transport>,
+    backend_pools: BackendPools,
+    seeds: Arc<SeedResolver>,
+    // peers from the last snapshot, tried alongside the seeds when joining
+    restored_peers: Vec<SocketAddr>,
+    metrics: Arc<GossipMetrics>,
+    event_tx: broadcast::Sender<UserEvent>,
+    // the incarnation in the state file. peers may hold suspicions about
+    // anything older, so a raise is saved right away instead of on the timer
+    saved_incarnation: Arc<AtomicU64>,
+    snapshot_due: Arc<Notify>,
+    // one flush at a time. applying outputs awaits, and two flushes side by
+    // side could apply an older kv value after a newer one
+    flushing: Arc<tokio::sync::Mutex<()>>,
 }
 
 impl GossipLayer {
+    // `backend_pools` is every named pool, or a single SharedBackendPool
+    // that then goes by DEFAULT_POOL
     pub async fn new(
         local_id: MemberId,
-        bind_addr: SocketAddr,
-        suspect_timeout: Duration,
-        backend_pool: SharedBackendPool,
-    ) -> Result<(Self, SharedMemberList)> {
-        let socket = UdpSocket::bind(bind_addr).await?;
+        config: &GossipConfig,
+        backend_pools: impl Into<BackendPools>,
+    ) -> Result<Self> {
+        let transport = UdpTransport::bind(config.bind_addr, config.stream_fallback).await?;
+        Self::with_transport(local_id, config, backend_pools, Arc::new(transport)).await
+    }
+
+    // a layer on another transport than UDP, e.g. a MemoryNetwork in tests.
+    // config.bind_addr is ignored, the transport is already bound
+    pub async fn with_transport(
+        local_id: MemberId,
+        config: &GossipConfig,
+        backend_pools: impl Into<BackendPools>,
+        transport: Arc<dyn Transport>,
+    ) -> Result<Self> {
+        let backend_pools = backend_pools.into();
+        if config.cluster_name.len() > MAX_CLUSTER_NAME_LEN {
+            return Err(anyhow::anyhow!(
+                "Cluster name '{}' is longer than {} bytes",
+                config.cluster_name,
+                MAX_CLUSTER_NAME_LEN
+            ));
+        }
+
+        let bind_addr = transport.local_addr();
         debug!("Gossip layer bound to {}", bind_addr);
 
+        // what we tell peers to send to, which isn't necessarily what we
+        // bound to (wildcard binds, NAT, containers)
+        let advertise_addr = config
+            .advertise_addr
+            .unwrap_or_else(|| advertise_addr(bind_addr));
+
+        let mut tags = config.tags.clone();
+        tags.entry("version".to_string())
+            .or_insert_with(|| env!("CARGO_PKG_VERSION").to_string());
+
+        let mut features = vec![FEATURE_COMPOUND, FEATURE_EVENTS, FEATURE_KV];
+        if config.compression {
+            features.push(FEATURE_LZ4);
+        }
+        if config.stream_fallback && transport.supports_stream() {
+            features.push(FEATURE_STREAM);
+        }
+        tags.insert(FEATURES_TAG.to_string(), features.join(","));
+
         let local_member = Member {
             id: local_id,
-            addr: bind_addr,
+            addr: advertise_addr,
             state: MemberState::Alive,
             incarnation: 0,
+            tags,
         };
 
-        let member_list = Arc::new(RwLock::new(MemberList::new(local_member, suspect_timeout)));
+        let clock = backend_pools.clock().await;
+        let mut machine = GossipMachine::new(local_member, config, clock, Instant::now());
+
+        let mut restored_peers = Vec::new();
+        if let Some(path) = &config.state_file {
+            match Snapshot::load(path).await {
+                Ok(Some(snapshot)) => {
+                    info!(
+                        "Restored {} members and incarnation {} from {}",
+                        snapshot.members.len(),
+                        snapshot.incarnation,
+                        path.display()
+                    );
+                    machine.restore(&snapshot);
+                    // last-writer-wins, anything newer from peers still takes over
+                    backend_pools
+                        .apply_backend_updates(&snapshot.backends)
+                        .await;
+                    restored_peers = snapshot
+                        .members
+                        .iter()
+                        .map(|member| member.addr)
+                        .filter(|&addr| addr != advertise_addr)
+                        .collect();
+                }
+                Ok(None) => debug!("No state file at {} yet", path.display()),
+                Err(e) => warn!("Ignoring state file {}: {}", path.display(), e),
+            }
+        }
 
-        let gossip_layer = Self {
-            member_list: member_list.clone(),
-            socket: Arc::new(socket),
-            pending_pings: Arc::new(Mutex::new(HashMap::new())),
-            backend_pool,
-            pending_indirect_pings: Arc::new(Mutex::new(HashMap::new())),
-        };
+        Ok(Self {
+            config: config.clone(),
+            metrics: machine.metrics(),
+            machine: Arc::new(Mutex::new(machine)),
+            transport,
+            backend_pools,
+            seeds: Arc::new(SeedResolver::new(config)?),
+            restored_peers,
+            event_tx: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            saved_incarnation: Arc::new(AtomicU64::new(0)),
+            snapshot_due: Arc::new(Notify::new()),
+            flushing: Arc::new(tokio::sync::Mutex::new(())),
+        })
+    }
 
-        Ok((gossip_layer, member_list))
+    // guards aren't Send, so holding one across an await doesn't compile
+    // in a spawned task
+    fn machine(&self) -> MutexGuard<'_, GossipMachine> {
+        self.machine.lock().unwrap()
     }
 
-    pub fn socket(&self) -> Arc<UdpSocket> {
-        self.socket.clone()
+    // runs `f` on the member list, for inspecting (or in tests, rigging) it
+    pub fn with_members<R>(&self, f: impl FnOnce(&mut MemberList) -> R) -> R {
+        f(self.machine().members_mut())
     }
 
-    pub fn pending_pings(&self) -> Arc<Mutex<HashMap<MemberId, Instant>>> {
-        self.pending_pings.clone()
+    pub fn local_member(&self) -> Member {
+        self.machine().local_member().clone()
     }
 
-    pub fn pending_indirect_pings(&self) -> Arc<Mutex<HashMap<MemberId, IndirectPingState>>> {
-        self.pending_indirect_pings.clone()
+    pub fn alive_members(&self) -> Vec<Member> {
+        self.machine().members().get_alive_members()
     }
 
-    pub async fn send_message(&self, message: GossipMessage, target: SocketAddr) -> Result<()> {
-        let bytes = message.to_bytes()?;
-        self.socket.send_to(&bytes, target).await?;
-        debug!("Sent {:?} to {}", message, target);
-        Ok(())
+    pub fn member(&self, id: &MemberId) -> Option<Member> {
+        self.machine().members().get_member(id)
     }
 
-    pub async fn run(&mut self) {
-        let mut buf = vec![0u8; 65535]; // Max UDP packet size
+    // lifeguard local health, 0 is healthy
+    pub fn awareness_score(&self) -> u32 {
+        self.machine().awareness().score()
+    }
 
-        loop {
-            match self.socket.recv_from(&mut buf).await {
-                Ok((len, src_addr)) => {
-                    let data = &buf[..len];
+    pub fn coordinate(&self) -> Coordinate {
+        self.machine().members().local_coordinate()
+    }
 
-                    match GossipMessage::from_bytes(data) {
-                        Ok(message) => {
-                            debug!("Received {:?} from {}", message, src_addr);
+    pub fn member_coordinate(&self, member: &MemberId) -> Option<Coordinate> {
+        self.machine().members().get_coordinate(member)
+    }
 
-                            if let Err(e) = self.handle_message(message, src_addr).await {
-                                error!("Error handling message from {}: {}", src_addr, e);
-                            }
-                        }
-                        Err(e) => {
-                            warn!("Failed to deserialize message from {}: {}", src_addr, e);
-                        }
-                    }
-                }
-                Err(e) => {
-                    error!("Error receiving UDP message: {}", e);
-                }
-            }
-        }
+    // RTT to a member estimated from coordinates, without probing it
+    pub fn estimate_rtt(&self, member: &MemberId) -> Option<Duration> {
+        self.machine().members().estimate_rtt(member)
     }
 
-    async fn handle_message(
-        &mut self,
-        message: GossipMessage,
-        _src_addr: SocketAddr,
-    ) -> Result<()> {
-        match message {
-            GossipMessage::Ping {
-                from,
-                from_addr,
-                incarnation,
-                member_updates,
-                backend_updates,
-            } => {
-                debug!("Handling Ping from {}", from.0);
-
-                self.process_member_updates(member_updates).await;
-                self.process_backend_updates(backend_updates).await;
-
-                {
-                    let mut members = self.member_list.write().await;
-                    members.upsert_member(Member {
-                        id: from.clone(),
-                        addr: from_addr,
-                        state: MemberState::Alive,
-                        incarnation,
-                    });
-                }
+    pub fn set_tags(&self, tags: Tags) {
+        self.machine().set_tags(tags);
+        self.check_incarnation();
+    }
 
-                let (ack_from, ack_addr, ack_incarnation, updates, backend_updates) = {
-                    let members = self.member_list.read().await;
-                    let backends = self.backend_pool.read().await;
+    // every random choice this layer makes (probe order, indirect probers,
+    // broadcast targets, event ids) follows from `seed`. for simulations,
+    // call before `run`
+    pub fn seed_rng(&self, seed: u64) {
+        self.machine().seed_rng(seed);
+    }
 
-                    let local = members.local_member();
-                    let mut backend_updates = backends.get_backend_health_updates();
-                    for update in &mut backend_updates {
-                        update.from_member = local.id.clone();
-                    }
-                    let update_limit: usize = std::cmp::max(5, members.get_all_members().len() / 2);
-                    (
-                        local.id.clone(),
-                        local.addr,
-                        local.incarnation,
-                        members.get_member_updates(update_limit),
-                        backend_updates,
-                    )
-                };
-
-                let ack = GossipMessage::Ack {
-                    from: ack_from,
-                    from_addr: ack_addr,
-                    incarnation: ack_incarnation,
-                    member_updates: updates,
-                    backend_updates,
-                }
-                .trim_to_fit();
+    pub fn metrics(&self) -> Arc<GossipMetrics> {
+        self.metrics.clone()
+    }
 
-                self.send_message(ack, from_addr).await?;
-            }
+    // sends a small application event to every member, including this one.
+    // each subscriber sees it once. while it spreads, a newer event with the
+    // same name from this node replaces it, so members that haven't got the
+    // old one yet only see the new one
+    pub async fn broadcast_event(&self, name: &str, payload: Vec<u8>) -> Result<()> {
+        self.machine().broadcast_event(name, payload)?;
+        self.flush().await;
+        Ok(())
+    }
 
-            GossipMessage::Ack {
-                from,
-                from_addr,
-                incarnation,
-                member_updates,
-                backend_updates,
-            } => {
-                debug!("Handling Ack from {}", from.0);
-
-                let rtt = {
-                    let mut pending = self.pending_pings.lock().await;
-                    pending.remove(&from).map(|sent_at| sent_at.elapsed())
-                };
-
-                {
-                    let mut members = self.member_list.write().await;
-                    if let Some(rtt) = rtt {
-                        members.record_rtt(&from, rtt);
-                    }
-                    members.upsert_member(Member {
-                        id: from.clone(),
-                        addr: from_addr,
-                        state: MemberState::Alive,
-                        incarnation,
-                    });
-                    members.mark_alive(&from);
-                }
+    pub fn subscribe_events(&self) -> broadcast::Receiver<UserEvent> {
+        self.event_tx.subscribe()
+    }
 
-                self.process_member_updates(member_updates).await;
-                self.process_backend_updates(backend_updates).await;
-            }
+    pub fn kv_get(&self, key: &str) -> Option<Vec<u8>> {
+        self.machine().kv().get(key).map(<[u8]>::to_vec)
+    }
 
-            GossipMessage::IndirectPing {
-                from,
-                from_addr,
-                target_id,
-                target_addr,
-            } => {
-                debug!(
-                    "Handling IndirectPing request from {} to ping {}",
-                    from.0, target_id.0
-                );
+    // live keys starting with `prefix`, in key order
+    pub fn kv_scan(&self, prefix: &str) -> Vec<(String, Vec<u8>)> {
+        self.machine().kv().scan(prefix)
+    }
 
-                let socket = self.socket.clone();
-                let member_list = self.member_list.clone();
-                let backend_pool = self.backend_pool.clone();
-                let pending_pings = self.pending_pings.clone();
+    // writes a key to the cluster-wide store. concurrent writes to the same
+    // key resolve to the latest one everywhere
+    pub async fn kv_set(&self, key: &str, value: Vec<u8>) -> Result<()> {
+        self.machine().kv_set(key, Some(value))?;
+        self.flush().await;
+        Ok(())
+    }
 
-                tokio::spawn(async move {
-                    let local_info = {
-                        let members = member_list.read().await;
-                        let backends = backend_pool.read().await;
-                        let local = members.local_member();
+    pub async fn kv_delete(&self, key: &str) {
+        // only writes are size checked
+        let _ = self.machine().kv_set(key, None);
+        self.flush().await;
+    }
 
-                        let mut backend_updates = backends.get_backend_health_updates();
-                        for update in &mut backend_updates {
-                            update.from_member = local.id.clone();
-                        }
+    // puts a backend in `mode` on every node until `ttl` runs out, or until
+    // someone clears it. a newer override from any node replaces it
+    pub async fn set_backend_override(
+        &self,
+        pool: &str,
+        addr: SocketAddr,
+        mode: OverrideMode,
+        ttl: Option<Duration>,
+    ) -> Result<()> {
+        let key = kv::backend_key(pool, addr, kv::OVERRIDE_FIELD);
+        self.kv_set(&key, BackendOverride::new(mode, ttl).encode())
+            .await
+    }
 
-                        let update_limit: usize =
-                            std::cmp::max(5, members.get_all_members().len() / 2);
-
-                        (
-                            local.id.clone(),
-                            local.addr,
-                            local.incarnation,
-                            members.get_member_updates(update_limit),
-                            backend_updates,
-                        )
-                    };
-
-                    {
-                        let mut pending = pending_pings.lock().await;
-                        pending.insert(target_id.clone(), Instant::now());
-                    }
+    pub async fn clear_backend_override(&self, pool: &str, addr: SocketAddr) {
+        self.kv_delete(&kv::backend_key(pool, addr, kv::OVERRIDE_FIELD))
+            .await;
+    }
 
-                    let ping = GossipMessage::Ping {
-                        from: local_info.0.clone(),
-                        from_addr: local_info.1,
-                        incarnation: local_info.2,
-                        member_updates: local_info.3,
-                        backend_updates: local_info.4,
-                    }
-                    .trim_to_fit();
+    // the override as replicated, expired or not
+    pub fn backend_override(&self, pool: &str, addr: SocketAddr) -> Option<BackendOverride> {
+        let value = self.kv_get(&kv::backend_key(pool, addr, kv::OVERRIDE_FIELD))?;
+        BackendOverride::decode(std::str::from_utf8(&value).ok()?)
+    }
 
-                    if let Ok(bytes) = ping.to_bytes() {
-                        let _ = socket.send_to(&bytes, target_addr).await;
-                    }
+    // takes a backend out of rotation on every node, without touching its
+    // health status
+    pub async fn drain_backend(&self, pool: &str, addr: SocketAddr, draining: bool) -> Result<()> {
+        if draining {
+            self.set_backend_override(pool, addr, OverrideMode::Drain, None)
+                .await
+        } else {
+            self.clear_backend_override(pool, addr).await;
+            Ok(())
+        }
+    }
 
-                    tokio::time::sleep(Duration::from_millis(500)).await;
-
-                    let target_responded = {
-                        let pending = pending_pings.lock().await;
-                        !pending.contains_key(&target_id)
-                    };
-
-                    let indirect_ack = GossipMessage::IndirectAck {
-                        from: local_info.0,
-                        target_id,
-                        target_responded,
-                    };
-
-                    if let Ok(bytes) = indirect_ack.to_bytes() {
-                        if let Err(e) = socket.send_to(&bytes, from_addr).await {
-                            debug!("Failed to send IndirectAck: {}", e);
-                        } else {
-                            debug!(
-                                "Sent IndirectAck to {} - target responded: {}",
-                                from.0, target_responded
-                            );
-                        }
-                    }
-                });
+    // overrides a backend's configured weight on every node, None restores it
+    pub async fn set_backend_weight(
+        &self,
+        pool: &str,
+        addr: SocketAddr,
+        weight: Option<u32>,
+    ) -> Result<()> {
+        let key = kv::backend_key(pool, addr, kv::WEIGHT_FIELD);
+        match weight {
+            Some(weight) => self.kv_set(&key, weight.to_string().into_bytes()).await,
+            None => {
+                self.kv_delete(&key).await;
+                Ok(())
             }
+        }
+    }
 
-            GossipMessage::IndirectAck {
-                from,
-                target_id,
-                target_responded,
-            } => {
-                debug!(
-                    "Handling IndirectAck from {}: target {} responded={}",
-                    from.0, target_id.0, target_responded
-                );
-
-                let mut pending = self.pending_indirect_pings.lock().await;
-
-                if let Some(state) = pending.get_mut(&target_id) {
-                    state.responses.push(target_responded);
-
-                    if target_responded {
-                        info!(
-                            "Target {} confirmed alive via indirect ping from {}",
-                            target_id.0, from.0
-                        );
-
-                        let mut members = self.member_list.write().await;
-                        members.mark_alive(&target_id);
+    // keys flux itself acts on
+    async fn apply_kv_entry(&self, entry: &KvEntry) {
+        let value = entry
+            .value
+            .as_deref()
+            .and_then(|v| std::str::from_utf8(v).ok());
+
+        if let Some((pool, client)) = kv::parse_sticky_key(&entry.key) {
+            if let Some(pool) = self.backend_pools.get(pool) {
+                let pin = value.and_then(StickyPin::decode);
+                pool.read()
+                    .await
+                    .apply_sticky_pin(client, pin, &entry.writer);
+            }
+            return;
+        }
+        let Some((pool, addr, field)) = kv::parse_backend_key(&entry.key) else {
+            return;
+        };
+        let Some(pool) = self.backend_pools.get(pool) else {
+            return;
+        };
 
-                        let mut direct_pending = self.pending_pings.lock().await;
-                        direct_pending.remove(&target_id);
+        let mut backends = pool.write().await;
+        match field {
+            kv::OVERRIDE_FIELD => {
+                backends.set_override(addr, value.and_then(BackendOverride::decode))
+            }
+            kv::WEIGHT_FIELD => backends.set_weight(addr, value.and_then(|v| v.parse().ok())),
+            _ => {}
+        }
+    }
 
-                        pending.remove(&target_id);
-                    }
+    // carries out whatever the machine queued up
+    async fn flush(&self) {
+        let _flushing = self.flushing.lock().await;
+        let outputs = self.machine().take_outputs();
+        let mut outbox = Outbox::new();
+
+        for output in outputs {
+            match output {
+                Output::Send(target, message) => outbox.push((target, message)),
+                Output::Event(event) => {
+                    // no subscribers is fine
+                    let _ = self.event_tx.send(event);
                 }
+                Output::BackendUpdates(updates) => {
+                    self.backend_pools.apply_backend_updates(&updates).await;
+                }
+                Output::KvChanged(entry) => self.apply_kv_entry(&entry).await,
             }
         }
 
-        Ok(())
+        if !outbox.is_empty() {
+            self.send_batch(outbox).await;
+        }
+        self.check_incarnation();
     }
 
-    async fn process_member_updates(&self, updates: Vec<MemberUpdate>) {
-        let mut members = self.member_list.write().await;
-        let local_id = members.local_member().id.clone();
-
-        for update in updates {
-            if update.member_id == local_id
-                && (update.state == MemberState::Suspect || update.state == MemberState::Dead)
-            {
-                warn!("Received false accusation - disputing.");
-                members.increment_incarnation();
-                continue;
-            }
+    // wakes the snapshot loop once our incarnation went past the saved one
+    fn check_incarnation(&self) {
+        if self.config.state_file.is_none() {
+            return;
+        }
+        let incarnation = self.machine().local_member().incarnation;
+        if incarnation > self.saved_incarnation.load(Ordering::Relaxed) {
+            self.snapshot_due.notify_one();
+        }
+    }
 
-            members.upsert_member(Member {
-                id: update.member_id,
-                addr: update.addr,
-                state: update.state,
-                incarnation: update.incarnation,
-            });
+    pub async fn send_message(&self, message: GossipMessage, target: SocketAddr) -> Result<()> {
+        if let Some(bytes) = self.prepare(message, target)? {
+            self.transport.send_packet(target, &bytes).await?;
         }
+        Ok(())
     }
 
-    pub async fn start_gossip_loop(
-        member_list: SharedMemberList,
-        socket: Arc<UdpSocket>,
-        pending_pings: Arc<Mutex<HashMap<MemberId, Instant>>>,
-        pending_indirect_pings: Arc<Mutex<HashMap<MemberId, IndirectPingState>>>,
-        backend_pool: SharedBackendPool,
-        gossip_interval: Duration,
-        ping_timeout: Duration,
-    ) {
-        let mut interval = tokio::time::interval(gossip_interval);
-        let mut tick_count = 0;
+    // sends a gossip round's messages at once: messages for the same peer
+    // share packets if it understands compounds, and all packets go out in
+    // as few syscalls as possible
+    pub async fn send_batch(&self, messages: Outbox) {
+        let mut by_target: Vec<(SocketAddr, Vec<GossipMessage>)> = Vec::new();
+        for (target, message) in messages {
+            match by_target.iter_mut().find(|(t, _)| *t == target) {
+                Some((_, queued)) => queued.push(message),
+                None => by_target.push((target, vec![message])),
+            }
+        }
 
-        loop {
-            interval.tick().await;
-            tick_count += 1;
+        let mut datagrams = Vec::new();
+        for (target, messages) in by_target {
+            let compound = self
+                .peer(target)
+                .is_some_and(|m| m.supports(FEATURE_COMPOUND));
+            let messages = if compound {
+                GossipMessage::pack(messages)
+            } else {
+                messages
+            };
 
-            {
-                let mut members = member_list.write().await;
-                members.check_suspect_timeouts();
+            for message in messages {
+                if matches!(message, GossipMessage::Compound(_)) {
+                    GossipMetrics::incr(&self.metrics.compound_messages);
+                }
+                match self.prepare(message, target) {
+                    Ok(Some(bytes)) => datagrams.push((target, bytes)),
+                    Ok(None) => {}
+                    Err(e) => warn!("Failed to encode message for {}: {}", target, e),
+                }
             }
+        }
 
-            if tick_count % 30 == 0 {
-                let mut members = member_list.write().await;
-                members.prune_dead_members(Duration::from_secs(60));
-
-                let mut pending_indirect = pending_indirect_pings.lock().await;
-                let dead_member_ids: Vec<MemberId> = members
-                    .get_all_members()
-                    .iter()
-                    .filter(|m| m.state == MemberState::Dead)
-                    .map(|m| m.id.clone())
-                    .collect();
+        self.transport.send_packets(&datagrams).await;
+    }
 
-                for id in dead_member_ids {
-                    pending_indirect.remove(&id);
-                }
+    fn peer(&self, addr: SocketAddr) -> Option<Member> {
+        self.machine().members().get_member_by_addr(addr)
+    }
 
-                info!("Pruned dead members from list");
+    // encodes a message the way `target` can take it. returns the UDP packet
+    // to send, or None if it's too large and went over TCP instead
+    fn prepare(&self, message: GossipMessage, target: SocketAddr) -> Result<Option<Vec<u8>>> {
+        // only what the peer told us it understands. unknown peers (seeds we
+        // haven't heard from yet) get plain UDP
+        let (compress, stream) = self.peer(target).map_or((false, false), |m| {
+            (
+                self.config.compression && m.supports(FEATURE_LZ4),
+                self.config.stream_fallback && m.supports(FEATURE_STREAM),
+            )
+        });
+
+        let (bytes, compressed) = message.encode(&self.config.cluster_name, compress)?;
+        debug!("Sending {:?} to {}", message, target);
+
+        if bytes.len() <= MAX_UDP_PACKET_SIZE {
+            if compressed {
+                GossipMetrics::incr(&self.metrics.compressed_messages);
             }
+            return Ok(Some(bytes));
+        }
 
-            {
-                let adaptive_timeout = {
-                    let members = member_list.read().await;
-                    members.get_adaptive_timeout(ping_timeout)
-                };
-
-                let mut pending_indirect = pending_indirect_pings.lock().await;
-                let now = Instant::now();
-
-                let check_timeout = adaptive_timeout * 2;
-                let hard_timeout = adaptive_timeout * 3;
-
-                let timed_out: Vec<MemberId> = pending_indirect
-                    .iter()
-                    .filter(|(_, state)| now.duration_since(state.started_at) > check_timeout)
-                    .map(|(id, _)| id.clone())
-                    .collect();
-
-                for member_id in timed_out {
-                    if let Some(state) = pending_indirect.get(&member_id) {
-                        let num_probers = 3;
-                        let got_all_responses = state.responses.len() >= num_probers;
-                        let timeout_exceeded = now.duration_since(state.started_at) > hard_timeout;
-
-                        if got_all_responses || timeout_exceeded {
-                            let any_success = state.responses.iter().any(|&r| r);
-                            if !any_success {
-                                warn!(
-                                    "All indirect pings failed for {} - marking as suspect",
-                                    member_id.0
-                                );
-                                let mut members = member_list.write().await;
-                                members.mark_suspect(&member_id);
-                            }
-                            pending_indirect.remove(&member_id);
-                        }
-                    }
+        if stream {
+            GossipMetrics::incr(&self.metrics.stream_messages);
+            debug!(
+                "Message of {} bytes to {} too large for UDP - streaming it",
+                bytes.len(),
+                target
+            );
+            // don't hold up the caller (often the receive loop) on a TCP handshake
+            let transport = self.transport.clone();
+            tokio::spawn(async move {
+                if let Err(e) = transport.send_stream(target, bytes).await {
+                    debug!("Failed to stream message to {}: {}", target, e);
                 }
-            }
+            });
+            return Ok(None);
+        }
 
-            {
-                let mut pending = pending_pings.lock().await;
-                let pending_indirect = pending_indirect_pings.lock().await;
-
-                if !pending.is_empty() {
-                    let members_to_indirect: Vec<Member> = {
-                        let members = member_list.read().await;
-                        pending
-                            .keys()
-                            .filter(|id| !pending_indirect.contains_key(id))
-                            .filter_map(|id| {
-                                members.get_all_members().into_iter().find(|m| &m.id == id)
-                            })
-                            .collect()
-                    };
-
-                    drop(pending_indirect);
-
-                    for target in members_to_indirect {
-                        warn!("No direct ACK from {} - trying indirect pings", target.id.0);
-
-                        if let Err(e) = GossipLayer::send_indirect_pings(
-                            &member_list,
-                            &socket,
-                            &pending_indirect_pings,
-                            target,
-                            3, // try 3 indirect probers
-                        )
-                        .await
-                        {
-                            error!("Error sending indirect pings: {}", e);
-                        }
-                    }
+        let trimmed = GossipMetrics::incr(&self.metrics.trimmed_messages);
+        debug!(
+            "Message of {} bytes to {} too large for UDP - trimming updates ({} trimmed so far)",
+            bytes.len(),
+            target,
+            trimmed
+        );
+        let (bytes, compressed) = message
+            .trim_to_fit()
+            .encode(&self.config.cluster_name, compress)?;
+        if bytes.len() > MAX_UDP_PACKET_SIZE {
+            return Err(anyhow::anyhow!(
+                "Message size {} exceeds max UDP packet size {}",
+                bytes.len(),
+                MAX_UDP_PACKET_SIZE
+            ));
+        }
+        if compressed {
+            GossipMetrics::incr(&self.metrics.compressed_messages);
+        }
+        Ok(Some(bytes))
+    }
 
-                    pending.clear();
+    // receives packets and runs the machine's timers (probe rounds, relayed
+    // pings) until the task is dropped
+    pub async fn run(&self) {
+        loop {
+            let deadline = self.machine().poll_timeout();
+
+            // timers first, so a flood of packets can't hold up probing, and
+            // in a fixed order so seeded simulations replay exactly
+            tokio::select! {
+                biased;
+                _ = tokio::time::sleep_until(deadline) => {
+                    let backend_updates = self.backend_pools.backend_health_updates().await;
+                    let mut machine = self.machine();
+                    machine.set_backend_updates(backend_updates);
+                    machine.handle_timeout(Instant::now());
                 }
+                received = self.transport.recv() => match received {
+                    Ok((data, src_addr)) => {
+                        self.machine().handle_packet(Instant::now(), &data, src_addr);
+                    }
+                    Err(e) => {
+                        error!("Error receiving gossip message: {}", e);
+                    }
+                },
             }
 
-            let target = {
-                let mut members = member_list.write().await;
-                members.get_random_alive_member()
-            };
+            self.flush().await;
+        }
+    }
 
-            if let Some(target_member) = target {
-                {
-                    let mut pending = pending_pings.lock().await;
-                    pending.insert(target_member.id.clone(), Instant::now());
-                }
-                let local_info = {
-                    let members = member_list.read().await;
-                    let backends = backend_pool.read().await;
-                    let local = members.local_member();
-
-                    let mut backend_updates = backends.get_backend_health_updates();
-                    for update in &mut backend_updates {
-                        update.from_member = local.id.clone();
-                    }
-                    let update_limit = std::cmp::max(5, members.get_all_members().len() / 2);
-
-                    (
-                        local.id.clone(),
-                        local.addr,
-                        local.incarnation,
-                        members.get_member_updates(update_limit),
-                        backend_updates,
-                    )
-                };
-
-                let ping = GossipMessage::Ping {
-                    from: local_info.0.clone(),
-                    from_addr: local_info.1,
-                    incarnation: local_info.2,
-                    member_updates: local_info.3,
-                    backend_updates: local_info.4,
-                }
-                .trim_to_fit();
+    // seeds can come from DNS or commands, so they are looked up here and
+    // not in the machine
+    pub async fn start_reconnect_loop(&self) {
+        let reconnect_interval = Duration::from_millis(self.config.reconnect_interval_ms);
 
-                debug!(
-                    "Pinging member {} at {}",
-                    target_member.id.0, target_member.addr
-                );
+        loop {
+            tokio::time::sleep(reconnect_interval).await;
 
-                if let Ok(bytes) = ping.to_bytes() {
-                    if let Err(e) = socket.send_to(&bytes, target_member.addr).await {
-                        warn!("Failed to send ping to {}: {}", target_member.addr, e);
-                        let mut pending = pending_pings.lock().await;
-                        pending.remove(&target_member.id);
-                    }
-                }
-            }
+            let seed_nodes = self.seeds.resolve().await;
+            self.machine().reconnect(&seed_nodes);
+            self.flush().await;
         }
     }
 
-    pub async fn join_cluster(&self, seed_nodes: Vec<SocketAddr>) -> Result<(), anyhow::Error> {
-        if seed_nodes.is_empty() {
-            info!("No seed nodes configured - starting as initial cluster member");
-            return Ok(());
+    // shares our connection counts with the cluster and recomputes our
+    // share of every backend's connection limit from everyone else's
+    pub async fn start_load_report_loop(&self) {
+        let mut interval =
+            tokio::time::interval(Duration::from_millis(self.config.load_report_interval_ms));
+        let mut published = None;
+        loop {
+            interval.tick().await;
+            self.report_load(&mut published).await;
         }
+    }
 
-        info!("Joining cluster via {} seed nodes", seed_nodes.len());
-        let mut successful_contacts = 0;
-        let max_retries = 3;
-        let retry_delay = Duration::from_millis(500);
-
-        for retry in 0..max_retries {
-            if successful_contacts > 0 {
-                break;
-            }
-            if retry > 0 {
-                info!(
-                    "Retry {}/{} - attempting to contact seed nodes",
-                    retry, max_retries
-                );
-                tokio::time::sleep(retry_delay).await;
+    async fn report_load(&self, published: &mut Option<Vec<u8>>) {
+        let mut connections = Vec::new();
+        for (name, pool) in self.backend_pools.iter() {
+            let local = pool.read().await.local_connections();
+            connections.extend(
+                local
+                    .into_iter()
+                    .map(|(addr, count)| (name.to_string(), addr, count)),
+            );
+        }
+        let key = kv::load_key(&self.local_member().id);
+        self.publish_report(&key, kv::encode_load(&connections), published)
+            .await;
+
+        let (reports, nodes) = self.member_reports(kv::LOAD_KEY_PREFIX);
+        let mut remote: HashMap<String, HashMap<SocketAddr, u64>> = HashMap::new();
+        for report in reports {
+            for (pool, addr, count) in kv::decode_load(&report) {
+                *remote.entry(pool).or_default().entry(addr).or_default() += count as u64;
             }
+        }
 
-            for seed_addr in &seed_nodes {
-                let (ping_msg, local_addr) = {
-                    let members = self.member_list.read().await;
-                    let local = members.local_member();
-                    let ping = GossipMessage::Ping {
-                        from: local.id.clone(),
-                        from_addr: local.addr,
-                        incarnation: local.incarnation,
-                        member_updates: vec![],
-                        backend_updates: vec![],
-                    };
-
-                    (ping, local.addr)
-                };
-
-                if *seed_addr == local_addr {
-                    continue;
-                }
+        for (name, pool) in self.backend_pools.iter() {
+            let remote = remote.remove(name).unwrap_or_default();
+            pool.write().await.rebalance(&remote, nodes);
+        }
+    }
 
-                info!("Contacting seed node at {}", seed_addr);
+    // writes the sticky session pins made here to the store as soon as
+    // they're made, and deletes ours once they expire, for every pool
+    pub async fn start_sticky_session_loop(&self) {
+        let publishers = self
+            .backend_pools
+            .iter()
+            .map(|(name, pool)| self.publish_sticky_pins(name, pool));
+        futures::future::join_all(publishers).await;
+    }
 
-                if let Ok(bytes) = ping_msg.to_bytes() {
-                    match self.socket.send_to(&bytes, seed_addr).await {
-                        Ok(_) => {
-                            info!("Sent join request to {}", seed_addr);
-                        }
-                        Err(e) => {
-                            warn!("Failed to contact seed node {}: {}", seed_addr, e);
+    async fn publish_sticky_pins(&self, name: &str, pool: &SharedBackendPool) {
+        let Some(changed) = pool.read().await.sticky_changes() else {
+            return;
+        };
+        loop {
+            tokio::select! {
+                _ = changed.notified() => {}
+                _ = tokio::time::sleep(STICKY_SWEEP_INTERVAL) => {}
+            }
+
+            let updates = pool.read().await.take_sticky_updates();
+            for (client, pin) in updates {
+                let key = kv::sticky_key(name, client);
+                match pin {
+                    Some(pin) => {
+                        if let Err(e) = self.kv_set(&key, pin.encode()).await {
+                            warn!("Failed to publish {}: {}", key, e);
                         }
                     }
-                }
-                tokio::time::sleep(Duration::from_millis(1000)).await;
-
-                {
-                    let members = self.member_list.read().await;
-                    let known_members = members.get_all_members().len();
-                    successful_contacts = known_members;
-                    if successful_contacts > 0 {
-                        info!(
-                            "Successfully discovered {} cluster members",
-                            successful_contacts
-                        );
-                        break;
-                    }
+                    None => self.kv_delete(&key).await,
                 }
             }
         }
+    }
 
-        if successful_contacts == 0 {
-            warn!(
-                "Failed to contact any seed nodes after {} retries - starting isolated",
-                max_retries
-            );
+    // shares what the rate limiter of `frontend` let through with the
+    // cluster and rebalances its shares from everyone else's
+    pub async fn start_rate_limit_loop(&self, frontend: &str, limiter: Arc<RateLimiter>) {
+        let mut interval = tokio::time::interval(limiter.report_interval());
+        let mut published = None;
+        loop {
+            interval.tick().await;
+
+            let report = limiter.take_report();
+            let key = kv::rate_limit_key(frontend, &self.local_member().id);
+            self.publish_report(&key, ratelimit::encode_report(&report), &mut published)
+                .await;
+
+            let (reports, nodes) = self.member_reports(&kv::rate_limit_prefix(frontend));
+            let mut remote = HashMap::new();
+            for report in reports {
+                for (key, count) in ratelimit::decode_report(&report) {
+                    *remote.entry(key).or_default() += count;
+                }
+            }
+            limiter.rebalance(remote, nodes);
         }
-        info!(
-            "Cluster join completed with {} known members",
-            successful_contacts
-        );
-        Ok(())
     }
 
-    async fn process_backend_updates(&self, updates: Vec<BackendUpdate>) {
-        if updates.is_empty() {
+    // writes a periodic report to the store, unless it didn't change
+    async fn publish_report(&self, key: &str, value: Vec<u8>, published: &mut Option<Vec<u8>>) {
+        // unless it was deleted meanwhile, e.g. reaped while we were away
+        if published.as_ref() == Some(&value) && self.kv_get(key).as_ref() == Some(&value) {
             return;
         }
-
-        let mut backends = self.backend_pool.write().await;
-        for update in updates {
-            backends.apply_backend_update(&update);
+        match self.kv_set(key, value.clone()).await {
+            Ok(()) => *published = Some(value),
+            Err(e) => warn!("Failed to publish {}: {}", key, e),
         }
     }
 
-    async fn send_indirect_pings(
-        member_list: &SharedMemberList,
-        socket: &Arc<UdpSocket>,
-        pending_indirect: &Arc<Mutex<HashMap<MemberId, IndirectPingState>>>,
-        target: Member,
-        num_indirect: usize,
-    ) -> Result<()> {
-        let indirect_probers: Vec<Member> = {
-            let members = member_list.read().await;
-            let local_id = members.local_member().id.clone();
+    // the reports alive members keep under `prefix`, and how many nodes
+    // there are, us included. reports of members that are gone don't count
+    fn member_reports(&self, prefix: &str) -> (Vec<Vec<u8>>, usize) {
+        let alive: HashSet<MemberId> = self.alive_members().into_iter().map(|m| m.id).collect();
+        let reports = self
+            .kv_scan(prefix)
+            .into_iter()
+            .filter(|(key, _)| alive.contains(&MemberId::new(key[prefix.len()..].to_string())))
+            .map(|(_, value)| value)
+            .collect();
+        (reports, alive.len() + 1)
+    }
+
+    // a singleton task: deletes the load and rate limit reports of members
+    // that were pruned from the member list. they aren't coming back to do
+    // it themselves, and every node would carry the reports around forever
+    pub async fn start_report_reaper_loop(&self) {
+        let mut interval = tokio::time::interval(REAP_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.reap_reports().await;
+        }
+    }
 
+    pub async fn reap_reports(&self) {
+        let known: HashSet<MemberId> = {
+            let machine = self.machine();
+            let members = machine.members();
             members
-                .get_alive_members()
+                .get_all_members()
                 .into_iter()
-                .filter(|m| m.id != target.id && m.id != local_id) // Not target, not us
-                .take(num_indirect)
+                .map(|m| m.id)
+                .chain([members.local_member().id.clone()])
                 .collect()
         };
+        let reports = self
+            .kv_scan(kv::LOAD_KEY_PREFIX)
+            .into_iter()
+            .chain(self.kv_scan(kv::RATE_LIMIT_KEY_PREFIX));
+        for (key, _) in reports {
+            if let Some(writer) = kv::report_writer(&key)
+                && !known.contains(&MemberId::new(writer.to_string()))
+            {
+                info!("Deleting {}, {} is gone", key, writer);
+                self.kv_delete(&key).await;
+            }
+        }
+    }
 
-        if indirect_probers.is_empty() {
-            warn!("No members available for indirect ping of {}", target.id.0);
-            return Ok(());
+    // re-runs the leader election for singleton tasks every gossip round
+    pub async fn start_leader_election_loop(&self, election: Arc<LeaderElection>) {
+        let mut interval =
+            tokio::time::interval(Duration::from_millis(self.config.gossip_interval_ms));
+        loop {
+            interval.tick().await;
+            let (local, term) = {
+                let machine = self.machine();
+                (machine.local_member().id.clone(), machine.members().term())
+            };
+            election.update(Instant::now(), &local, term, &self.alive_members());
         }
+    }
 
-        info!(
-            "Sending {} indirect ping requests for {} via {:?}",
-            indirect_probers.len(),
-            target.id.0,
-            indirect_probers.iter().map(|m| &m.id.0).collect::<Vec<_>>()
-        );
+    // elects an owner for every virtual IP among the alive members each
+    // gossip round, and runs the hooks of the ones we gain or lose
+    pub async fn start_virtual_ip_loop(&self, vips: Arc<VirtualIps>) {
+        self.advertise_virtual_ips(&vips);
+        // give the cluster time to tell us about current owners before
+        // claiming anything
+        tokio::time::sleep(Duration::from_millis(self.config.suspect_timeout_ms)).await;
+
+        // the last hook of every address
+        let mut hooks: HashMap<IpAddr, JoinHandle<()>> = HashMap::new();
+        let mut interval =
+            tokio::time::interval(Duration::from_millis(self.config.gossip_interval_ms));
+        loop {
+            interval.tick().await;
 
-        {
-            let mut pending = pending_indirect.lock().await;
-            pending.insert(
-                target.id.clone(),
-                IndirectPingState {
-                    target: target.clone(),
-                    responses: Vec::new(),
-                    started_at: Instant::now(),
-                },
-            );
+            let local = self.local_member();
+            let mut members = self.alive_members();
+            members.push(local.clone());
+            let changes = vips.update(&local.id, &members);
+            if changes.is_empty() {
+                continue;
+            }
+            // claims first, so the others learn about them while hooks run.
+            // hooks run in the background, so a slow one doesn't hold up
+            // the elections. an address's hooks still run in order, after
+            // the one before
+            self.advertise_virtual_ips(&vips);
+            for (config, up) in changes {
+                let previous = hooks.remove(&config.addr);
+                let addr = config.addr;
+                let hook = tokio::spawn(async move {
+                    if let Some(previous) = previous {
+                        let _ = previous.await;
+                    }
+                    vip::run_hook(&config, up).await;
+                });
+                hooks.insert(addr, hook);
+            }
+            hooks.retain(|_, hook| !hook.is_finished());
         }
+    }
+
+    fn advertise_virtual_ips(&self, vips: &VirtualIps) {
+        let mut tags = self.local_member().tags;
+        vips.advertise(&mut tags);
+        self.set_tags(tags);
+    }
 
-        let local_info = {
-            let members = member_list.read().await;
-            let local = members.local_member();
-            (local.id.clone(), local.addr)
+    // writes the current membership and backend health to the state file
+    pub async fn save_snapshot(&self) -> Result<()> {
+        let Some(path) = &self.config.state_file else {
+            return Ok(());
         };
+        let backends = self.backend_pools.backend_health_updates().await;
+        let snapshot = self.machine().snapshot(backends);
+        snapshot.save(path).await?;
+        self.saved_incarnation
+            .fetch_max(snapshot.incarnation, Ordering::Relaxed);
+        Ok(())
+    }
 
-        for prober in indirect_probers {
-            let indirect_ping = GossipMessage::IndirectPing {
-                from: local_info.0.clone(),
-                from_addr: local_info.1,
-                target_id: target.id.clone(),
-                target_addr: target.addr,
-            };
+    pub async fn start_snapshot_loop(&self) {
+        if self.config.state_file.is_none() {
+            return;
+        }
+        let mut interval =
+            tokio::time::interval(Duration::from_millis(self.config.snapshot_interval_ms));
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = self.snapshot_due.notified() => {}
+            }
+            if let Err(e) = self.save_snapshot().await {
+                warn!("Failed to save gossip state: {}", e);
+            }
+        }
+    }
 
-            if let Ok(bytes) = indirect_ping.to_bytes() {
-                socket.send_to(&bytes, prober.addr).await?;
-                debug!(
-                    "Sent indirect ping request to {} for target {}",
-                    prober.id.0, target.id.0
+    pub async fn join_cluster(&self) -> Result<(), anyhow::Error> {
+        if self.seeds.is_empty() && self.restored_peers.is_empty() {
+            info!("No seed nodes configured - starting as initial cluster member");
+            return Ok(());
+        }
+
+        let mut known_members = 0;
+        for attempt in 0..JOIN_ATTEMPTS {
+            if attempt > 0 {
+                info!(
+                    "Retry {}/{} - attempting to contact seed nodes",
+                    attempt, JOIN_ATTEMPTS
                 );
+                tokio::time::sleep(JOIN_RETRY_DELAY).await;
+            }
+
+            // resolved again on every attempt, the set behind a DNS name or
+            // seed file may change while we are trying
+            let mut seed_nodes = self.seeds.resolve().await;
+            for &addr in &self.restored_peers {
+                if !seed_nodes.contains(&addr) {
+                    seed_nodes.push(addr);
+                }
+            }
+            info!("Joining cluster via {} seed nodes", seed_nodes.len());
+            self.machine().join(&seed_nodes);
+            self.flush().await;
+
+            tokio::time::sleep(JOIN_WAIT).await;
+            known_members = self.machine().members().get_all_members().len();
+            if known_members > 0 {
+                info!("Successfully discovered {} cluster members", known_members);
+                break;
             }
         }
 
+        if known_members == 0 {
+            warn!(
+                "Failed to contact any seed nodes after {} attempts - starting isolated",
+                JOIN_ATTEMPTS
+            );
+        }
+        info!(
+            "Cluster join completed with {} known members",
+            known_members
+        );
         Ok(())
     }
 }
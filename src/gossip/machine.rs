@@ -0,0 +1,922 @@
+use super::awareness::Awareness;
+use super::coordinate::Coordinate;
+use super::events::{Enqueued, EventBuffer, LamportClock};
+use super::hlc::HybridClock;
+use super::kv::KvStore;
+use super::member_list::MemberList;
+use super::messages::{
+    BackendUpdate, FEATURE_EVENTS, FEATURE_KV, FEATURES_TAG, GossipMessage, KvEntry, Member,
+    MemberId, MemberState, MemberUpdate, Tags, UserEvent,
+};
+use super::metrics::GossipMetrics;
+use super::snapshot::{Snapshot, SnapshotMember};
+use super::states::IndirectPingState;
+use crate::config::GossipConfig;
+use anyhow::Result;
+use rand::rngs::StdRng;
+use rand::seq::{IndexedRandom, SliceRandom};
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+const NUM_INDIRECT_PROBERS: usize = 3;
+const FOREIGN_WARNING_INTERVAL: Duration = Duration::from_secs(60);
+// senders we remember warning about. source addresses are easily spoofed,
+// past this many the others are only counted until some expire
+const MAX_FOREIGN_WARNINGS: usize = 1024;
+// members each round's user events and key/value updates are sent to
+const BROADCAST_FANOUT: usize = 3;
+// user events and key/value entries are meant to be small
+const MAX_EVENT_SIZE: usize = 512;
+const MAX_KV_ENTRY_SIZE: usize = 512;
+// how long we wait for the target of an IndirectPing before we nack
+const RELAY_TIMEOUT: Duration = Duration::from_millis(500);
+// dead members are pruned every this many rounds
+const PRUNE_ROUNDS: u64 = 30;
+
+// what the machine needs done, in the order it needs it
+#[derive(Debug)]
+pub enum Output {
+    Send(SocketAddr, GossipMessage),
+    // a user event we hadn't seen before, for subscribers
+    Event(UserEvent),
+    // backend health heard from a peer, for the backend pool
+    BackendUpdates(Vec<BackendUpdate>),
+    // a key/value entry that changed, locally or from a peer
+    KvChanged(KvEntry),
+}
+
+// an IndirectPing we are answering: `requester` wants to know whether the
+// target acks us before `deadline`
+#[derive(Debug)]
+struct Relay {
+    requester: MemberId,
+    requester_addr: SocketAddr,
+    deadline: Instant,
+}
+
+// the whole gossip protocol without any I/O. fed received packets and the
+// current time, it queues the messages to send and tells when it next
+// needs to be woken up. GossipLayer drives it over a real transport, tests
+// can drive it by hand
+pub struct GossipMachine {
+    config: GossipConfig,
+    members: MemberList,
+    awareness: Awareness,
+    metrics: Arc<GossipMetrics>,
+    // direct probes sent this round
+    pending_pings: HashMap<MemberId, Instant>,
+    pending_indirect_pings: HashMap<MemberId, IndirectPingState>,
+    // targets we are pinging on behalf of an IndirectPing, until they ack
+    relays: HashMap<MemberId, Vec<Relay>>,
+    foreign_warnings: HashMap<SocketAddr, Instant>,
+    event_clock: LamportClock,
+    events: EventBuffer,
+    kv: KvStore,
+    // health of our own backends, piggybacked on every Ping and Ack
+    backend_updates: Vec<BackendUpdate>,
+    rng: StdRng,
+    next_round: Instant,
+    rounds: u64,
+    last_push_pull: Option<Instant>,
+    outputs: Vec<Output>,
+}
+
+impl GossipMachine {
+    pub fn new(
+        local_member: Member,
+        config: &GossipConfig,
+        clock: Arc<HybridClock>,
+        now: Instant,
+    ) -> Self {
+        let kv = KvStore::new(local_member.id.clone(), clock);
+        let members = MemberList::new(
+            local_member,
+            Duration::from_millis(config.suspect_timeout_ms),
+            config.suspicion_max_timeout_mult,
+            config.suspicion_confirmations,
+            now,
+        );
+
+        Self {
+            config: config.clone(),
+            members,
+            awareness: Awareness::new(config.awareness_max_multiplier),
+            metrics: Arc::new(GossipMetrics::default()),
+            pending_pings: HashMap::new(),
+            pending_indirect_pings: HashMap::new(),
+            relays: HashMap::new(),
+            foreign_warnings: HashMap::new(),
+            event_clock: LamportClock::default(),
+            events: EventBuffer::new(config.event_buffer_size),
+            kv,
+            backend_updates: Vec::new(),
+            rng: StdRng::from_rng(&mut rand::rng()),
+            next_round: now + Duration::from_millis(config.gossip_interval_ms),
+            rounds: 0,
+            last_push_pull: None,
+            outputs: Vec::new(),
+        }
+    }
+
+    // every random choice (probe order, indirect probers, broadcast targets,
+    // event ids) follows from `seed`, for simulations
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+        self.members.seed_rng(seed.wrapping_add(1));
+    }
+
+    pub fn members(&self) -> &MemberList {
+        &self.members
+    }
+
+    pub fn members_mut(&mut self) -> &mut MemberList {
+        &mut self.members
+    }
+
+    pub fn local_member(&self) -> &Member {
+        self.members.local_member()
+    }
+
+    pub fn awareness(&self) -> &Awareness {
+        &self.awareness
+    }
+
+    pub fn metrics(&self) -> Arc<GossipMetrics> {
+        self.metrics.clone()
+    }
+
+    pub fn kv(&self) -> &KvStore {
+        &self.kv
+    }
+
+    pub fn take_outputs(&mut self) -> Vec<Output> {
+        std::mem::take(&mut self.outputs)
+    }
+
+    fn send(&mut self, target: SocketAddr, message: GossipMessage) {
+        self.outputs.push(Output::Send(target, message));
+    }
+
+    pub fn set_tags(&mut self, mut tags: Tags) {
+        // wire features describe this binary, they aren't the caller's to change
+        if let Some(features) = self.members.local_member().tag(FEATURES_TAG) {
+            tags.insert(FEATURES_TAG.to_string(), features.to_string());
+        }
+        self.members.set_local_tags(tags);
+    }
+
+    pub fn set_backend_updates(&mut self, updates: Vec<BackendUpdate>) {
+        self.backend_updates = updates;
+    }
+
+    // everyone we know of that isn't dead, for rejoining after a restart
+    pub fn snapshot(&self, backends: Vec<BackendUpdate>) -> Snapshot {
+        let members = self
+            .members
+            .get_all_members()
+            .into_iter()
+            .filter(|m| m.state != MemberState::Dead)
+            .map(|m| SnapshotMember {
+                id: m.id,
+                addr: m.addr,
+                incarnation: m.incarnation,
+            })
+            .collect();
+
+        Snapshot {
+            incarnation: self.members.local_member().incarnation,
+            members,
+            backends,
+        }
+    }
+
+    // peers may still hold suspicions about our last incarnation, starting
+    // above it refutes them
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.members.refute(snapshot.incarnation);
+    }
+
+    // when handle_timeout should be called next
+    pub fn poll_timeout(&self) -> Instant {
+        self.relays
+            .values()
+            .flatten()
+            .map(|relay| relay.deadline)
+            .fold(self.next_round, Instant::min)
+    }
+
+    pub fn handle_timeout(&mut self, now: Instant) {
+        self.expire_relays(now);
+
+        if now >= self.next_round {
+            self.gossip_round(now);
+            // lifeguard: a node that keeps missing acks probes less aggressively
+            self.next_round = now
+                + self
+                    .awareness
+                    .scale_timeout(Duration::from_millis(self.config.gossip_interval_ms));
+        }
+    }
+
+    pub fn broadcast_event(&mut self, name: &str, payload: Vec<u8>) -> Result<()> {
+        if name.len() + payload.len() > MAX_EVENT_SIZE {
+            return Err(anyhow::anyhow!(
+                "Event '{}' is larger than {} bytes",
+                name,
+                MAX_EVENT_SIZE
+            ));
+        }
+
+        let event = UserEvent {
+            id: self.rng.random(),
+            ltime: self.event_clock.increment(),
+            origin: self.members.local_member().id.clone(),
+            name: name.to_string(),
+            payload,
+        };
+
+        debug!(
+            "Broadcasting event '{}' at ltime {}",
+            event.name, event.ltime
+        );
+        self.receive_event(event);
+        Ok(())
+    }
+
+    fn receive_event(&mut self, event: UserEvent) {
+        self.event_clock.witness(event.ltime);
+
+        if !self.events.observe(&event, self.event_clock.time()) {
+            return;
+        }
+        match self.events.enqueue(event.clone()) {
+            Enqueued::Queued => {}
+            Enqueued::Coalesced => {
+                GossipMetrics::incr(&self.metrics.coalesced_events);
+            }
+            Enqueued::Dropped => {
+                GossipMetrics::incr(&self.metrics.dropped_events);
+            }
+        }
+
+        self.outputs.push(Output::Event(event));
+    }
+
+    // a local write to the key/value store, None deletes the key. only
+    // writes can be too large
+    pub fn kv_set(&mut self, key: &str, value: Option<Vec<u8>>) -> Result<()> {
+        if let Some(value) = &value
+            && key.len() + value.len() > MAX_KV_ENTRY_SIZE
+        {
+            return Err(anyhow::anyhow!(
+                "Entry '{}' is larger than {} bytes",
+                key,
+                MAX_KV_ENTRY_SIZE
+            ));
+        }
+        let entry = self.kv.set(key, value);
+        self.outputs.push(Output::KvChanged(entry));
+        Ok(())
+    }
+
+    fn merge_kv_entries(&mut self, entries: Vec<KvEntry>) {
+        for entry in entries {
+            if self.kv.merge(entry.clone()) {
+                debug!("Key {} updated by {}", entry.key, entry.writer.0);
+                self.outputs.push(Output::KvChanged(entry));
+            }
+        }
+    }
+
+    // first contact with the cluster, every seed gets our own state
+    pub fn join(&mut self, seeds: &[SocketAddr]) {
+        let local = self.members.local_member().clone();
+        for &seed_addr in seeds {
+            if seed_addr == local.addr {
+                continue;
+            }
+            info!("Contacting seed node at {}", seed_addr);
+            let ping = GossipMessage::Ping {
+                from: local.id.clone(),
+                from_addr: local.addr,
+                incarnation: local.incarnation,
+                coordinate: self.members.local_coordinate(),
+                member_updates: vec![MemberUpdate::from(&local)],
+                backend_updates: vec![],
+            };
+            self.send(seed_addr, ping);
+        }
+    }
+
+    // SWIM never probes dead members again, so once both halves of a
+    // partition declared each other dead nothing would bring them back
+    // together. Every so often ping one dead member and any seed we aren't
+    // currently connected to.
+    pub fn reconnect(&mut self, seeds: &[SocketAddr]) {
+        let local_addr = self.members.local_member().addr;
+        let alive_addrs: Vec<SocketAddr> = self
+            .members
+            .get_alive_members()
+            .iter()
+            .map(|m| m.addr)
+            .collect();
+
+        let mut targets = Vec::new();
+        if let Some(member) = self.members.get_random_dead_member() {
+            info!(
+                "Attempting to reconnect to dead member {} at {}",
+                member.id.0, member.addr
+            );
+            targets.push(member);
+        }
+
+        for &seed_addr in seeds {
+            if seed_addr == local_addr || alive_addrs.contains(&seed_addr) {
+                continue;
+            }
+            debug!("Re-contacting seed node at {}", seed_addr);
+            targets.push(Member {
+                id: MemberId::generate(seed_addr),
+                addr: seed_addr,
+                state: MemberState::Dead,
+                incarnation: 0,
+                tags: Tags::new(),
+            });
+        }
+
+        // not tracked in pending_pings: no answer just means still dead
+        for target in targets {
+            let ping = self.build_ping(&target);
+            self.send(target.addr, ping);
+        }
+    }
+
+    pub fn handle_packet(&mut self, now: Instant, data: &[u8], src_addr: SocketAddr) {
+        match GossipMessage::decode(data) {
+            Ok((cluster, _)) if cluster != self.config.cluster_name => {
+                self.reject_foreign_message(now, &cluster, src_addr);
+            }
+            Ok((_, message)) => {
+                debug!("Received {:?} from {}", message, src_addr);
+
+                let messages = match message {
+                    GossipMessage::Compound(messages) => messages,
+                    message => vec![message],
+                };
+                for message in messages {
+                    self.handle_message(now, message, src_addr);
+                }
+            }
+            Err(e) => {
+                warn!("Failed to deserialize message from {}: {}", src_addr, e);
+            }
+        }
+    }
+
+    fn reject_foreign_message(&mut self, now: Instant, cluster: &str, src_addr: SocketAddr) {
+        let rejected = GossipMetrics::incr(&self.metrics.foreign_cluster_messages);
+
+        // once a minute per sender is plenty, it'll keep pinging us
+        let should_warn = self
+            .foreign_warnings
+            .get(&src_addr)
+            .is_none_or(|at| now.duration_since(*at) > FOREIGN_WARNING_INTERVAL);
+
+        if should_warn && self.foreign_warnings.len() < MAX_FOREIGN_WARNINGS {
+            self.foreign_warnings.insert(src_addr, now);
+            warn!(
+                "Rejecting gossip from {} in cluster '{}', we are in cluster '{}' - check its seed nodes ({} foreign messages rejected so far)",
+                src_addr, cluster, self.config.cluster_name, rejected
+            );
+        } else {
+            debug!("Rejected gossip from {} in cluster '{}'", src_addr, cluster);
+        }
+    }
+
+    pub fn handle_message(&mut self, now: Instant, message: GossipMessage, _src_addr: SocketAddr) {
+        match message {
+            GossipMessage::Ping {
+                from,
+                from_addr,
+                incarnation,
+                coordinate,
+                member_updates,
+                backend_updates,
+            } => {
+                debug!("Handling Ping from {}", from.0);
+
+                self.process_member_updates(now, member_updates, &from);
+                self.process_backend_updates(backend_updates);
+
+                self.members.upsert_member(
+                    Member {
+                        id: from.clone(),
+                        addr: from_addr,
+                        state: MemberState::Alive,
+                        incarnation,
+                        tags: Tags::new(),
+                    },
+                    now,
+                );
+                self.members.set_coordinate(&from, coordinate);
+
+                let (local, coordinate, member_updates, backend_updates) = self.piggyback();
+                let ack = GossipMessage::Ack {
+                    from: local.id,
+                    from_addr: local.addr,
+                    incarnation: local.incarnation,
+                    coordinate,
+                    member_updates,
+                    backend_updates,
+                };
+                self.send(from_addr, ack);
+            }
+
+            GossipMessage::Ack {
+                from,
+                from_addr,
+                incarnation,
+                coordinate,
+                member_updates,
+                backend_updates,
+            } => {
+                debug!("Handling Ack from {}", from.0);
+
+                let rtt = self
+                    .pending_pings
+                    .remove(&from)
+                    .map(|sent_at| now.duration_since(sent_at));
+
+                // the sender's own update (with its tags) goes first, so the
+                // header below only refreshes what we already know
+                self.process_member_updates(now, member_updates, &from);
+
+                self.members.upsert_member(
+                    Member {
+                        id: from.clone(),
+                        addr: from_addr,
+                        state: MemberState::Alive,
+                        incarnation,
+                        tags: Tags::new(),
+                    },
+                    now,
+                );
+                self.members.mark_alive(&from, now);
+                match rtt {
+                    Some(rtt) => {
+                        self.members.record_rtt(&from, rtt, coordinate);
+                        self.awareness.apply_delta(-1);
+                    }
+                    None => self.members.set_coordinate(&from, coordinate),
+                }
+
+                self.process_backend_updates(backend_updates);
+                self.answer_relays(&from, true);
+            }
+
+            GossipMessage::IndirectPing {
+                from,
+                from_addr,
+                target_id,
+                target_addr,
+            } => {
+                debug!(
+                    "Handling IndirectPing request from {} to ping {}",
+                    from.0, target_id.0
+                );
+
+                let (local, coordinate, member_updates, backend_updates) = self.piggyback();
+                let ping = GossipMessage::Ping {
+                    from: local.id,
+                    from_addr: local.addr,
+                    incarnation: local.incarnation,
+                    coordinate,
+                    member_updates,
+                    backend_updates,
+                };
+                self.send(target_addr, ping);
+
+                self.relays.entry(target_id).or_default().push(Relay {
+                    requester: from,
+                    requester_addr: from_addr,
+                    deadline: now + RELAY_TIMEOUT,
+                });
+            }
+
+            GossipMessage::IndirectAck {
+                from,
+                target_id,
+                target_responded,
+            } => {
+                debug!(
+                    "Handling IndirectAck from {}: target {} responded={}",
+                    from.0, target_id.0, target_responded
+                );
+
+                if let Some(state) = self.pending_indirect_pings.get_mut(&target_id) {
+                    state.responses.push(target_responded);
+
+                    if target_responded {
+                        info!(
+                            "Target {} confirmed alive via indirect ping from {}",
+                            target_id.0, from.0
+                        );
+                        self.members.mark_alive(&target_id, now);
+                        self.pending_pings.remove(&target_id);
+                        self.pending_indirect_pings.remove(&target_id);
+                    }
+                }
+            }
+
+            GossipMessage::UserEvents(events) => {
+                for event in events {
+                    self.receive_event(event);
+                }
+            }
+
+            GossipMessage::KvUpdates(entries) => {
+                self.merge_kv_entries(entries);
+            }
+
+            GossipMessage::KvPushPull {
+                from_addr,
+                entries,
+                reply,
+            } => {
+                self.merge_kv_entries(entries);
+
+                if reply {
+                    self.queue_kv_snapshot(from_addr, false);
+                }
+            }
+
+            GossipMessage::Compound(_) => {
+                warn!("Ignoring nested compound message");
+            }
+        }
+    }
+
+    // tells whoever asked us to ping `target` how it went
+    fn answer_relays(&mut self, target: &MemberId, target_responded: bool) {
+        let Some(relays) = self.relays.remove(target) else {
+            return;
+        };
+        let local_id = self.members.local_member().id.clone();
+
+        for relay in relays {
+            debug!(
+                "Sending IndirectAck to {} - target responded: {}",
+                relay.requester.0, target_responded
+            );
+            self.send(
+                relay.requester_addr,
+                GossipMessage::IndirectAck {
+                    from: local_id.clone(),
+                    target_id: target.clone(),
+                    target_responded,
+                },
+            );
+        }
+    }
+
+    fn expire_relays(&mut self, now: Instant) {
+        let local_id = self.members.local_member().id.clone();
+        let mut expired = Vec::new();
+
+        self.relays.retain(|target, relays| {
+            relays.retain(|relay| {
+                if relay.deadline > now {
+                    return true;
+                }
+                expired.push((target.clone(), relay.requester_addr));
+                false
+            });
+            !relays.is_empty()
+        });
+
+        // relays is a HashMap, keep the output order stable
+        expired.sort();
+        for (target_id, requester_addr) in expired {
+            let nack = GossipMessage::IndirectAck {
+                from: local_id.clone(),
+                target_id,
+                target_responded: false,
+            };
+            self.send(requester_addr, nack);
+        }
+    }
+
+    fn process_member_updates(
+        &mut self,
+        now: Instant,
+        updates: Vec<MemberUpdate>,
+        from: &MemberId,
+    ) {
+        let local_id = self.members.local_member().id.clone();
+
+        for update in updates {
+            if update.member_id == local_id
+                && (update.state == MemberState::Suspect || update.state == MemberState::Dead)
+            {
+                // stale accusations about an incarnation we already refuted don't count
+                if update.incarnation >= self.members.local_member().incarnation {
+                    warn!("Received false accusation from {} - disputing.", from.0);
+                    self.members.refute(update.incarnation);
+                    self.awareness.apply_delta(1);
+                }
+                continue;
+            }
+
+            if update.member_id == local_id {
+                continue;
+            }
+
+            self.members.apply_member_update(update, from, now);
+        }
+    }
+
+    fn process_backend_updates(&mut self, updates: Vec<BackendUpdate>) {
+        if !updates.is_empty() {
+            self.outputs.push(Output::BackendUpdates(updates));
+        }
+    }
+
+    // local member and coordinate, plus the updates we piggyback on every
+    // Ping and Ack
+    fn piggyback(&self) -> (Member, Coordinate, Vec<MemberUpdate>, Vec<BackendUpdate>) {
+        let local = self.members.local_member().clone();
+
+        let update_limit = std::cmp::max(5, self.members.get_all_members().len() / 2);
+        let mut member_updates = self.members.get_member_updates(update_limit);
+
+        // always spread our own state and tags. if the message has to be
+        // trimmed, updates at the back are kept first
+        member_updates.retain(|u| u.member_id != local.id);
+        member_updates.push(MemberUpdate::from(&local));
+
+        (
+            local,
+            self.members.local_coordinate(),
+            member_updates,
+            self.backend_updates.clone(),
+        )
+    }
+
+    fn build_ping(&self, target: &Member) -> GossipMessage {
+        let (local, coordinate, mut member_updates, backend_updates) = self.piggyback();
+
+        // buddy system: make sure a suspect hears about its own suspicion so it
+        // can refute it. updates at the back survive trimming
+        if target.state == MemberState::Suspect {
+            member_updates.retain(|u| u.member_id != target.id);
+            member_updates.push(
+                self.members
+                    .get_member_update(&target.id)
+                    .unwrap_or_else(|| MemberUpdate::from(target)),
+            );
+        }
+
+        GossipMessage::Ping {
+            from: local.id,
+            from_addr: local.addr,
+            incarnation: local.incarnation,
+            coordinate,
+            member_updates,
+            backend_updates,
+        }
+    }
+
+    fn gossip_round(&mut self, now: Instant) {
+        self.rounds += 1;
+        self.members.check_suspect_timeouts(now);
+        self.foreign_warnings
+            .retain(|_, at| now.duration_since(*at) <= FOREIGN_WARNING_INTERVAL);
+
+        if self.rounds.is_multiple_of(PRUNE_ROUNDS) {
+            self.members.prune_dead_members(
+                Duration::from_millis(self.config.dead_member_timeout_ms),
+                now,
+            );
+
+            let dead_member_ids: Vec<MemberId> = self
+                .members
+                .get_all_members()
+                .iter()
+                .filter(|m| m.state == MemberState::Dead)
+                .map(|m| m.id.clone())
+                .collect();
+            for id in dead_member_ids {
+                self.pending_indirect_pings.remove(&id);
+            }
+
+            info!("Pruned dead members from list");
+        }
+
+        self.check_indirect_pings(now);
+
+        // whoever didn't ack last round's probe gets indirect pings
+        let mut unanswered: Vec<MemberId> = self
+            .pending_pings
+            .drain()
+            .map(|(id, _)| id)
+            .filter(|id| !self.pending_indirect_pings.contains_key(id))
+            .collect();
+        unanswered.sort();
+        for id in unanswered {
+            if let Some(target) = self.members.get_member(&id) {
+                warn!("No direct ACK from {} - trying indirect pings", target.id.0);
+                self.send_indirect_pings(now, target);
+            }
+        }
+
+        if let Some(target) = self.members.get_next_probe_target() {
+            self.pending_pings.insert(target.id.clone(), now);
+            debug!("Pinging member {} at {}", target.id.0, target.addr);
+            let ping = self.build_ping(&target);
+            self.send(target.addr, ping);
+        }
+
+        let push_pull_interval = Duration::from_millis(self.config.push_pull_interval_ms);
+        if self
+            .last_push_pull
+            .is_none_or(|at| now.duration_since(at) >= push_pull_interval)
+        {
+            self.last_push_pull = Some(now);
+            self.kv
+                .prune_tombstones(Duration::from_millis(self.config.kv_tombstone_ttl_ms));
+            self.push_pull();
+        }
+
+        self.queue_broadcasts();
+    }
+
+    fn check_indirect_pings(&mut self, now: Instant) {
+        let adaptive_timeout = self.awareness.scale_timeout(
+            self.members
+                .get_adaptive_timeout(Duration::from_millis(self.config.ping_timeout_ms)),
+        );
+        let check_timeout = adaptive_timeout * 2;
+        let hard_timeout = adaptive_timeout * 3;
+
+        let mut timed_out: Vec<MemberId> = self
+            .pending_indirect_pings
+            .iter()
+            .filter(|(_, state)| now.duration_since(state.started_at) > check_timeout)
+            .map(|(id, _)| id.clone())
+            .collect();
+        timed_out.sort();
+
+        for member_id in timed_out {
+            let Some(state) = self.pending_indirect_pings.get(&member_id) else {
+                continue;
+            };
+            let got_all_responses = state.responses.len() >= state.expected;
+            let timeout_exceeded = now.duration_since(state.started_at) > hard_timeout;
+            if !got_all_responses && !timeout_exceeded {
+                continue;
+            }
+
+            let Some(state) = self.pending_indirect_pings.remove(&member_id) else {
+                continue;
+            };
+            // lifeguard: probers that didn't even nack hint that it's
+            // our own network or scheduling that is in trouble
+            let missed_nacks = state.expected.saturating_sub(state.responses.len());
+            self.awareness.apply_delta(missed_nacks as i32);
+
+            if !state.responses.iter().any(|&r| r) {
+                warn!(
+                    "All indirect pings failed for {} - marking as suspect",
+                    member_id.0
+                );
+                self.suspect(now, state.target);
+            }
+        }
+    }
+
+    fn send_indirect_pings(&mut self, now: Instant, target: Member) {
+        let local = self.members.local_member().clone();
+
+        let mut probers: Vec<Member> = self
+            .members
+            .get_alive_members()
+            .into_iter()
+            .filter(|m| m.id != target.id && m.id != local.id) // Not target, not us
+            .collect();
+
+        // nearby probers answer sooner. shuffled first so equally close
+        // ones (or ones without a coordinate yet) share the load
+        probers.shuffle(&mut self.rng);
+        probers.sort_by_key(|m| self.members.estimate_rtt(&m.id).unwrap_or(Duration::MAX));
+        probers.truncate(NUM_INDIRECT_PROBERS);
+
+        if probers.is_empty() {
+            warn!(
+                "No members available for indirect ping of {} - marking as suspect",
+                target.id.0
+            );
+            self.awareness.apply_delta(1);
+            self.suspect(now, target);
+            return;
+        }
+
+        info!(
+            "Sending {} indirect ping requests for {} via {:?}",
+            probers.len(),
+            target.id.0,
+            probers.iter().map(|m| &m.id.0).collect::<Vec<_>>()
+        );
+
+        self.pending_indirect_pings.insert(
+            target.id.clone(),
+            IndirectPingState {
+                target: target.clone(),
+                responses: Vec::new(),
+                expected: probers.len(),
+                started_at: now,
+            },
+        );
+
+        for prober in probers {
+            let indirect_ping = GossipMessage::IndirectPing {
+                from: local.id.clone(),
+                from_addr: local.addr,
+                target_id: target.id.clone(),
+                target_addr: target.addr,
+            };
+            self.send(prober.addr, indirect_ping);
+        }
+    }
+
+    // marks `target` as suspect and tells it so right away (lifeguard buddy
+    // system), instead of waiting for the suspicion to reach it through gossip
+    fn suspect(&mut self, now: Instant, mut target: Member) {
+        self.members.mark_suspect(&target.id, now);
+
+        target.state = MemberState::Suspect;
+        let ping = self.build_ping(&target);
+        self.send(target.addr, ping);
+    }
+
+    // sends our whole key/value store to a random member, which answers with
+    // its own. catches whatever piggybacked updates missed
+    fn push_pull(&mut self) {
+        let candidates: Vec<Member> = self
+            .members
+            .get_alive_members()
+            .into_iter()
+            .filter(|m| m.supports(FEATURE_KV))
+            .collect();
+
+        let Some(target) = candidates.choose(&mut self.rng) else {
+            return;
+        };
+        debug!("Push-pull with {}", target.id.0);
+        self.queue_kv_snapshot(target.addr, true);
+    }
+
+    fn queue_kv_snapshot(&mut self, target: SocketAddr, reply: bool) {
+        let local_addr = self.members.local_member().addr;
+        for (i, entries) in self.kv.snapshot().into_iter().enumerate() {
+            self.send(
+                target,
+                GossipMessage::KvPushPull {
+                    from_addr: local_addr,
+                    entries,
+                    reply: reply && i == 0,
+                },
+            );
+        }
+    }
+
+    // piggybacks queued user events and key/value updates on this round to a
+    // few random members
+    fn queue_broadcasts(&mut self) {
+        let alive = self.members.get_alive_members();
+        let targets: Vec<Member> = alive
+            .choose_multiple(&mut self.rng, BROADCAST_FANOUT)
+            .cloned()
+            .collect();
+        if targets.is_empty() {
+            return;
+        }
+
+        let cluster_size = alive.len() + 1;
+        let events = self.events.take(cluster_size);
+        let kv_updates = self.kv.take_updates(cluster_size);
+
+        for target in targets {
+            if !events.is_empty() && target.supports(FEATURE_EVENTS) {
+                self.send(target.addr, GossipMessage::UserEvents(events.clone()));
+            }
+            if !kv_updates.is_empty() && target.supports(FEATURE_KV) {
+                self.send(target.addr, GossipMessage::KvUpdates(kv_updates.clone()));
+            }
+        }
+    }
+}
@@ -1,46 +1,32 @@
+use super::coordinate::{Coordinate, Vivaldi};
+use super::messages::{Member, MemberId, MemberState, MemberUpdate, Tags};
+use super::suspicion::Suspicion;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::{IndexedRandom, SliceRandom};
 use std::collections::HashMap;
-use std::sync::Arc;
-use std::time::{Duration, Instant};
-use tokio::sync::RwLock;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::time::Instant;
 use tracing::{debug, info, warn};
-use std::time::{SystemTime, UNIX_EPOCH};
-use super::messages::{Member, MemberId, MemberState, MemberUpdate};
-
-fn simple_rand(seed: &mut u64) -> u64 {
-    // Linear Congruential Generator (LCG): not great RNG, but fine for quick shuffling
-    *seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
-    (*seed >> 33) ^ *seed
-}
-
-fn shuffle<T>(v: &mut [T]) {
-    let start = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_nanos();
-    let mut seed = start as u64;
-
-    for i in (1..v.len()).rev() {
-        let j = (simple_rand(&mut seed) as usize) % (i + 1);
-        v.swap(i, j);
-    }
-}
-
 
 #[derive(Debug, Clone)]
 struct MemberInfo {
     member: Member,
     last_seen: Instant,
-    suspect_at: Option<Instant>,
+    suspicion: Option<Suspicion>,
     rtt_samples: Vec<Duration>,
+    coordinate: Option<Coordinate>,
 }
 
 impl MemberInfo {
-    fn new(member: Member) -> Self {
+    fn new(member: Member, now: Instant) -> Self {
         Self {
             member,
-            last_seen: Instant::now(),
-            suspect_at: None,
+            last_seen: now,
+            suspicion: None,
             rtt_samples: Vec::new(),
+            coordinate: None,
         }
     }
 
@@ -51,6 +37,14 @@ impl MemberInfo {
         }
     }
 
+    // what we gossip about the member, suspicions name their accuser
+    fn update(&self) -> MemberUpdate {
+        MemberUpdate {
+            accuser: self.suspicion.as_ref().map(|s| s.accuser().clone()),
+            ..MemberUpdate::from(&self.member)
+        }
+    }
+
     fn avg_rtt(&self) -> Option<Duration> {
         if self.rtt_samples.is_empty() {
             return None;
@@ -60,17 +54,38 @@ impl MemberInfo {
     }
 }
 
+fn state_rank(state: MemberState) -> u8 {
+    match state {
+        MemberState::Alive => 0,
+        MemberState::Suspect => 1,
+        MemberState::Dead => 2,
+    }
+}
+
 pub struct MemberList {
     local_member: Member,
     members: HashMap<MemberId, MemberInfo>,
     order: Vec<MemberId>,
     index: HashMap<MemberId, u64>,
     suspect_timeout: Duration,
+    suspicion_max_timeout_mult: u32,
+    suspicion_confirmations: u32,
+    // the incarnation we last refuted a suspicion at. unlike the incarnation
+    // itself, tag changes don't move it
+    term: u64,
     cursor: usize,
+    vivaldi: Vivaldi,
+    rng: StdRng,
 }
 
 impl MemberList {
-    pub fn new(local_member: Member, suspect_timeout: Duration) -> Self {
+    pub fn new(
+        local_member: Member,
+        suspect_timeout: Duration,
+        suspicion_max_timeout_mult: u32,
+        suspicion_confirmations: u32,
+        now: Instant,
+    ) -> Self {
         let mut members = HashMap::new();
         let mut order = Vec::new();
         let mut index = HashMap::new();
@@ -78,7 +93,7 @@ impl MemberList {
         // add ourselves to the member list
         members.insert(
             local_member.id.clone(),
-            MemberInfo::new(local_member.clone()),
+            MemberInfo::new(local_member.clone(), now),
         );
         order.push(local_member.id.clone());
         index.insert(local_member.id.clone(), 0);
@@ -89,12 +104,62 @@ impl MemberList {
             order,
             index,
             suspect_timeout,
+            suspicion_max_timeout_mult,
+            suspicion_confirmations,
+            term: 0,
             cursor: 0,
+            vivaldi: Vivaldi::default(),
+            rng: StdRng::from_rng(&mut rand::rng()),
         }
     }
 
-    pub fn upsert_member(&mut self, member: Member) {
+    // makes the probe order (and the rest of our random choices) repeat
+    // between runs, for simulations
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+        self.vivaldi.seed_rng(seed);
+    }
+
+    pub fn upsert_member(&mut self, member: Member, now: Instant) {
+        self.merge_member(member, None, None, now);
+    }
+
+    // same as upsert_member, but for state we heard second hand from `from`.
+    // its accuser confirms a suspicion we already hold, if it is a new one
+    pub fn apply_member_update(&mut self, update: MemberUpdate, from: &MemberId, now: Instant) {
+        let accuser = update.accuser.clone().unwrap_or_else(|| from.clone());
+        self.merge_member(update.into(), Some(from), Some(accuser), now);
+    }
+
+    fn new_suspicion(&self, accuser: MemberId, now: Instant) -> Suspicion {
+        let alive = self
+            .members
+            .values()
+            .filter(|info| info.member.state != MemberState::Dead)
+            .count() as u32;
+        // confirmations can only come from members other than us and the accused
+        let expected = self.suspicion_confirmations.min(alive.saturating_sub(2));
+
+        Suspicion::new(
+            accuser,
+            expected,
+            self.suspect_timeout,
+            self.suspect_timeout * self.suspicion_max_timeout_mult,
+            now,
+        )
+    }
+
+    fn merge_member(
+        &mut self,
+        mut member: Member,
+        from: Option<&MemberId>,
+        accuser: Option<MemberId>,
+        now: Instant,
+    ) {
         let member_id = member.id.clone();
+        let accuser = accuser.unwrap_or_else(|| member_id.clone());
+        let suspicion = (member.state == MemberState::Suspect)
+            .then(|| self.new_suspicion(accuser.clone(), now));
 
         if let Some(existing) = self.members.get_mut(&member_id) {
             if member.incarnation > existing.member.incarnation {
@@ -102,28 +167,75 @@ impl MemberList {
                     "Updating member {} from incarnation {} to {}",
                     member_id.0, existing.member.incarnation, member.incarnation
                 );
+                if member.state == MemberState::Suspect {
+                    warn!(
+                        "Member {} is now SUSPECT (reported by {})",
+                        member_id.0, accuser.0
+                    );
+                }
+                // direct contact (Ping/Ack headers) doesn't carry tags, they
+                // arrive with the member's own update
+                if from.is_none() {
+                    member.tags = std::mem::take(&mut existing.member.tags);
+                }
+                existing.suspicion = suspicion;
                 existing.member = member;
-                existing.last_seen = Instant::now();
-                existing.suspect_at = None;
+                existing.last_seen = now;
             } else if member.incarnation == existing.member.incarnation {
-                existing.last_seen = Instant::now();
+                existing.last_seen = now;
 
-                if member.state != existing.member.state {
+                // tags only change together with the incarnation, so any update
+                // for this incarnation carries the authoritative set
+                if from.is_some() && member.tags != existing.member.tags {
+                    debug!("Updating tags of {} to {:?}", member_id.0, member.tags);
+                    existing.member.tags = std::mem::take(&mut member.tags);
+                }
+
+                // at the same incarnation only Alive -> Suspect -> Dead is allowed,
+                // going back requires the member to refute with a higher incarnation
+                if state_rank(member.state) > state_rank(existing.member.state) {
+                    if member.state == MemberState::Suspect {
+                        warn!(
+                            "Member {} is now SUSPECT (reported by {})",
+                            member_id.0, accuser.0
+                        );
+                        existing.suspicion = suspicion;
+                    } else if member.state == MemberState::Dead {
+                        warn!(
+                            "Member {} is now DEAD (reported by {})",
+                            member_id.0, accuser.0
+                        );
+                        existing.suspicion = None;
+                    }
                     existing.member.state = member.state;
+                } else if member.state == MemberState::Suspect
+                    && existing.member.state == MemberState::Suspect
+                    && from.is_some()
+                    && let Some(suspicion) = existing.suspicion.as_mut()
+                    && suspicion.confirm(&accuser)
+                {
+                    debug!(
+                        "Suspicion of {} confirmed by {} ({} confirmations, timeout {:?})",
+                        member_id.0,
+                        accuser.0,
+                        suspicion.confirmations(),
+                        suspicion.timeout()
+                    );
                 }
             }
         } else {
             // new member
             info!("Discovered new member: {} at {}", member_id.0, member.addr);
 
-            self.members
-                .insert(member_id.clone(), MemberInfo::new(member));
+            let mut info = MemberInfo::new(member, now);
+            info.suspicion = suspicion;
+            self.members.insert(member_id.clone(), info);
             // TODO
             self.order.push(member_id.clone());
             self.index
                 .insert(member_id, (self.order.len() - 1).try_into().unwrap());
 
-            shuffle(&mut self.order);
+            self.order.shuffle(&mut self.rng);
             for (index, mid) in self.order.iter().enumerate() {
                 self.index.insert(mid.clone(), index.try_into().unwrap());
             }
@@ -132,67 +244,83 @@ impl MemberList {
         }
     }
 
-    pub fn mark_alive(&mut self, member_id: &MemberId) {
+    pub fn mark_alive(&mut self, member_id: &MemberId, now: Instant) {
         if let Some(info) = self.members.get_mut(member_id) {
             if info.member.state != MemberState::Alive {
                 info!("Member {} is now ALIVE", member_id.0);
                 info.member.state = MemberState::Alive;
             }
-            info.last_seen = Instant::now();
-            info.suspect_at = None;
+            info.last_seen = now;
+            info.suspicion = None;
         }
     }
 
-    pub fn mark_suspect(&mut self, member_id: &MemberId) {
-        if let Some(info) = self.members.get_mut(member_id) {
-            if info.member.state == MemberState::Alive {
-                warn!("Member {} is now SUSPECT", member_id.0);
+    pub fn mark_suspect(&mut self, member_id: &MemberId, now: Instant) {
+        let local_id = self.local_member.id.clone();
+        let suspicion = self.new_suspicion(local_id.clone(), now);
+        let Some(info) = self.members.get_mut(member_id) else {
+            return;
+        };
+        match info.member.state {
+            MemberState::Alive => {
+                warn!(
+                    "Member {} is now SUSPECT (timeout {:?})",
+                    member_id.0,
+                    suspicion.timeout()
+                );
                 info.member.state = MemberState::Suspect;
-                info.suspect_at = Some(Instant::now());
+                info.suspicion = Some(suspicion);
+            }
+            // we heard about it first, our own failed probe is a confirmation
+            MemberState::Suspect => {
+                if let Some(suspicion) = info.suspicion.as_mut()
+                    && suspicion.accuse(&local_id)
+                {
+                    debug!(
+                        "Suspicion of {} confirmed by us ({} confirmations, timeout {:?})",
+                        member_id.0,
+                        suspicion.confirmations(),
+                        suspicion.timeout()
+                    );
+                }
             }
+            MemberState::Dead => {}
         }
     }
 
     pub fn mark_dead(&mut self, member_id: &MemberId) {
-        if let Some(info) = self.members.get_mut(member_id) {
-            if info.member.state != MemberState::Dead {
-                warn!("Member {} is now DEAD", member_id.0);
-                info.member.state = MemberState::Dead;
-            }
+        if let Some(info) = self.members.get_mut(member_id)
+            && info.member.state != MemberState::Dead
+        {
+            warn!("Member {} is now DEAD", member_id.0);
+            info.member.state = MemberState::Dead;
+            info.suspicion = None;
         }
     }
 
     pub fn get_alive_members(&self) -> Vec<Member> {
-        self.order.iter()
-            .map(|id| self.members.get(id))
-            .filter(|item| item.is_some())
-            .map(|i| i.unwrap())
+        self.order
+            .iter()
+            .filter_map(|id| self.members.get(id))
             .filter(|info| {
                 info.member.state == MemberState::Alive && info.member.id != self.local_member.id
             })
             .map(|info| info.member.clone())
             .collect()
-
-        // self.members
-        //     .values()
-        //     .filter(|info| {
-        //         info.member.state == MemberState::Alive && info.member.id != self.local_member.id
-        //     })
-        //     .map(|info| info.member.clone())
-        //     .collect()
     }
 
     pub fn get_all_members(&self) -> Vec<Member> {
-        self.order.iter()
-            .map(|id| self.members.get(id))
-            .filter(|item| item.is_some())
-            .map(|i| i.unwrap())
+        self.order
+            .iter()
+            .filter_map(|id| self.members.get(id))
             .filter(|m| m.member.id != self.local_member.id)
             .map(|m| m.member.clone())
             .collect()
     }
 
-    pub fn get_random_alive_member(&mut self) -> Option<Member> {
+    // suspects are probed too, that's how they get to hear about (and refute)
+    // the suspicion before it times out
+    pub fn get_next_probe_target(&mut self) -> Option<Member> {
         if self.order.is_empty() {
             return None;
         }
@@ -201,35 +329,35 @@ impl MemberList {
             let member_id = &self.order[self.cursor % self.order.len()];
             self.cursor += 1;
 
-            if let Some(info) = self.members.get(member_id) {
-                if info.member.state == MemberState::Alive
-                    && info.member.id != self.local_member.id
-                {
-                    return Some(info.member.clone());
-                }
+            if let Some(info) = self.members.get(member_id)
+                && info.member.state != MemberState::Dead
+                && info.member.id != self.local_member.id
+            {
+                return Some(info.member.clone());
             }
         }
 
         None
     }
 
-    pub fn check_suspect_timeouts(&mut self) {
-        let now = Instant::now();
-        let suspect_timeout = self.suspect_timeout;
+    pub fn get_random_dead_member(&mut self) -> Option<Member> {
+        let dead: Vec<&MemberInfo> = self
+            .order
+            .iter()
+            .filter_map(|id| self.members.get(id))
+            .filter(|info| info.member.state == MemberState::Dead)
+            .collect();
+
+        dead.choose(&mut self.rng).map(|info| info.member.clone())
+    }
 
+    pub fn check_suspect_timeouts(&mut self, now: Instant) {
         let to_mark_dead: Vec<MemberId> = self
             .members
             .iter()
-            .filter_map(|(id, info)| {
-                if info.member.state == MemberState::Suspect {
-                    if let Some(suspect_at) = info.suspect_at {
-                        if now.duration_since(suspect_at) > suspect_timeout {
-                            return Some(id.clone());
-                        }
-                    }
-                }
-                None
-            })
+            .filter(|(_, info)| info.member.state == MemberState::Suspect)
+            .filter(|(_, info)| info.suspicion.as_ref().is_some_and(|s| s.is_expired(now)))
+            .map(|(id, _)| id.clone())
             .collect();
 
         for member_id in to_mark_dead {
@@ -237,9 +365,7 @@ impl MemberList {
         }
     }
 
-    pub fn prune_dead_members(&mut self, dead_timeout: Duration) {
-        let now = Instant::now();
-
+    pub fn prune_dead_members(&mut self, dead_timeout: Duration, now: Instant) {
         let mut pruned_ids = Vec::new();
 
         self.members.retain(|id, info| {
@@ -255,8 +381,11 @@ impl MemberList {
         });
 
         self.order.retain(|id| !pruned_ids.contains(id));
+        for id in &pruned_ids {
+            self.vivaldi.forget(id);
+        }
 
-        shuffle(&mut self.order);
+        self.order.shuffle(&mut self.rng);
         self.index.clear();
         for (index, mid) in self.order.iter().enumerate() {
             self.index.insert(mid.clone(), index.try_into().unwrap());
@@ -266,33 +395,108 @@ impl MemberList {
     }
 
     pub fn get_member_updates(&self, max_count: usize) -> Vec<MemberUpdate> {
-        let mut updates: Vec<_> = self.members
-                .values()
-                .map(|m| (m, m.last_seen))
-                .collect();
+        let mut updates: Vec<_> = self.members.values().map(|m| (m, m.last_seen)).collect();
+
+        // ties broken by id, not by whatever order the map iterates in
+        updates.sort_by(|a, b| {
+            b.1.cmp(&a.1)
+                .then_with(|| a.0.member.id.cmp(&b.0.member.id))
+        });
 
-        updates.sort_by(|a, b| b.1.cmp(&a.1));
+        updates
+            .into_iter()
+            .take(max_count)
+            .map(|(info, _)| info.update())
+            .collect()
+    }
 
-        updates.into_iter().take(max_count).map(|(info, _)| MemberUpdate {
-            member_id: info.member.id.clone(),
-            addr: info.member.addr,
-            state: info.member.state,
-            incarnation: info.member.incarnation,
-        }).collect()
+    pub fn get_member_update(&self, member_id: &MemberId) -> Option<MemberUpdate> {
+        self.members.get(member_id).map(MemberInfo::update)
     }
 
     pub fn local_member(&self) -> &Member {
         &self.local_member
     }
 
-    pub fn record_rtt(&mut self, member_id: &MemberId, rtt: Duration) {
+    pub fn get_member(&self, member_id: &MemberId) -> Option<Member> {
+        self.members.get(member_id).map(|info| info.member.clone())
+    }
+
+    pub fn get_member_state(&self, member_id: &MemberId) -> Option<MemberState> {
+        self.members.get(member_id).map(|info| info.member.state)
+    }
+
+    pub fn get_member_by_addr(&self, addr: SocketAddr) -> Option<Member> {
+        self.members
+            .values()
+            .find(|info| info.member.addr == addr)
+            .map(|info| info.member.clone())
+    }
+
+    // alive members (including ourselves) whose tag `key` is set to `value`
+    pub fn get_members_with_tag(&self, key: &str, value: &str) -> Vec<Member> {
+        self.order
+            .iter()
+            .filter_map(|id| self.members.get(id))
+            .filter(|info| info.member.state == MemberState::Alive)
+            .filter(|info| info.member.tag(key) == Some(value))
+            .map(|info| info.member.clone())
+            .collect()
+    }
+
+    // changing tags bumps our incarnation so the new set overrides the old
+    // one everywhere
+    pub fn set_local_tags(&mut self, tags: Tags) {
+        if tags == self.local_member.tags {
+            return;
+        }
+
+        info!("Updating local tags to {:?}", tags);
+        self.local_member.tags = tags.clone();
+        if let Some(info) = self.members.get_mut(&self.local_member.id) {
+            info.member.tags = tags;
+        }
+        self.increment_incarnation();
+    }
+
+    // an ack to our ping: the RTT sample also moves our own coordinate. a
+    // coordinate that fails is_valid is dropped, the member keeps its last
+    // good one
+    pub fn record_rtt(&mut self, member_id: &MemberId, rtt: Duration, coordinate: Coordinate) {
         if let Some(info) = self.members.get_mut(member_id) {
             info.record_rtt(rtt);
+            if coordinate.is_valid() {
+                self.vivaldi.update(member_id, &coordinate, rtt);
+                info.coordinate = Some(coordinate);
+            }
         }
     }
 
+    pub fn set_coordinate(&mut self, member_id: &MemberId, coordinate: Coordinate) {
+        if let Some(info) = self.members.get_mut(member_id)
+            && coordinate.is_valid()
+        {
+            info.coordinate = Some(coordinate);
+        }
+    }
+
+    pub fn local_coordinate(&self) -> Coordinate {
+        self.vivaldi.coordinate().clone()
+    }
+
+    pub fn get_coordinate(&self, member_id: &MemberId) -> Option<Coordinate> {
+        self.members.get(member_id)?.coordinate.clone()
+    }
+
+    // RTT from us to the member, as far as the coordinates tell
+    pub fn estimate_rtt(&self, member_id: &MemberId) -> Option<Duration> {
+        let coordinate = self.members.get(member_id)?.coordinate.as_ref()?;
+        Some(self.vivaldi.coordinate().distance_to(coordinate))
+    }
+
     pub fn get_adaptive_timeout(&self, base_timeout: Duration) -> Duration {
-        let rtts: Vec<Duration> = self.members
+        let rtts: Vec<Duration> = self
+            .members
             .values()
             .filter_map(|info| info.avg_rtt())
             .collect();
@@ -304,10 +508,19 @@ impl MemberList {
         let avg: Duration = rtts.iter().sum::<Duration>() / rtts.len() as u32;
         let timeout = avg * 3;
 
-        timeout.clamp(
-            Duration::from_millis(300),
-            Duration::from_secs(2)
-        )
+        timeout.clamp(Duration::from_millis(300), Duration::from_secs(2))
+    }
+
+    // jump past the incarnation we were accused at, so the refutation
+    // overrides the accusation everywhere
+    pub fn refute(&mut self, accused_incarnation: u64) {
+        self.local_member.incarnation = self.local_member.incarnation.max(accused_incarnation);
+        self.increment_incarnation();
+        self.term = self.local_member.incarnation;
+    }
+
+    pub fn term(&self) -> u64 {
+        self.term
     }
 
     pub fn increment_incarnation(&mut self) {
@@ -321,5 +534,3 @@ impl MemberList {
         );
     }
 }
-
-pub type SharedMemberList = Arc<RwLock<MemberList>>;
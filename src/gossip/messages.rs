@@ -1,8 +1,29 @@
+use super::coordinate::Coordinate;
+use super::hlc::HybridTimestamp;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::net::SocketAddr;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+// arbitrary key/value metadata about a member (zone, rack, version, ...)
+pub type Tags = BTreeMap<String, String>;
+
+// where clients reach the member's proxy, which is not its gossip address
+pub const PROXY_ADDR_TAG: &str = "proxy_addr";
+
+// locality of the member, e.g. an availability zone
+pub const ZONE_TAG: &str = "zone";
+
+// comma separated wire features a member understands, so newer nodes only
+// compress or stream to peers that can handle it
+pub const FEATURES_TAG: &str = "gossip_features";
+pub const FEATURE_LZ4: &str = "lz4";
+pub const FEATURE_STREAM: &str = "stream";
+pub const FEATURE_COMPOUND: &str = "compound";
+pub const FEATURE_EVENTS: &str = "events";
+pub const FEATURE_KV: &str = "kv";
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct MemberId(pub String);
 
 impl MemberId {
@@ -28,6 +49,26 @@ pub struct Member {
     pub addr: SocketAddr,
     pub state: MemberState,
     pub incarnation: u64,
+    pub tags: Tags,
+}
+
+impl Member {
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags.get(key).map(String::as_str)
+    }
+
+    pub fn proxy_addr(&self) -> Option<SocketAddr> {
+        self.tag(PROXY_ADDR_TAG)?.parse().ok()
+    }
+
+    pub fn zone(&self) -> Option<&str> {
+        self.tag(ZONE_TAG)
+    }
+
+    pub fn supports(&self, feature: &str) -> bool {
+        self.tag(FEATURES_TAG)
+            .is_some_and(|features| features.split(',').any(|f| f.trim() == feature))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -37,20 +78,75 @@ pub struct BackendHealthInfo {
     pub timestamp: u64, // When this observation was made
 }
 
+// an application event broadcast to every member, see
+// `GossipLayer::broadcast_event`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserEvent {
+    // random, together with ltime it identifies the event cluster-wide
+    pub id: u64,
+    pub ltime: u64,
+    pub origin: MemberId,
+    pub name: String,
+    pub payload: Vec<u8>,
+}
+
+// one key of the replicated key/value store. the highest (version, writer)
+// wins, a None value is a deletion
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KvEntry {
+    pub key: String,
+    pub value: Option<Vec<u8>>,
+    pub version: HybridTimestamp,
+    pub writer: MemberId,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MemberUpdate {
     pub member_id: MemberId,
     pub addr: SocketAddr,
     pub state: MemberState,
     pub incarnation: u64,
+    pub tags: Tags,
+    // who suspects the member, for Suspect updates. only distinct accusers
+    // confirm a suspicion, not every member that passes it on
+    pub accuser: Option<MemberId>,
+}
+
+impl From<&Member> for MemberUpdate {
+    fn from(member: &Member) -> Self {
+        Self {
+            member_id: member.id.clone(),
+            addr: member.addr,
+            state: member.state,
+            incarnation: member.incarnation,
+            tags: member.tags.clone(),
+            accuser: None,
+        }
+    }
+}
+
+impl From<MemberUpdate> for Member {
+    fn from(update: MemberUpdate) -> Self {
+        Self {
+            id: update.member_id,
+            addr: update.addr,
+            state: update.state,
+            incarnation: update.incarnation,
+            tags: update.tags,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BackendUpdate {
+    // the backend pool it was checked in, the same address can be in several
+    pub pool: String,
     pub backend_addr: SocketAddr,
     pub is_healthy: bool,
+    // member that observed this status and the time it did so. together they
+    // order updates, the highest (version, from_member) wins everywhere
     pub from_member: MemberId,
-    pub timestamp: u64,
+    pub version: HybridTimestamp,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -59,6 +155,7 @@ pub enum GossipMessage {
         from: MemberId,
         from_addr: SocketAddr,
         incarnation: u64,
+        coordinate: Coordinate,
         member_updates: Vec<MemberUpdate>,
         backend_updates: Vec<BackendUpdate>,
     },
@@ -67,6 +164,7 @@ pub enum GossipMessage {
         from: MemberId,
         from_addr: SocketAddr,
         incarnation: u64,
+        coordinate: Coordinate,
         member_updates: Vec<MemberUpdate>,
         backend_updates: Vec<BackendUpdate>,
     },
@@ -83,102 +181,164 @@ pub enum GossipMessage {
         target_id: MemberId,
         target_responded: bool,
     },
+
+    // several messages for the same peer sharing one packet
+    Compound(Vec<GossipMessage>),
+
+    UserEvents(Vec<UserEvent>),
+
+    KvUpdates(Vec<KvEntry>),
+
+    // anti-entropy: a slice of the sender's whole store. the first slice of
+    // a push asks for the receiver's store in return
+    KvPushPull {
+        from_addr: SocketAddr,
+        entries: Vec<KvEntry>,
+        reply: bool,
+    },
 }
 
-const MAX_UDP_PACKET_SIZE: usize = 1400;
+pub const MAX_UDP_PACKET_SIZE: usize = 1400;
+pub const MAX_CLUSTER_NAME_LEN: usize = 64;
+// what's left of a packet once the cluster name and its framing are in
+const MAX_MESSAGE_SIZE: usize = MAX_UDP_PACKET_SIZE - MAX_CLUSTER_NAME_LEN - 32;
+// upper bound for messages sent over TCP, and for what we decompress
+pub const MAX_STREAM_MESSAGE_SIZE: usize = 8 * 1024 * 1024;
+// below this compression rarely pays for itself
+const COMPRESSION_THRESHOLD: usize = 256;
 
 impl GossipMessage {
-    pub fn to_bytes(&self) -> Result<Vec<u8>> {
-        let bytes = bincode::serialize(self)?;
-        if bytes.len() > MAX_UDP_PACKET_SIZE {
+    // every message on the wire is prefixed with the sender's cluster name, so
+    // nodes from another cluster can't get merged in by a misconfigured seed.
+    // returns the packet and whether its body ended up compressed
+    pub fn encode(&self, cluster: &str, compress: bool) -> Result<(Vec<u8>, bool)> {
+        let mut body = bincode::serialize(self)?;
+        let mut compressed = false;
+
+        if compress && body.len() > COMPRESSION_THRESHOLD {
+            let smaller = lz4_flex::compress_prepend_size(&body);
+            if smaller.len() < body.len() {
+                body = smaller;
+                compressed = true;
+            }
+        }
+
+        let bytes = bincode::serialize(&(cluster, compressed, body))?;
+        if bytes.len() > MAX_STREAM_MESSAGE_SIZE {
             return Err(anyhow::anyhow!(
-                "Message size {} exceeds max UDP packet size {}",
+                "Message size {} exceeds max message size {}",
                 bytes.len(),
-                MAX_UDP_PACKET_SIZE
+                MAX_STREAM_MESSAGE_SIZE
             ));
         }
-        Ok(bytes)
+        Ok((bytes, compressed))
     }
 
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
-        let message = bincode::deserialize(bytes)?;
-        Ok(message)
+    pub fn decode(bytes: &[u8]) -> Result<(String, Self)> {
+        let (cluster, compressed, body): (String, bool, Vec<u8>) = bincode::deserialize(bytes)?;
+        if !compressed {
+            return Ok((cluster, bincode::deserialize(&body)?));
+        }
+
+        // the size prefix is the sender's word, don't allocate whatever it claims
+        let (size, _) = lz4_flex::block::uncompressed_size(&body)?;
+        if size > MAX_STREAM_MESSAGE_SIZE {
+            return Err(anyhow::anyhow!(
+                "Decompressed size {} exceeds max message size {}",
+                size,
+                MAX_STREAM_MESSAGE_SIZE
+            ));
+        }
+        let body = lz4_flex::decompress_size_prepended(&body)?;
+        Ok((cluster, bincode::deserialize(&body)?))
     }
 
     pub fn estimated_size(&self) -> usize {
         bincode::serialized_size(self).unwrap_or(0) as usize
     }
 
-    pub fn trim_to_fit(self) -> Self {
+    // packs messages for a single peer into as few packet-sized compounds as
+    // possible. messages that don't fit in a packet on their own are left as
+    // they are
+    pub fn pack(messages: Vec<GossipMessage>) -> Vec<GossipMessage> {
+        let mut packed = Vec::new();
+        let mut current: Vec<GossipMessage> = Vec::new();
+        let mut current_size = 0;
+
+        for message in messages {
+            let size = message.estimated_size();
+            if size > MAX_MESSAGE_SIZE {
+                packed.push(message);
+                continue;
+            }
+            // + the variant tag and length of the compound itself
+            if !current.is_empty() && current_size + size + 12 > MAX_MESSAGE_SIZE {
+                packed.push(Self::compound(std::mem::take(&mut current)));
+                current_size = 0;
+            }
+            current_size += size;
+            current.push(message);
+        }
+        if !current.is_empty() {
+            packed.push(Self::compound(current));
+        }
+
+        packed
+    }
+
+    fn compound(mut messages: Vec<GossipMessage>) -> Self {
+        if messages.len() == 1 {
+            messages.pop().unwrap()
+        } else {
+            GossipMessage::Compound(messages)
+        }
+    }
+
+    fn updates_mut(&mut self) -> Option<(&mut Vec<MemberUpdate>, &mut Vec<BackendUpdate>)> {
         match self {
             GossipMessage::Ping {
-                from,
-                from_addr,
-                incarnation,
-                mut member_updates,
-                mut backend_updates,
-            } => {
-                let mut msg = GossipMessage::Ping {
-                    from: from.clone(),
-                    from_addr,
-                    incarnation,
-                    member_updates: vec![],
-                    backend_updates: vec![],
-                };
-
-                while msg.estimated_size() < MAX_UDP_PACKET_SIZE && !member_updates.is_empty() {
-                    if let Some(update) = member_updates.pop() {
-                        if let GossipMessage::Ping { member_updates: ref mut updates, .. } = msg {
-                            updates.push(update);
-                        }
-                    }
-                }
-
-                while msg.estimated_size() < MAX_UDP_PACKET_SIZE && !backend_updates.is_empty() {
-                    if let Some(update) = backend_updates.pop() {
-                        if let GossipMessage::Ping { backend_updates: ref mut updates, .. } = msg {
-                            updates.push(update);
-                        }
-                    }
-                }
-
-                msg
+                member_updates,
+                backend_updates,
+                ..
             }
-            GossipMessage::Ack {
-                from,
-                from_addr,
-                incarnation,
-                mut member_updates,
-                mut backend_updates,
-            } => {
-                let mut msg = GossipMessage::Ack {
-                    from: from.clone(),
-                    from_addr,
-                    incarnation,
-                    member_updates: vec![],
-                    backend_updates: vec![],
-                };
-
-                while msg.estimated_size() < MAX_UDP_PACKET_SIZE && !member_updates.is_empty() {
-                    if let Some(update) = member_updates.pop() {
-                        if let GossipMessage::Ack { member_updates: ref mut updates, .. } = msg {
-                            updates.push(update);
-                        }
-                    }
-                }
-
-                while msg.estimated_size() < MAX_UDP_PACKET_SIZE && !backend_updates.is_empty() {
-                    if let Some(update) = backend_updates.pop() {
-                        if let GossipMessage::Ack { backend_updates: ref mut updates, .. } = msg {
-                            updates.push(update);
-                        }
-                    }
-                }
-
-                msg
+            | GossipMessage::Ack {
+                member_updates,
+                backend_updates,
+                ..
+            } => Some((member_updates, backend_updates)),
+            _ => None,
+        }
+    }
+
+    // drops piggybacked updates until the message fits in a packet. updates
+    // at the back of each list are kept first
+    pub fn trim_to_fit(mut self) -> Self {
+        if self.estimated_size() <= MAX_MESSAGE_SIZE {
+            return self;
+        }
+
+        let Some((member_updates, backend_updates)) = self.updates_mut() else {
+            return self;
+        };
+        let mut member_updates = std::mem::take(member_updates);
+        let mut backend_updates = std::mem::take(backend_updates);
+
+        while let Some(update) = member_updates.pop() {
+            self.updates_mut().unwrap().0.push(update);
+            if self.estimated_size() > MAX_MESSAGE_SIZE {
+                self.updates_mut().unwrap().0.pop();
+                break;
             }
-            other => other,
         }
+
+        while let Some(update) = backend_updates.pop() {
+            self.updates_mut().unwrap().1.push(update);
+            if self.estimated_size() > MAX_MESSAGE_SIZE {
+                self.updates_mut().unwrap().1.pop();
+                break;
+            }
+        }
+
+        self
     }
 }
-
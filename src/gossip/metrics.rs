@@ -0,0 +1,26 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// counters about the gossip layer itself, cheap enough to bump on every packet
+#[derive(Debug, Default)]
+pub struct GossipMetrics {
+    pub foreign_cluster_messages: AtomicU64,
+    // packets carrying several messages for the same peer
+    pub compound_messages: AtomicU64,
+    // messages sent with an lz4 compressed body
+    pub compressed_messages: AtomicU64,
+    // messages too large for UDP that went over TCP instead
+    pub stream_messages: AtomicU64,
+    // messages too large for UDP that lost piggybacked updates because the
+    // peer can't take a stream
+    pub trimmed_messages: AtomicU64,
+    // user events superseded by a newer one with the same name, or dropped
+    // from a full queue before they finished spreading
+    pub coalesced_events: AtomicU64,
+    pub dropped_events: AtomicU64,
+}
+
+impl GossipMetrics {
+    pub fn incr(counter: &AtomicU64) -> u64 {
+        counter.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
@@ -1,8 +1,38 @@
-
-mod messages;
-mod member_list;
+mod advertise;
+mod awareness;
+mod coordinate;
+mod events;
+mod hlc;
+mod kv;
 mod layer;
+mod machine;
+mod member_list;
+mod messages;
+mod metrics;
+mod seeds;
+mod simulation;
+mod snapshot;
 mod states;
+mod suspicion;
+mod transport;
 
+pub use advertise::advertise_addr;
+pub use awareness::Awareness;
+pub use coordinate::Coordinate;
+pub use events::LamportClock;
+pub use hlc::{HybridClock, HybridTimestamp};
+pub use kv::{
+    KvStore, LOAD_KEY_PREFIX, OVERRIDE_FIELD, RATE_LIMIT_KEY_PREFIX, STICKY_KEY_PREFIX,
+    WEIGHT_FIELD, backend_key, decode_load, encode_load, load_key, rate_limit_key,
+    rate_limit_prefix, sticky_key,
+};
+pub use layer::{GossipLayer, Outbox};
+pub use machine::{GossipMachine, Output};
+pub use member_list::MemberList;
 pub use messages::*;
-pub use layer::GossipLayer;
+pub use metrics::GossipMetrics;
+pub use seeds::SeedResolver;
+pub use simulation::{Simulation, node_addr};
+pub use snapshot::{Snapshot, SnapshotMember};
+pub use suspicion::Suspicion;
+pub use transport::{MemoryNetwork, MemoryTransport, NetworkConditions, Transport, UdpTransport};
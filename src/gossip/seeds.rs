@@ -0,0 +1,188 @@
+use crate::config::{GossipConfig, SeedSource};
+use anyhow::{Result, anyhow};
+use hickory_resolver::TokioResolver;
+use hickory_resolver::config::{ConnectionConfig, NameServerConfig, ResolverConfig};
+use hickory_resolver::net::runtime::TokioRuntimeProvider;
+use hickory_resolver::proto::rr::RData;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tokio::process::Command;
+use tracing::{debug, info, warn};
+
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Turns the static seed_nodes plus every configured seed source into a list of
+// addresses. Sources are looked up again on every call, so join attempts and
+// the reconnect loop always see the current set of peers.
+pub struct SeedResolver {
+    static_seeds: Vec<SocketAddr>,
+    sources: Vec<SeedSource>,
+    dns: Option<TokioResolver>,
+    files: Mutex<HashMap<PathBuf, (SystemTime, Vec<SocketAddr>)>>,
+}
+
+impl SeedResolver {
+    pub fn new(config: &GossipConfig) -> Result<Self> {
+        let needs_dns = config
+            .seed_sources
+            .iter()
+            .any(|source| matches!(source, SeedSource::Dns { .. } | SeedSource::Srv { .. }));
+
+        let dns = if !needs_dns {
+            None
+        } else if config.dns_servers.is_empty() {
+            Some(TokioResolver::builder_tokio()?.build()?)
+        } else {
+            let name_servers = config
+                .dns_servers
+                .iter()
+                .map(|addr| {
+                    let mut udp = ConnectionConfig::udp();
+                    udp.port = addr.port();
+                    let mut tcp = ConnectionConfig::tcp();
+                    tcp.port = addr.port();
+                    NameServerConfig::new(addr.ip(), true, vec![udp, tcp])
+                })
+                .collect();
+            let resolver_config = ResolverConfig::from_name_servers(name_servers);
+            Some(
+                TokioResolver::builder_with_config(
+                    resolver_config,
+                    TokioRuntimeProvider::default(),
+                )
+                .build()?,
+            )
+        };
+
+        Ok(Self {
+            static_seeds: config.seed_nodes.clone(),
+            sources: config.seed_sources.clone(),
+            dns,
+            files: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.static_seeds.is_empty() && self.sources.is_empty()
+    }
+
+    // a failing source is logged and skipped, the others still count
+    pub async fn resolve(&self) -> Vec<SocketAddr> {
+        let mut seeds = self.static_seeds.clone();
+
+        for source in &self.sources {
+            match self.resolve_source(source).await {
+                Ok(addrs) => {
+                    debug!("Seed source {:?} resolved to {:?}", source, addrs);
+                    seeds.extend(addrs);
+                }
+                Err(e) => warn!("Failed to resolve seed source {:?}: {}", source, e),
+            }
+        }
+
+        let mut unique = Vec::with_capacity(seeds.len());
+        for addr in seeds {
+            if !unique.contains(&addr) {
+                unique.push(addr);
+            }
+        }
+        unique
+    }
+
+    async fn resolve_source(&self, source: &SeedSource) -> Result<Vec<SocketAddr>> {
+        match source {
+            SeedSource::Dns { name, port } => {
+                let lookup = self.dns()?.lookup_ip(name.as_str()).await?;
+                Ok(lookup.iter().map(|ip| SocketAddr::new(ip, *port)).collect())
+            }
+            SeedSource::Srv { name } => {
+                let dns = self.dns()?;
+                let lookup = dns.srv_lookup(name.as_str()).await?;
+
+                // a target that doesn't resolve is skipped, the others count
+                let mut addrs = Vec::new();
+                for record in lookup.answers() {
+                    let RData::SRV(srv) = &record.data else {
+                        continue;
+                    };
+                    match dns.lookup_ip(srv.target.clone()).await {
+                        Ok(target) => {
+                            addrs.extend(target.iter().map(|ip| SocketAddr::new(ip, srv.port)))
+                        }
+                        Err(e) => warn!("Skipping SRV target {}: {}", srv.target, e),
+                    }
+                }
+                Ok(addrs)
+            }
+            SeedSource::File { path } => self.read_seed_file(path).await,
+            SeedSource::Command { command, args } => {
+                let output = tokio::time::timeout(
+                    COMMAND_TIMEOUT,
+                    Command::new(command).args(args).kill_on_drop(true).output(),
+                )
+                .await
+                .map_err(|_| anyhow!("`{}` timed out after {:?}", command, COMMAND_TIMEOUT))??;
+
+                if !output.status.success() {
+                    return Err(anyhow!("`{}` exited with {}", command, output.status));
+                }
+                Ok(parse_addrs(&String::from_utf8_lossy(&output.stdout)).await)
+            }
+        }
+    }
+
+    fn dns(&self) -> Result<&TokioResolver> {
+        self.dns
+            .as_ref()
+            .ok_or_else(|| anyhow!("DNS resolver not configured"))
+    }
+
+    // not watched, an edit is only noticed here on the next resolve, a join
+    // attempt or reconnect round. re-parsed only when the file's mtime changes
+    async fn read_seed_file(&self, path: &Path) -> Result<Vec<SocketAddr>> {
+        let modified = tokio::fs::metadata(path).await?.modified()?;
+
+        if let Some((cached_at, addrs)) = self.files.lock().unwrap().get(path)
+            && *cached_at == modified
+        {
+            return Ok(addrs.clone());
+        }
+
+        let content = tokio::fs::read_to_string(path).await?;
+        let addrs = parse_addrs(&content).await;
+        info!("Seed file {} changed: {:?}", path.display(), addrs);
+
+        self.files
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), (modified, addrs.clone()));
+        Ok(addrs)
+    }
+}
+
+// one `host:port` per line, blank lines and `#` comments are ignored. a
+// line that doesn't resolve is logged and skipped
+async fn parse_addrs(content: &str) -> Vec<SocketAddr> {
+    let mut addrs = Vec::new();
+
+    for line in content.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Ok(addr) = line.parse::<SocketAddr>() {
+            addrs.push(addr);
+            continue;
+        }
+        match tokio::net::lookup_host(line).await {
+            Ok(resolved) => addrs.extend(resolved),
+            Err(e) => warn!("Skipping seed {}: {}", line, e),
+        }
+    }
+
+    addrs
+}
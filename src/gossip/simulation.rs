@@ -0,0 +1,281 @@
+use super::hlc::HybridClock;
+use super::layer::GossipLayer;
+use super::messages::{MemberId, MemberState};
+use super::transport::{MemoryNetwork, NetworkConditions};
+use crate::backend::BackendPool;
+use crate::config::GossipConfig;
+use anyhow::Result;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+
+// how often properties are checked while the simulation runs
+const CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+struct Node {
+    id: MemberId,
+    addr: SocketAddr,
+    layer: GossipLayer,
+    tasks: Vec<JoinHandle<()>>,
+    crashed: bool,
+}
+
+// a cluster of gossip layers on a MemoryNetwork, to see how the protocol
+// copes with crashes, partitions, loss and slow nodes. meant to run on a
+// paused tokio clock (#[tokio::test(start_paused = true)]), where minutes of
+// gossip take a moment, and the same seed plays out the same way every time
+pub struct Simulation {
+    network: MemoryNetwork,
+    interval: Duration,
+    nodes: Vec<Node>,
+}
+
+pub fn node_addr(index: usize) -> SocketAddr {
+    SocketAddr::from(([10, 0, (index / 250) as u8, (index % 250) as u8 + 1], 7946))
+}
+
+impl Simulation {
+    // starts `size` nodes with `config`, all seeded with node 0, and waits
+    // for the joins. bind_addr and seed_nodes are set per node
+    pub async fn new(seed: u64, size: usize, config: &GossipConfig) -> Result<Self> {
+        let network = MemoryNetwork::new(seed);
+        let mut nodes = Vec::with_capacity(size);
+
+        for index in 0..size {
+            let addr = node_addr(index);
+            let id = MemberId::new(format!("node-{index}"));
+            let mut config = config.clone();
+            config.bind_addr = addr;
+            config.seed_nodes = vec![node_addr(0)];
+
+            let backend_pool = Arc::new(RwLock::new(BackendPool::new(
+                vec![],
+                id.clone(),
+                Arc::new(HybridClock::new()),
+            )));
+            let transport = Arc::new(network.bind(addr)?);
+            let layer =
+                GossipLayer::with_transport(id.clone(), &config, backend_pool, transport).await?;
+            // each node gets its own stream of random choices
+            layer.seed_rng(seed.wrapping_mul(1000).wrapping_add(index as u64));
+
+            nodes.push(Node {
+                id,
+                addr,
+                layer,
+                tasks: Vec::new(),
+                crashed: false,
+            });
+        }
+
+        for node in &mut nodes {
+            for task in 0..2 {
+                let layer = node.layer.clone();
+                node.tasks.push(tokio::spawn(async move {
+                    match task {
+                        0 => layer.run().await,
+                        _ => layer.start_reconnect_loop().await,
+                    }
+                }));
+            }
+        }
+
+        futures::future::try_join_all(nodes[1..].iter().map(|node| node.layer.join_cluster()))
+            .await?;
+
+        Ok(Self {
+            network,
+            interval: Duration::from_millis(config.gossip_interval_ms),
+            nodes,
+        })
+    }
+
+    pub fn network(&self) -> &MemoryNetwork {
+        &self.network
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn id(&self, index: usize) -> &MemberId {
+        &self.nodes[index].id
+    }
+
+    pub fn layer(&self, index: usize) -> &GossipLayer {
+        &self.nodes[index].layer
+    }
+
+    // indexes of the nodes that haven't crashed
+    pub fn live(&self) -> Vec<usize> {
+        (0..self.nodes.len())
+            .filter(|&index| !self.nodes[index].crashed)
+            .collect()
+    }
+
+    pub fn set_conditions(&self, conditions: NetworkConditions) {
+        self.network.set_conditions(conditions);
+    }
+
+    pub fn partition(&self, side_a: &[usize], side_b: &[usize]) {
+        let addrs = |side: &[usize]| -> Vec<SocketAddr> {
+            side.iter().map(|&index| self.nodes[index].addr).collect()
+        };
+        self.network.partition(&addrs(side_a), &addrs(side_b));
+    }
+
+    pub fn heal(&self) {
+        self.network.heal();
+    }
+
+    pub fn slow_down(&self, index: usize, delay: Duration) {
+        self.network.set_node_delay(self.nodes[index].addr, delay);
+    }
+
+    // the node stops dead: its loops are gone and nothing it had in flight
+    // gets out anymore
+    pub fn crash(&mut self, index: usize) {
+        let node = &mut self.nodes[index];
+        for task in node.tasks.drain(..) {
+            task.abort();
+        }
+        self.network.disconnect(node.addr);
+        node.crashed = true;
+    }
+
+    pub async fn run_for(&self, duration: Duration) {
+        tokio::time::sleep(duration).await;
+    }
+
+    pub async fn run_rounds(&self, rounds: u32) {
+        self.run_for(self.interval * rounds).await;
+    }
+
+    // what `observer` thinks of `member`, None if it doesn't know it
+    pub fn state(&self, observer: usize, member: usize) -> Option<MemberState> {
+        let id = &self.nodes[member].id;
+        self.nodes[observer]
+            .layer
+            .with_members(|members| members.get_member_state(id))
+    }
+
+    // what `observer` thinks of every node, itself included
+    pub fn view(&self, observer: usize) -> Vec<Option<MemberState>> {
+        self.nodes[observer].layer.with_members(|members| {
+            self.nodes
+                .iter()
+                .map(|node| members.get_member_state(&node.id))
+                .collect()
+        })
+    }
+
+    // every live node's view, for comparing runs
+    pub fn views(&self) -> Vec<Vec<Option<MemberState>>> {
+        let mut views = Vec::new();
+        for observer in self.live() {
+            views.push(self.view(observer));
+        }
+        views
+    }
+
+    // live nodes that consider another live node dead
+    pub fn false_deaths(&self) -> Vec<(MemberId, MemberId)> {
+        let mut deaths = Vec::new();
+        for observer in self.live() {
+            let view = self.view(observer);
+            for member in self.live() {
+                if view[member] == Some(MemberState::Dead) {
+                    deaths.push((
+                        self.nodes[observer].id.clone(),
+                        self.nodes[member].id.clone(),
+                    ));
+                }
+            }
+        }
+        deaths
+    }
+
+    // true once every live node sees every other live node alive and every
+    // crashed node as dead or gone
+    pub fn converged(&self) -> bool {
+        for observer in self.live() {
+            let view = self.view(observer);
+            for (member, state) in view.into_iter().enumerate() {
+                let expected = if self.nodes[member].crashed {
+                    state.is_none_or(|state| state == MemberState::Dead)
+                } else {
+                    state == Some(MemberState::Alive)
+                };
+                if member != observer && !expected {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    // rounds until all `observers` have `member` dead or forgotten, None if
+    // that takes more than `max_rounds`
+    pub async fn rounds_until_dead(
+        &self,
+        observers: &[usize],
+        member: usize,
+        max_rounds: u32,
+    ) -> Option<u32> {
+        self.rounds_until(max_rounds, || {
+            for &observer in observers {
+                if self
+                    .state(observer, member)
+                    .is_some_and(|state| state != MemberState::Dead)
+                {
+                    return false;
+                }
+            }
+            true
+        })
+        .await
+    }
+
+    pub async fn rounds_until_converged(&self, max_rounds: u32) -> Option<u32> {
+        self.rounds_until(max_rounds, || self.converged()).await
+    }
+
+    // runs for `rounds` rounds and returns the first time a live node was
+    // considered dead by another live node, if that happened
+    pub async fn watch_false_deaths(
+        &self,
+        rounds: u32,
+    ) -> Option<(Duration, Vec<(MemberId, MemberId)>)> {
+        let start = tokio::time::Instant::now();
+        let end = start + self.interval * rounds;
+        while tokio::time::Instant::now() < end {
+            let deaths = self.false_deaths();
+            if !deaths.is_empty() {
+                return Some((start.elapsed(), deaths));
+            }
+            self.run_for(CHECK_INTERVAL).await;
+        }
+        None
+    }
+
+    async fn rounds_until(&self, max_rounds: u32, mut check: impl FnMut() -> bool) -> Option<u32> {
+        let start = tokio::time::Instant::now();
+        let end = start + self.interval * max_rounds;
+        loop {
+            if check() {
+                let elapsed = start.elapsed();
+                return Some(elapsed.div_duration_f64(self.interval).ceil() as u32);
+            }
+            if tokio::time::Instant::now() >= end {
+                return None;
+            }
+            self.run_for(CHECK_INTERVAL).await;
+        }
+    }
+}
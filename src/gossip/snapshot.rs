@@ -0,0 +1,71 @@
+use super::messages::{BackendUpdate, MemberId};
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
+
+// bumped whenever the layout below changes, older files are ignored
+const SNAPSHOT_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotMember {
+    pub id: MemberId,
+    pub addr: SocketAddr,
+    pub incarnation: u64,
+}
+
+// what a node remembers about the cluster across restarts: who its peers
+// were, the incarnation it last used, and the backend health it last saw
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub incarnation: u64,
+    pub members: Vec<SnapshotMember>,
+    pub backends: Vec<BackendUpdate>,
+}
+
+impl Snapshot {
+    // None if there is no state file yet
+    pub async fn load(path: &Path) -> Result<Option<Self>> {
+        let bytes = match tokio::fs::read(path).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        // the version alone first, the rest may be in another layout
+        let version: u32 = bincode::deserialize(&bytes)?;
+        if version != SNAPSHOT_VERSION {
+            return Err(anyhow!(
+                "State file has version {}, expected {}",
+                version,
+                SNAPSHOT_VERSION
+            ));
+        }
+        let (_, snapshot): (u32, Snapshot) = bincode::deserialize(&bytes)?;
+        Ok(Some(snapshot))
+    }
+
+    // written next to the target and renamed over it, so a crash mid-write
+    // leaves the previous snapshot in place. the data is synced before the
+    // rename and the directory after it, or a power loss could leave the new
+    // name pointing at an empty file, or the old one back in place
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        let bytes = bincode::serialize(&(SNAPSHOT_VERSION, self))?;
+
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut file = tokio::fs::File::create(&tmp).await?;
+        file.write_all(&bytes).await?;
+        file.sync_all().await?;
+        drop(file);
+        tokio::fs::rename(&tmp, path).await?;
+
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        tokio::fs::File::open(dir).await?.sync_all().await?;
+        Ok(())
+    }
+}
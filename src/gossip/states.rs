@@ -1,9 +1,10 @@
-use super::messages::{Member};
-use std::time::Instant;
+use super::messages::Member;
+use tokio::time::Instant;
 
 #[derive(Debug, Clone)]
 pub struct IndirectPingState {
     pub(super) target: Member,
     pub(super) responses: Vec<bool>,
+    pub(super) expected: usize,
     pub(super) started_at: Instant,
 }
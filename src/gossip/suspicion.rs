@@ -0,0 +1,83 @@
+use super::messages::MemberId;
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::time::Instant;
+
+// lifeguard's dynamic suspicion timeout. starts at max and shrinks towards
+// min as other members confirm it, one slow accuser can't kill anyone fast
+#[derive(Debug, Clone)]
+pub struct Suspicion {
+    started_at: Instant,
+    min: Duration,
+    max: Duration,
+    expected_confirmations: u32,
+    // who our updates about the suspect name as its accuser
+    accuser: MemberId,
+    confirmations: HashSet<MemberId>,
+}
+
+impl Suspicion {
+    pub fn new(
+        accuser: MemberId,
+        expected_confirmations: u32,
+        min: Duration,
+        max: Duration,
+        now: Instant,
+    ) -> Self {
+        // the original accuser doesn't count as a confirmation
+        let mut confirmations = HashSet::new();
+        confirmations.insert(accuser.clone());
+
+        Self {
+            started_at: now,
+            min,
+            max: max.max(min),
+            expected_confirmations,
+            accuser,
+            confirmations,
+        }
+    }
+
+    pub fn accuser(&self) -> &MemberId {
+        &self.accuser
+    }
+
+    // true if `from` hadn't confirmed yet
+    pub fn confirm(&mut self, from: &MemberId) -> bool {
+        if self.confirmations() >= self.expected_confirmations {
+            return false;
+        }
+        self.confirmations.insert(from.clone())
+    }
+
+    // confirm, and name `accuser` from now on, so others can count it too
+    pub fn accuse(&mut self, accuser: &MemberId) -> bool {
+        let confirmed = self.confirm(accuser);
+        if confirmed {
+            self.accuser = accuser.clone();
+        }
+        confirmed
+    }
+
+    pub fn confirmations(&self) -> u32 {
+        self.confirmations.len() as u32 - 1
+    }
+
+    pub fn timeout(&self) -> Duration {
+        if self.expected_confirmations < 1 {
+            return self.min;
+        }
+
+        let confirmations = self.confirmations().min(self.expected_confirmations);
+        let frac =
+            ((confirmations + 1) as f64).ln() / ((self.expected_confirmations + 1) as f64).ln();
+        let span = (self.max - self.min).as_secs_f64();
+        let timeout = self.max.as_secs_f64() - span * frac;
+
+        Duration::from_secs_f64(timeout).max(self.min)
+    }
+
+    pub fn is_expired(&self, now: Instant) -> bool {
+        now.duration_since(self.started_at) > self.timeout()
+    }
+}
@@ -0,0 +1,109 @@
+use std::net::SocketAddr;
+use tokio::net::UdpSocket;
+use tracing::debug;
+
+// sends every datagram with as few syscalls as the platform allows. one the
+// kernel refuses is skipped and the rest still go out. returns how many were
+// sent
+#[cfg(target_os = "linux")]
+pub async fn send_all(socket: &UdpSocket, datagrams: &[(SocketAddr, Vec<u8>)]) -> usize {
+    use tokio::io::Interest;
+
+    // well below UIO_MAXIOV, a gossip round rarely has more than a handful
+    const MAX_BATCH: usize = 64;
+
+    let mut next = 0;
+    let mut sent = 0;
+
+    while next < datagrams.len() {
+        let batch = &datagrams[next..datagrams.len().min(next + MAX_BATCH)];
+
+        if let Err(e) = socket.writable().await {
+            debug!("Gossip socket not writable: {}", e);
+            break;
+        }
+
+        match socket.try_io(Interest::WRITABLE, || sys::sendmmsg(socket, batch)) {
+            // nothing sent and nothing reported, don't spin on it
+            Ok(0) => next += 1,
+            Ok(n) => {
+                next += n;
+                sent += n;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+            Err(e) => {
+                // sendmmsg stops at the first datagram that fails
+                debug!("Failed to send gossip packet to {}: {}", batch[0].0, e);
+                next += 1;
+            }
+        }
+    }
+
+    sent
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn send_all(socket: &UdpSocket, datagrams: &[(SocketAddr, Vec<u8>)]) -> usize {
+    let mut sent = 0;
+    for (target, bytes) in datagrams {
+        match socket.send_to(bytes, target).await {
+            Ok(_) => sent += 1,
+            Err(e) => debug!("Failed to send gossip packet to {}: {}", target, e),
+        }
+    }
+    sent
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use socket2::SockAddr;
+    use std::io;
+    use std::net::SocketAddr;
+    use std::os::fd::AsRawFd;
+    use tokio::net::UdpSocket;
+
+    pub fn sendmmsg(socket: &UdpSocket, datagrams: &[(SocketAddr, Vec<u8>)]) -> io::Result<usize> {
+        let addrs: Vec<SockAddr> = datagrams.iter().map(|(addr, _)| (*addr).into()).collect();
+        let mut iovecs: Vec<libc::iovec> = datagrams
+            .iter()
+            .map(|(_, bytes)| libc::iovec {
+                iov_base: bytes.as_ptr() as *mut libc::c_void,
+                iov_len: bytes.len(),
+            })
+            .collect();
+
+        let mut headers: Vec<libc::mmsghdr> = iovecs
+            .iter_mut()
+            .zip(&addrs)
+            .map(|(iov, addr)| {
+                // SAFETY: msghdr is plain old data, all zeroes is a valid empty header
+                let mut hdr: libc::msghdr = unsafe { std::mem::zeroed() };
+                hdr.msg_name = addr.as_ptr() as *mut libc::c_void;
+                hdr.msg_namelen = addr.len();
+                hdr.msg_iov = iov;
+                hdr.msg_iovlen = 1;
+                libc::mmsghdr {
+                    msg_hdr: hdr,
+                    msg_len: 0,
+                }
+            })
+            .collect();
+
+        // SAFETY: every header points into addrs, iovecs and datagrams, which
+        // all outlive the call, and the kernel only reads from them
+        let n = unsafe {
+            libc::sendmmsg(
+                socket.as_raw_fd(),
+                headers.as_mut_ptr(),
+                headers.len() as libc::c_uint,
+                0,
+            )
+        };
+
+        if n < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(n as usize)
+        }
+    }
+}
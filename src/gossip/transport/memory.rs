@@ -0,0 +1,208 @@
+use super::Transport;
+use anyhow::Result;
+use futures::future::BoxFuture;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+type Inbox = mpsc::UnboundedSender<(Vec<u8>, SocketAddr)>;
+
+#[derive(Debug, Clone, Default)]
+pub struct NetworkConditions {
+    pub latency: Duration,
+    // random extra delay per packet, packets sent closer together than this
+    // may arrive out of order
+    pub jitter: Duration,
+    // chance that a packet is lost, streams are never lost
+    pub loss: f64,
+}
+
+struct Network {
+    rng: StdRng,
+    conditions: NetworkConditions,
+    nodes: HashMap<SocketAddr, Inbox>,
+    // (from, to) pairs that can't reach each other
+    blocked: HashSet<(SocketAddr, SocketAddr)>,
+    // nodes cut off from everyone, as if they crashed
+    disconnected: HashSet<SocketAddr>,
+    // extra delay on everything to or from a node
+    slow: HashMap<SocketAddr, Duration>,
+}
+
+// a simulated network for tests: every node binds a MemoryTransport to it
+// instead of a socket. seeded, so the same seed drops and delays the same
+// packets
+#[derive(Clone)]
+pub struct MemoryNetwork {
+    inner: Arc<Mutex<Network>>,
+}
+
+impl MemoryNetwork {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Network {
+                rng: StdRng::seed_from_u64(seed),
+                conditions: NetworkConditions::default(),
+                nodes: HashMap::new(),
+                blocked: HashSet::new(),
+                disconnected: HashSet::new(),
+                slow: HashMap::new(),
+            })),
+        }
+    }
+
+    pub fn set_conditions(&self, conditions: NetworkConditions) {
+        self.inner.lock().unwrap().conditions = conditions;
+    }
+
+    pub fn bind(&self, addr: SocketAddr) -> Result<MemoryTransport> {
+        let mut network = self.inner.lock().unwrap();
+        if network.nodes.contains_key(&addr) {
+            return Err(anyhow::anyhow!("Address {} already in use", addr));
+        }
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        network.nodes.insert(addr, tx);
+
+        Ok(MemoryTransport {
+            network: self.clone(),
+            local_addr: addr,
+            inbox: tokio::sync::Mutex::new(rx),
+        })
+    }
+
+    // nodes on one side can't reach the other, in either direction
+    pub fn partition(&self, side_a: &[SocketAddr], side_b: &[SocketAddr]) {
+        let mut network = self.inner.lock().unwrap();
+        for a in side_a {
+            for b in side_b {
+                network.blocked.insert((*a, *b));
+                network.blocked.insert((*b, *a));
+            }
+        }
+    }
+
+    // heals partitions, disconnected nodes stay disconnected
+    pub fn heal(&self) {
+        self.inner.lock().unwrap().blocked.clear();
+    }
+
+    // nothing the node sends arrives and nothing reaches it, until reconnected
+    pub fn disconnect(&self, addr: SocketAddr) {
+        self.inner.lock().unwrap().disconnected.insert(addr);
+    }
+
+    pub fn reconnect(&self, addr: SocketAddr) {
+        self.inner.lock().unwrap().disconnected.remove(&addr);
+    }
+
+    // delays everything the node sends or receives by another `delay`, like
+    // an overloaded host. zero makes it normal again
+    pub fn set_node_delay(&self, addr: SocketAddr, delay: Duration) {
+        let mut network = self.inner.lock().unwrap();
+        if delay.is_zero() {
+            network.slow.remove(&addr);
+        } else {
+            network.slow.insert(addr, delay);
+        }
+    }
+
+    fn deliver(&self, from: SocketAddr, to: SocketAddr, bytes: Vec<u8>, reliable: bool) -> bool {
+        let mut network = self.inner.lock().unwrap();
+        if network.blocked.contains(&(from, to))
+            || network.disconnected.contains(&from)
+            || network.disconnected.contains(&to)
+        {
+            return false;
+        }
+        let Some(inbox) = network.nodes.get(&to).cloned() else {
+            return false;
+        };
+
+        let loss = network.conditions.loss;
+        if !reliable && loss > 0.0 && network.rng.random::<f64>() < loss {
+            // sent fine as far as the sender can tell
+            return true;
+        }
+
+        let jitter = network.conditions.jitter;
+        let delay = network.conditions.latency
+            + network.slow.get(&from).copied().unwrap_or_default()
+            + network.slow.get(&to).copied().unwrap_or_default()
+            + if jitter.is_zero() {
+                Duration::ZERO
+            } else {
+                network.rng.random_range(Duration::ZERO..jitter)
+            };
+
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            let _ = inbox.send((bytes, from));
+        });
+        true
+    }
+}
+
+pub struct MemoryTransport {
+    network: MemoryNetwork,
+    local_addr: SocketAddr,
+    inbox: tokio::sync::Mutex<mpsc::UnboundedReceiver<(Vec<u8>, SocketAddr)>>,
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        self.network
+            .inner
+            .lock()
+            .unwrap()
+            .nodes
+            .remove(&self.local_addr);
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    fn send_packet<'a>(
+        &'a self,
+        target: SocketAddr,
+        bytes: &'a [u8],
+    ) -> BoxFuture<'a, io::Result<()>> {
+        // like UDP, an unreachable peer isn't an error
+        self.network
+            .deliver(self.local_addr, target, bytes.to_vec(), false);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn supports_stream(&self) -> bool {
+        true
+    }
+
+    fn send_stream(&self, target: SocketAddr, bytes: Vec<u8>) -> BoxFuture<'_, Result<()>> {
+        let delivered = self.network.deliver(self.local_addr, target, bytes, true);
+        Box::pin(async move {
+            if delivered {
+                Ok(())
+            } else {
+                Err(anyhow::anyhow!("Connection to {} refused", target))
+            }
+        })
+    }
+
+    fn recv(&self) -> BoxFuture<'_, io::Result<(Vec<u8>, SocketAddr)>> {
+        Box::pin(async move {
+            let mut inbox = self.inbox.lock().await;
+            inbox
+                .recv()
+                .await
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "network shut down"))
+        })
+    }
+}
@@ -0,0 +1,44 @@
+mod batch;
+mod memory;
+mod stream;
+mod udp;
+
+pub use memory::{MemoryNetwork, MemoryTransport, NetworkConditions};
+pub use udp::UdpTransport;
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use std::io;
+use std::net::SocketAddr;
+
+// how gossip messages get between members. packets are best effort, streams
+// are for messages too large for a packet and arrive intact or not at all.
+// whatever arrives, packet or stream, comes out of `recv`
+pub trait Transport: Send + Sync + 'static {
+    fn local_addr(&self) -> SocketAddr;
+
+    fn send_packet<'a>(
+        &'a self,
+        target: SocketAddr,
+        bytes: &'a [u8],
+    ) -> BoxFuture<'a, io::Result<()>>;
+
+    // returns how many were sent
+    fn send_packets<'a>(&'a self, packets: &'a [(SocketAddr, Vec<u8>)]) -> BoxFuture<'a, usize> {
+        Box::pin(async move {
+            let mut sent = 0;
+            for (target, bytes) in packets {
+                if self.send_packet(*target, bytes).await.is_ok() {
+                    sent += 1;
+                }
+            }
+            sent
+        })
+    }
+
+    fn supports_stream(&self) -> bool;
+
+    fn send_stream(&self, target: SocketAddr, bytes: Vec<u8>) -> BoxFuture<'_, Result<()>>;
+
+    fn recv(&self) -> BoxFuture<'_, io::Result<(Vec<u8>, SocketAddr)>>;
+}
@@ -0,0 +1,53 @@
+use crate::gossip::MAX_STREAM_MESSAGE_SIZE;
+use anyhow::Result;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+// TCP fallback for gossip messages that don't fit in a UDP packet. Each
+// message is a u32 big endian length followed by the same bytes we'd have
+// put in the datagram.
+
+const STREAM_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub async fn send(target: SocketAddr, bytes: &[u8]) -> Result<()> {
+    let len = u32::try_from(bytes.len())?;
+
+    tokio::time::timeout(STREAM_TIMEOUT, async {
+        let mut stream = TcpStream::connect(target).await?;
+        stream.write_all(&len.to_be_bytes()).await?;
+        stream.write_all(bytes).await?;
+        stream.shutdown().await?;
+        Ok::<_, anyhow::Error>(())
+    })
+    .await
+    .map_err(|_| anyhow::anyhow!("Timed out streaming message to {}", target))?
+}
+
+// next message on the stream, None once the peer closed it
+pub async fn read_message(stream: &mut TcpStream) -> Result<Option<Vec<u8>>> {
+    tokio::time::timeout(STREAM_TIMEOUT, async {
+        let mut len = [0u8; 4];
+        match stream.read_exact(&mut len).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        let len = u32::from_be_bytes(len) as usize;
+        if len > MAX_STREAM_MESSAGE_SIZE {
+            return Err(anyhow::anyhow!(
+                "Streamed message size {} exceeds max message size {}",
+                len,
+                MAX_STREAM_MESSAGE_SIZE
+            ));
+        }
+
+        let mut bytes = vec![0u8; len];
+        stream.read_exact(&mut bytes).await?;
+        Ok(Some(bytes))
+    })
+    .await
+    .map_err(|_| anyhow::anyhow!("Timed out reading streamed message"))?
+}
@@ -0,0 +1,126 @@
+use super::{Transport, batch, stream};
+use anyhow::Result;
+use futures::future::BoxFuture;
+use std::io;
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::{Mutex, mpsc};
+use tracing::{error, warn};
+
+// streamed messages waiting for `recv`
+const STREAM_QUEUE_SIZE: usize = 1024;
+
+struct Inbox {
+    buf: Vec<u8>,
+    streamed: mpsc::Receiver<(Vec<u8>, SocketAddr)>,
+}
+
+// UDP for packets, and TCP on the same port for streams
+pub struct UdpTransport {
+    socket: UdpSocket,
+    local_addr: SocketAddr,
+    stream: bool,
+    inbox: Mutex<Inbox>,
+}
+
+impl UdpTransport {
+    pub async fn bind(addr: SocketAddr, stream: bool) -> Result<Self> {
+        let socket = UdpSocket::bind(addr).await?;
+        let local_addr = socket.local_addr()?;
+        let (tx, streamed) = mpsc::channel(STREAM_QUEUE_SIZE);
+
+        if stream {
+            // same port as UDP, so peers find it at the address we advertise
+            let listener = TcpListener::bind(local_addr).await?;
+            tokio::spawn(accept_streams(listener, tx));
+        }
+
+        Ok(Self {
+            socket,
+            local_addr,
+            stream,
+            inbox: Mutex::new(Inbox {
+                buf: vec![0u8; 65535], // Max UDP packet size
+                streamed,
+            }),
+        })
+    }
+}
+
+impl Transport for UdpTransport {
+    fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    fn send_packet<'a>(
+        &'a self,
+        target: SocketAddr,
+        bytes: &'a [u8],
+    ) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            self.socket.send_to(bytes, target).await?;
+            Ok(())
+        })
+    }
+
+    fn send_packets<'a>(&'a self, packets: &'a [(SocketAddr, Vec<u8>)]) -> BoxFuture<'a, usize> {
+        Box::pin(batch::send_all(&self.socket, packets))
+    }
+
+    fn supports_stream(&self) -> bool {
+        self.stream
+    }
+
+    fn send_stream(&self, target: SocketAddr, bytes: Vec<u8>) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move { stream::send(target, &bytes).await })
+    }
+
+    fn recv(&self) -> BoxFuture<'_, io::Result<(Vec<u8>, SocketAddr)>> {
+        Box::pin(async move {
+            let mut inbox = self.inbox.lock().await;
+            let Inbox { buf, streamed } = &mut *inbox;
+
+            tokio::select! {
+                received = self.socket.recv_from(buf) => {
+                    let (len, src_addr) = received?;
+                    Ok((buf[..len].to_vec(), src_addr))
+                }
+                Some(message) = streamed.recv() => Ok(message),
+            }
+        })
+    }
+}
+
+async fn accept_streams(listener: TcpListener, tx: mpsc::Sender<(Vec<u8>, SocketAddr)>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, src_addr)) => {
+                tokio::spawn(read_stream(stream, src_addr, tx.clone()));
+            }
+            Err(e) => {
+                error!("Error accepting gossip stream: {}", e);
+            }
+        }
+    }
+}
+
+async fn read_stream(
+    mut stream: TcpStream,
+    src_addr: SocketAddr,
+    tx: mpsc::Sender<(Vec<u8>, SocketAddr)>,
+) {
+    loop {
+        match stream::read_message(&mut stream).await {
+            Ok(Some(data)) => {
+                if tx.send((data, src_addr)).await.is_err() {
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                warn!("Failed to read gossip stream from {}: {}", src_addr, e);
+                break;
+            }
+        }
+    }
+}
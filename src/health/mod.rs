@@ -34,7 +34,7 @@ impl HealthChecker {
     async fn check_all_backends(&self) {
         let backends = {
             let pool = self.backend_pool.read().await;
-            pool.get_all_backends()
+            pool.get_checked_backends()
         };
 
         let mut check_tasks = Vec::new();
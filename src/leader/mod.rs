@@ -0,0 +1,152 @@
+use crate::gossip::{Member, MemberId};
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::info;
+
+type TaskFn = Arc<dyn Fn(Term) -> BoxFuture<'static, ()> + Send + Sync>;
+
+// a leader and the incarnation it last refuted a suspicion at (or started
+// with). a leader that has to refute a suspicion starts a new term: while it
+// was suspected someone else may have taken over, so its tasks are stopped
+// and only started again after another lease. other incarnation bumps, like
+// tag changes, leave the term alone
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Term {
+    pub leader: MemberId,
+    pub incarnation: u64,
+}
+
+enum Role {
+    Follower,
+    // lowest id since then, leader once the lease has passed
+    Candidate { since: Instant },
+    Leader { term: Term },
+}
+
+struct State {
+    role: Role,
+    leader: Option<MemberId>,
+    tasks: Vec<(String, TaskFn)>,
+    running: Vec<(String, JoinHandle<()>)>,
+}
+
+// the alive member with the lowest id leads. a member only takes over after
+// it has been the lowest for a whole lease, so a leader that is going away
+// has time to notice. membership is eventually consistent: a partition has a
+// leader on each side until it heals, tasks have to cope with that
+pub struct LeaderElection {
+    lease: Duration,
+    state: Mutex<State>,
+}
+
+impl LeaderElection {
+    pub fn new(lease: Duration) -> Self {
+        Self {
+            lease,
+            state: Mutex::new(State {
+                role: Role::Follower,
+                leader: None,
+                tasks: Vec::new(),
+                running: Vec::new(),
+            }),
+        }
+    }
+
+    // runs `task` on whichever node leads. it is started with the term when
+    // this node takes over, and aborted when it steps down
+    pub fn register<F, Fut>(&self, name: &str, task: F)
+    where
+        F: Fn(Term) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let task: TaskFn = Arc::new(move |term| task(term).boxed());
+        let mut state = self.state.lock().unwrap();
+        if let Role::Leader { term } = &state.role {
+            let handle = tokio::spawn(task(term.clone()));
+            state.running.push((name.to_string(), handle));
+        }
+        state.tasks.push((name.to_string(), task));
+    }
+
+    // who we think leads, which isn't necessarily running the tasks yet
+    pub fn leader(&self) -> Option<MemberId> {
+        self.state.lock().unwrap().leader.clone()
+    }
+
+    // our term, if we lead
+    pub fn term(&self) -> Option<Term> {
+        match &self.state.lock().unwrap().role {
+            Role::Leader { term } => Some(term.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.term().is_some()
+    }
+
+    // picks the leader among `local` and the other alive `members`, and
+    // starts or stops the tasks if that changes our role. `term` is the
+    // incarnation of our last refutation, see MemberList::term
+    pub fn update(&self, now: Instant, local: &MemberId, term: u64, members: &[Member]) {
+        let mut state = self.state.lock().unwrap();
+        let lowest = members.iter().map(|m| &m.id).chain([local]).min().cloned();
+        if state.leader != lowest {
+            if let Some(leader) = &lowest {
+                info!("Cluster leader is now {}", leader.0);
+            }
+            state.leader = lowest.clone();
+        }
+
+        if lowest.as_ref() != Some(local) {
+            if matches!(state.role, Role::Leader { .. }) {
+                info!("Stepping down as leader");
+                stop_tasks(&mut state);
+            }
+            state.role = Role::Follower;
+            return;
+        }
+
+        let term = Term {
+            leader: local.clone(),
+            incarnation: term,
+        };
+        match &state.role {
+            Role::Follower => state.role = Role::Candidate { since: now },
+            Role::Candidate { since } if now.duration_since(*since) >= self.lease => {
+                info!("Leading the cluster at incarnation {}", term.incarnation);
+                let running = state
+                    .tasks
+                    .iter()
+                    .map(|(name, task)| {
+                        info!("Starting singleton task {}", name);
+                        (name.clone(), tokio::spawn(task(term.clone())))
+                    })
+                    .collect();
+                state.running = running;
+                state.role = Role::Leader { term };
+            }
+            Role::Leader { term: current } if *current != term => {
+                info!(
+                    "Refuted a suspicion at incarnation {}, leading again after the lease",
+                    term.incarnation
+                );
+                stop_tasks(&mut state);
+                state.role = Role::Candidate { since: now };
+            }
+            _ => {}
+        }
+    }
+}
+
+fn stop_tasks(state: &mut State) {
+    for (name, handle) in state.running.drain(..) {
+        info!("Stopping singleton task {}", name);
+        handle.abort();
+    }
+}
@@ -0,0 +1,10 @@
+pub mod admin;
+pub mod backend;
+pub mod config;
+pub mod connection_pool;
+pub mod gossip;
+pub mod health;
+pub mod leader;
+pub mod proxy;
+pub mod ratelimit;
+pub mod vip;
@@ -1,15 +1,14 @@
 use anyhow::Result;
+use flux::gossip::Transport;
+use flux::{
+    admin, backend, config, connection_pool, gossip, health, leader, proxy, ratelimit, vip,
+};
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
+use tokio::signal::unix::{SignalKind, signal};
 use tokio::sync::RwLock;
-use tracing::info;
-
-mod backend;
-mod config;
-mod connection_pool;
-mod gossip;
-mod health;
-mod proxy;
+use tracing::{info, warn};
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 8)]
 async fn main() -> Result<()> {
@@ -25,83 +24,229 @@ async fn main() -> Result<()> {
     info!("Loading config from {}", config_path);
 
     let config = config::Config::from_file(&config_path)?;
-    info!("Loaded config with {} backends", config.backends.len());
-
-    let backends: Vec<backend::Backend> = config
-        .backends
-        .into_iter()
-        .map(|b| backend::Backend {
-            addr: b.addr,
-            weight: b.weight,
-        })
-        .collect();
+    let frontends = config.frontends();
+    let pool_configs = config.pools();
+    info!(
+        "Loaded config with {} frontends and {} backend pools",
+        frontends.len(),
+        pool_configs.len()
+    );
 
-    let backend_pool = Arc::new(RwLock::new(backend::BackendPool::new(backends)));
+    // bound first, so a port 0 bind advertises and names us by the port
+    // we actually got
+    let transport = Arc::new(
+        gossip::UdpTransport::bind(config.gossip.bind_addr, config.gossip.stream_fallback).await?,
+    );
+    let gossip_addr = config
+        .gossip
+        .advertise_addr
+        .unwrap_or_else(|| gossip::advertise_addr(transport.local_addr()));
+    let member_id = gossip::MemberId::generate(gossip_addr);
+    let clock = Arc::new(gossip::HybridClock::new());
 
     let max_connections = 100; // TODO: Make configurable
-    let connection_pool = Arc::new(connection_pool::ConnectionPool::new(max_connections));
+    let mut backend_pools = backend::BackendPools::new();
+    let mut connection_pools = HashMap::new();
+    for pool_config in pool_configs {
+        let name = pool_config.name;
+        let backends: Vec<backend::Backend> = pool_config
+            .backends
+            .into_iter()
+            .map(|b| backend::Backend {
+                addr: b.addr,
+                weight: b.weight,
+                max_connections: b.max_connections,
+                zone: b.zone,
+                priority: b.priority,
+            })
+            .collect();
+        info!("Pool {} has {} backends", name, backends.len());
+
+        let mut backend_pool =
+            backend::BackendPool::new(backends, member_id.clone(), clock.clone());
+        if let Some(sticky) = &pool_config.sticky_sessions {
+            info!(
+                "Sticky sessions on for pool {}, pins last {}ms, at most {} of ours at a time",
+                name, sticky.ttl_ms, sticky.max_pins
+            );
+            backend_pool
+                .enable_sticky_sessions(Duration::from_millis(sticky.ttl_ms), sticky.max_pins);
+        }
+        backend_pool.set_min_healthy_backends(pool_config.min_healthy_backends);
+        if let Some(zone) = &config.server.zone {
+            backend_pool.prefer_zone(zone.clone(), config.server.min_zone_capacity_percent);
+        }
+        let backend_pool = Arc::new(RwLock::new(backend_pool));
+
+        let health_check = pool_config
+            .health_check
+            .as_ref()
+            .unwrap_or(&config.health_check);
+        let health_checker = health::HealthChecker::new(
+            backend_pool.clone(),
+            health_check.check_interval_seconds,
+            health_check.check_timeout_seconds,
+        );
+        tokio::spawn(async move {
+            health_checker.run().await;
+        });
+
+        connection_pools.insert(
+            name.clone(),
+            Arc::new(connection_pool::ConnectionPool::new(max_connections)),
+        );
+        backend_pools.insert(name, backend_pool);
+    }
     info!(
-        "Connection pool created (max {} per backend)",
+        "Health checkers started, connection pools hold at most {} per backend",
         max_connections
     );
-
-    let health_checker = health::HealthChecker::new(
-        backend_pool.clone(),
-        config.health_check.check_interval_seconds,
-        config.health_check.check_timeout_seconds,
-    );
-
-    tokio::spawn(async move {
-        health_checker.run().await;
+    if let Some(zone) = &config.server.zone {
+        info!(
+            "Preferring backends in zone {} while {}% of them are available",
+            zone, config.server.min_zone_capacity_percent
+        );
+    }
+
+    let proxy_addr = config.server.advertise_addr.unwrap_or_else(|| {
+        // validated to have at least one frontend
+        gossip::advertise_addr(frontends[0].listen_addr)
     });
-    info!("Health checker started.");
-
-    let gossip_addr = config.gossip.bind_addr;
-    let member_id = gossip::MemberId::generate(gossip_addr);
-    let suspect_timeout = Duration::from_millis(config.gossip.suspect_timeout_ms);
-
-    let (mut gossip_layer, member_list) = gossip::GossipLayer::new(
+    let mut gossip_config = config.gossip.clone();
+    gossip_config
+        .tags
+        .insert(gossip::PROXY_ADDR_TAG.to_string(), proxy_addr.to_string());
+    if let Some(zone) = &config.server.zone {
+        gossip_config
+            .tags
+            .entry(gossip::ZONE_TAG.to_string())
+            .or_insert_with(|| zone.clone());
+    }
+
+    let gossip_layer = gossip::GossipLayer::with_transport(
         member_id,
-        gossip_addr,
-        suspect_timeout,
-        backend_pool.clone(),
+        &gossip_config,
+        backend_pools.clone(),
+        transport,
     )
     .await?;
 
-    let seed_nodes = config.gossip.seed_nodes.clone();
-    gossip_layer.join_cluster(seed_nodes).await?;
+    let runner = gossip_layer.clone();
+    tokio::spawn(async move {
+        runner.run().await;
+    });
+
+    gossip_layer.join_cluster().await?;
 
-    let socket_clone = gossip_layer.socket();
-    let pending_pings_clone = gossip_layer.pending_pings();
-    let pending_indirect_pings_clone = gossip_layer.pending_indirect_pings();
-    let member_list_clone = member_list.clone();
+    let reconnector = gossip_layer.clone();
+    tokio::spawn(async move {
+        reconnector.start_reconnect_loop().await;
+    });
 
+    let snapshotter = gossip_layer.clone();
     tokio::spawn(async move {
-        gossip_layer.run().await;
+        snapshotter.start_snapshot_loop().await;
     });
 
-    let gossip_interval = Duration::from_millis(config.gossip.gossip_interval_ms);
-    let ping_timeout = Duration::from_millis(config.gossip.ping_timeout_ms);
-    let backend_pool_for_gossip = backend_pool.clone();
+    let pin_publisher = gossip_layer.clone();
+    tokio::spawn(async move {
+        pin_publisher.start_sticky_session_loop().await;
+    });
 
+    let load_reporter = gossip_layer.clone();
     tokio::spawn(async move {
-        gossip::GossipLayer::start_gossip_loop(
-            member_list_clone,
-            socket_clone,
-            pending_pings_clone,
-            pending_indirect_pings_clone,
-            backend_pool_for_gossip,
-            gossip_interval,
-            ping_timeout,
-        )
-        .await;
+        load_reporter.start_load_report_loop().await;
     });
 
-    info!("Gossip layer started on {}", gossip_addr);
+    // singleton tasks register here, they run on one node at a time
+    let election = Arc::new(leader::LeaderElection::new(Duration::from_millis(
+        config.gossip.leader_lease_ms,
+    )));
+    let reaper = gossip_layer.clone();
+    election.register("report reaper", move |_| {
+        let reaper = reaper.clone();
+        async move { reaper.start_report_reaper_loop().await }
+    });
+    let leader_elector = gossip_layer.clone();
+    tokio::spawn(async move {
+        leader_elector.start_leader_election_loop(election).await;
+    });
+
+    // kept for shutdown, the addresses we hold are released then
+    let vips = Arc::new(vip::VirtualIps::new(config.virtual_ips.clone()));
+    if !config.virtual_ips.is_empty() {
+        let elector = gossip_layer.clone();
+        let vips = vips.clone();
+        tokio::spawn(async move {
+            elector.start_virtual_ip_loop(vips).await;
+        });
+    }
 
-    let proxy = proxy::Proxy::new(config.server.listen_addr, backend_pool, connection_pool);
-    proxy.run().await?;
+    info!(
+        "Gossip layer started on {}",
+        gossip_layer.local_member().addr
+    );
 
+    if let Some(admin_addr) = config.server.admin_addr {
+        let admin =
+            admin::AdminServer::bind(admin_addr, gossip_layer.clone(), backend_pools.clone())
+                .await?;
+        info!("Admin socket listening on {}", admin.local_addr()?);
+        tokio::spawn(admin.run());
+    }
+
+    let mut proxies = Vec::new();
+    for frontend in frontends {
+        // validated to route to a pool we have
+        let mut proxy = proxy::Proxy::new(
+            frontend.listen_addr,
+            backend_pools.get(&frontend.pool).unwrap().clone(),
+            connection_pools[&frontend.pool].clone(),
+        )
+        .with_strategy(frontend.strategy);
+        info!(
+            "Frontend {} on {} routes to pool {} ({:?})",
+            frontend.name, frontend.listen_addr, frontend.pool, frontend.strategy
+        );
+        if let Some(rate_limit) = frontend.rate_limit {
+            info!(
+                "Rate limiting new connections to {} per {:?} to {}/s (burst {}) across the cluster",
+                frontend.name, rate_limit.key, rate_limit.rate, rate_limit.burst
+            );
+            let limiter = Arc::new(ratelimit::RateLimiter::new(rate_limit));
+            let reporter = gossip_layer.clone();
+            tokio::spawn({
+                let limiter = limiter.clone();
+                let name = frontend.name.clone();
+                async move {
+                    reporter.start_rate_limit_loop(&name, limiter).await;
+                }
+            });
+            proxy = proxy.with_rate_limiter(limiter);
+        }
+        proxies.push(proxy);
+    }
     info!("Flux is running.");
+    tokio::select! {
+        result = futures::future::try_join_all(proxies.iter().map(|proxy| proxy.run())) => {
+            result?;
+        }
+        _ = shutdown_signal() => info!("Shutting down"),
+    }
+
+    vips.release_all().await;
+    // a restart then comes back with the incarnation we left at
+    if let Err(e) = gossip_layer.save_snapshot().await {
+        warn!("Failed to save gossip state: {}", e);
+    }
     Ok(())
 }
+
+// ctrl-c or SIGTERM
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
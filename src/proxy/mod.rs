@@ -1,16 +1,21 @@
 use crate::backend::SharedBackendPool;
+use crate::config::Strategy;
 use crate::connection_pool::SharedConnectionPool;
-use anyhow::{Error, Result, anyhow};
-use socket2::{Socket, Domain, Type, Protocol};
+use crate::ratelimit::RateLimiter;
+use anyhow::{Result, anyhow};
+use socket2::{Domain, Protocol, Socket, Type};
 use std::net::SocketAddr;
+use std::net::TcpListener as StdTcpListener;
+use std::sync::Arc;
 use tokio::net::{TcpListener, TcpStream};
-use tracing::{debug, error, info};
-use std::{net::TcpListener as StdTcpListener};
+use tracing::{debug, error};
 
 pub struct Proxy {
     listen_addr: SocketAddr,
     backend_pool: SharedBackendPool,
     connection_pool: SharedConnectionPool,
+    strategy: Strategy,
+    rate_limiter: Option<Arc<RateLimiter>>,
 }
 
 impl Proxy {
@@ -23,9 +28,23 @@ impl Proxy {
             listen_addr,
             backend_pool,
             connection_pool,
+            strategy: Strategy::RoundRobin,
+            rate_limiter: None,
         }
     }
 
+    pub fn with_strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    // new connections over the limit are delayed or closed before they
+    // get a backend
+    pub fn with_rate_limiter(mut self, rate_limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
     pub async fn run(&self) -> Result<()> {
         let mut listeners = Vec::new();
         for _ in 0..8 {
@@ -36,6 +55,9 @@ impl Proxy {
         for lst in listeners {
             let backend_pool = self.backend_pool.clone();
             let connection_pool = self.connection_pool.clone();
+            let rate_limiter = self.rate_limiter.clone();
+            let listen_addr = self.listen_addr;
+            let strategy = self.strategy;
 
             tokio::spawn(async move {
                 loop {
@@ -43,14 +65,26 @@ impl Proxy {
                         Ok((client_socket, client_addr)) => {
                             let backend_pool = backend_pool.clone();
                             let connection_pool = connection_pool.clone();
+                            let rate_limiter = rate_limiter.clone();
 
                             tokio::spawn(async move {
+                                if let Some(limiter) = rate_limiter {
+                                    let key = limiter.key_for(client_addr, listen_addr);
+                                    if !limiter.acquire(&key).await {
+                                        debug!("Rate limited {client_addr}, closing");
+                                        return;
+                                    }
+                                }
+
                                 if let Err(e) = handle_connection(
                                     client_socket,
                                     backend_pool,
                                     connection_pool,
+                                    strategy,
                                     client_addr,
-                                ).await {
+                                )
+                                .await
+                                {
                                     error!("Error handling {client_addr}: {e:#}");
                                 }
                             });
@@ -70,13 +104,16 @@ async fn handle_connection(
     mut client_socket: TcpStream,
     backend_pool: SharedBackendPool,
     connection_pool: SharedConnectionPool,
+    strategy: Strategy,
     client_addr: SocketAddr,
 ) -> Result<()> {
-    let backend = {
+    // counts against the backend's connection limit until we return
+    let connection = {
         let pool = backend_pool.read().await;
-        pool.select_backend()
+        pool.acquire_backend_with(client_addr.ip(), strategy)
             .ok_or_else(|| anyhow!("No backends available!"))?
     };
+    let backend = connection.backend();
     debug!("Routing {} to backend {}", client_addr, backend.addr);
     let mut backend_socket = connection_pool.get(backend.addr).await?;
 
@@ -97,9 +134,8 @@ async fn copy_with_pooling(client: &mut TcpStream, backend: &mut TcpStream) -> R
     Ok(())
 }
 
-
 fn bind_reuseport(addr: &SocketAddr) -> Result<StdTcpListener> {
-    let addr: std::net::SocketAddr = (*addr).into();
+    let addr: std::net::SocketAddr = *addr;
     let domain = match addr {
         std::net::SocketAddr::V4(_) => Domain::IPV4,
         std::net::SocketAddr::V6(_) => Domain::IPV6,
@@ -114,4 +150,4 @@ fn bind_reuseport(addr: &SocketAddr) -> Result<StdTcpListener> {
     sock.listen(4096)?;
     sock.set_nonblocking(true)?;
     Ok(sock.into())
-}
\ No newline at end of file
+}
@@ -0,0 +1,251 @@
+use crate::config::{OnLimit, RateLimitConfig, RateLimitKey};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::debug;
+
+// a consumption report has to fit in a gossip key/value entry, the
+// busiest keys go first
+const MAX_REPORT_BYTES: usize = 400;
+
+// ends a report that was cut short, with how many keys were left out.
+// client IPs and listener addresses are never "*"
+pub const UNREPORTED_KEY: &str = "*";
+
+#[derive(Debug, Default)]
+pub struct RateLimitMetrics {
+    pub allowed: AtomicU64,
+    // allowed after waiting for a token
+    pub delayed: AtomicU64,
+    pub rejected: AtomicU64,
+}
+
+impl RateLimitMetrics {
+    fn incr(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Reservation {
+    Now,
+    After(Duration),
+    Rejected,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    // fraction of the cluster-wide rate and burst this node may use
+    share: f64,
+    // connections allowed since the last report, and in the interval before
+    consumed: u64,
+    last_consumed: u64,
+}
+
+#[derive(Default)]
+struct State {
+    buckets: HashMap<String, Bucket>,
+    // what the other nodes consumed per key in their last report
+    remote: HashMap<String, u64>,
+    nodes: usize,
+}
+
+// token buckets for new connections, one per client IP or listener. the
+// configured rate and burst hold for the whole cluster: every node gets a
+// share of them, proportional to how much of a key's traffic it saw last
+// interval, and shares are rebalanced whenever consumption reports come in
+// through gossip
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    state: Mutex<State>,
+    metrics: RateLimitMetrics,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(State {
+                nodes: 1,
+                ..State::default()
+            }),
+            metrics: RateLimitMetrics::default(),
+        }
+    }
+
+    pub fn metrics(&self) -> &RateLimitMetrics {
+        &self.metrics
+    }
+
+    pub fn report_interval(&self) -> Duration {
+        Duration::from_millis(self.config.report_interval_ms)
+    }
+
+    pub fn key_for(&self, client_addr: SocketAddr, listen_addr: SocketAddr) -> String {
+        match self.config.key {
+            RateLimitKey::ClientIp => client_addr.ip().to_string(),
+            RateLimitKey::Listener => listen_addr.to_string(),
+        }
+    }
+
+    // false if the connection should be turned away. with on_limit = delay
+    // this waits up to max_delay_ms for a token
+    pub async fn acquire(&self, key: &str) -> bool {
+        match self.reserve(key, Instant::now()) {
+            Reservation::Now => {
+                RateLimitMetrics::incr(&self.metrics.allowed);
+                true
+            }
+            Reservation::After(wait) => {
+                RateLimitMetrics::incr(&self.metrics.delayed);
+                tokio::time::sleep(wait).await;
+                true
+            }
+            Reservation::Rejected => {
+                RateLimitMetrics::incr(&self.metrics.rejected);
+                false
+            }
+        }
+    }
+
+    fn reserve(&self, key: &str, now: Instant) -> Reservation {
+        let mut state = self.state.lock().unwrap();
+        let share = key_share(&state.remote, state.nodes, key, 0);
+        let bucket = state
+            .buckets
+            .entry(key.to_string())
+            .or_insert_with(|| Bucket {
+                tokens: self.burst(share),
+                updated: now,
+                share,
+                consumed: 0,
+                last_consumed: 0,
+            });
+        self.refill(bucket, now);
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            bucket.consumed += 1;
+            return Reservation::Now;
+        }
+        if self.config.on_limit == OnLimit::Reject {
+            return Reservation::Rejected;
+        }
+
+        // the token is taken now, so later callers queue up behind us
+        let wait = (1.0 - bucket.tokens) / (self.config.rate * bucket.share);
+        if wait * 1000.0 > self.config.max_delay_ms as f64 {
+            return Reservation::Rejected;
+        }
+        bucket.tokens -= 1.0;
+        bucket.consumed += 1;
+        Reservation::After(Duration::from_secs_f64(wait))
+    }
+
+    fn burst(&self, share: f64) -> f64 {
+        // at least one connection, or a small share could never be used
+        (self.config.burst as f64 * share).max(1.0)
+    }
+
+    fn refill(&self, bucket: &mut Bucket, now: Instant) {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.config.rate * bucket.share)
+            .min(self.burst(bucket.share));
+        bucket.updated = now;
+    }
+
+    // what this node consumed per key since the last report, busiest first
+    pub fn take_report(&self) -> Vec<(String, u64)> {
+        let mut state = self.state.lock().unwrap();
+        let mut report = Vec::new();
+        for (key, bucket) in &mut state.buckets {
+            bucket.last_consumed = std::mem::take(&mut bucket.consumed);
+            if bucket.last_consumed > 0 {
+                report.push((key.clone(), bucket.last_consumed));
+            }
+        }
+        report.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        report
+    }
+
+    // new shares from the other nodes' last reports. idle buckets that
+    // have filled up again are dropped, they'd start out full anyway
+    pub fn rebalance(&self, remote: HashMap<String, u64>, nodes: usize) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let nodes = nodes.max(1);
+
+        let mut buckets = std::mem::take(&mut state.buckets);
+        buckets.retain(|key, bucket| {
+            self.refill(bucket, now);
+            let new_share = key_share(&remote, nodes, key, bucket.last_consumed);
+            if new_share != bucket.share {
+                debug!(
+                    "Rate limit share for {} is now {:.2} ({} local, {:?} remote)",
+                    key,
+                    new_share,
+                    bucket.last_consumed,
+                    remote.get(key)
+                );
+                bucket.share = new_share;
+            }
+            bucket.consumed > 0
+                || bucket.last_consumed > 0
+                || bucket.tokens < self.burst(bucket.share)
+        });
+
+        state.buckets = buckets;
+        state.remote = remote;
+        state.nodes = nodes;
+    }
+}
+
+// every node gets a little, so one that was idle can still take traffic
+fn share(local: u64, remote: u64, nodes: usize) -> f64 {
+    (local + 1) as f64 / (local + remote + nodes as u64) as f64
+}
+
+// a key nobody reported may still be busy on a node whose report was cut
+// short, so it only gets an even split of the limit then
+fn key_share(remote: &HashMap<String, u64>, nodes: usize, key: &str, local: u64) -> f64 {
+    match remote.get(key) {
+        Some(&remote) => share(local, remote, nodes),
+        None if remote.contains_key(UNREPORTED_KEY) => {
+            share(local, 0, nodes).min(1.0 / nodes as f64)
+        }
+        None => share(local, 0, nodes),
+    }
+}
+
+// one "key count" line per key, as many as fit. if not all of them do,
+// an UNREPORTED_KEY line with how many were left out ends it
+pub fn encode_report(report: &[(String, u64)]) -> Vec<u8> {
+    // room for the last line, whatever the count
+    let budget = MAX_REPORT_BYTES - format!("{} {}\n", UNREPORTED_KEY, usize::MAX).len();
+    let mut encoded = String::new();
+    for (reported, (key, count)) in report.iter().enumerate() {
+        let line = format!("{} {}\n", key, count);
+        if encoded.len() + line.len() > budget {
+            let left_out = report.len() - reported;
+            encoded.push_str(&format!("{} {}\n", UNREPORTED_KEY, left_out));
+            break;
+        }
+        encoded.push_str(&line);
+    }
+    encoded.into_bytes()
+}
+
+// lines that don't parse are skipped
+pub fn decode_report(value: &[u8]) -> Vec<(String, u64)> {
+    String::from_utf8_lossy(value)
+        .lines()
+        .filter_map(|line| {
+            let (key, count) = line.rsplit_once(' ')?;
+            Some((key.to_string(), count.parse().ok()?))
+        })
+        .collect()
+}
@@ -0,0 +1,184 @@
+use crate::config::VirtualIpConfig;
+use crate::gossip::{Member, MemberId, Tags};
+use anyhow::{Result, anyhow};
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::{info, warn};
+
+const HOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+// every node advertises its priority for a virtual IP as a member tag,
+// vip/<addr> = "<priority>", with " owner" appended while it holds it
+pub const VIP_TAG_PREFIX: &str = "vip/";
+const OWNER_CLAIM: &str = "owner";
+
+pub fn vip_tag(addr: IpAddr) -> String {
+    format!("{}{}", VIP_TAG_PREFIX, addr)
+}
+
+// (priority, owns it) as advertised by `member`
+fn claim(member: &Member, addr: IpAddr) -> Option<(u32, bool)> {
+    let value = member.tag(&vip_tag(addr))?;
+    let mut parts = value.split_whitespace();
+    let priority = parts.next()?.parse().ok()?;
+    let owner = parts.next() == Some(OWNER_CLAIM);
+    Some((priority, owner))
+}
+
+// who should own `addr`, decided from the members' tags alone so every node
+// that sees the same members agrees. the highest priority wins, ties go to
+// the highest member id. without preemption a member that already owns it
+// keeps it for as long as it is alive
+pub fn elect(addr: IpAddr, preempt: bool, members: &[Member]) -> Option<MemberId> {
+    let candidates: Vec<(u32, bool, &MemberId)> = members
+        .iter()
+        .filter_map(|member| {
+            let (priority, owner) = claim(member, addr)?;
+            Some((priority, owner, &member.id))
+        })
+        .collect();
+
+    let best = |owners_only: bool| {
+        candidates
+            .iter()
+            .filter(|(_, owner, _)| !owners_only || *owner)
+            .max_by_key(|(priority, _, id)| (*priority, *id))
+            .map(|(_, _, id)| (*id).clone())
+    };
+    if !preempt && let Some(owner) = best(true) {
+        return Some(owner);
+    }
+    best(false)
+}
+
+// the virtual IPs this node can hold, and which of them it holds right now
+pub struct VirtualIps {
+    configs: Vec<VirtualIpConfig>,
+    owned: Mutex<BTreeSet<IpAddr>>,
+    // set on shutdown, nothing is claimed after that
+    released: AtomicBool,
+}
+
+impl VirtualIps {
+    pub fn new(configs: Vec<VirtualIpConfig>) -> Self {
+        Self {
+            configs,
+            owned: Mutex::new(BTreeSet::new()),
+            released: AtomicBool::new(false),
+        }
+    }
+
+    pub fn owned(&self) -> Vec<IpAddr> {
+        self.owned.lock().unwrap().iter().copied().collect()
+    }
+
+    // our priorities and ownership claims, on top of our other tags
+    pub fn advertise(&self, tags: &mut Tags) {
+        let owned = self.owned.lock().unwrap();
+        for config in &self.configs {
+            let value = if owned.contains(&config.addr) {
+                format!("{} {}", config.priority, OWNER_CLAIM)
+            } else {
+                config.priority.to_string()
+            };
+            tags.insert(vip_tag(config.addr), value);
+        }
+    }
+
+    // runs an election for every virtual IP among the alive `members`, us
+    // included, and returns the ones we gained (true) or lost (false)
+    pub fn update(&self, local_id: &MemberId, members: &[Member]) -> Vec<(VirtualIpConfig, bool)> {
+        let mut owned = self.owned.lock().unwrap();
+        if self.released.load(Ordering::Relaxed) {
+            return Vec::new();
+        }
+        let mut changes = Vec::new();
+        for config in &self.configs {
+            let owner = elect(config.addr, config.preempt, members);
+            let ours = owner.as_ref() == Some(local_id);
+            if ours == owned.contains(&config.addr) {
+                continue;
+            }
+
+            match &owner {
+                _ if ours => info!("Taking over virtual IP {}", config.addr),
+                Some(owner) => info!("Virtual IP {} moves to {}", config.addr, owner.0),
+                None => info!("Releasing virtual IP {}", config.addr),
+            }
+            if ours {
+                owned.insert(config.addr);
+            } else {
+                owned.remove(&config.addr);
+            }
+            changes.push((config.clone(), ours));
+        }
+        changes
+    }
+
+    // gives up every address we hold and runs their down hooks, one after
+    // the other. for shutdown: a process that is going away must not leave
+    // an address configured that another node is about to take over
+    pub async fn release_all(&self) {
+        let released: Vec<VirtualIpConfig> = {
+            let mut owned = self.owned.lock().unwrap();
+            self.released.store(true, Ordering::Relaxed);
+            let released = self
+                .configs
+                .iter()
+                .filter(|config| owned.contains(&config.addr))
+                .cloned()
+                .collect();
+            owned.clear();
+            released
+        };
+        for config in released {
+            info!("Releasing virtual IP {} on shutdown", config.addr);
+            run_hook(&config, false).await;
+        }
+    }
+}
+
+// runs the up or down command of a virtual IP. failures are only logged,
+// ownership has moved either way
+pub async fn run_hook(config: &VirtualIpConfig, up: bool) {
+    let command = if up {
+        &config.up_command
+    } else {
+        &config.down_command
+    };
+    let Some((program, args)) = command.split_first() else {
+        return;
+    };
+    if let Err(e) = run_command(program, args, config.addr, up).await {
+        warn!(
+            "Virtual IP {} {} command failed: {}",
+            config.addr,
+            if up { "up" } else { "down" },
+            e
+        );
+    }
+}
+
+async fn run_command(program: &str, args: &[String], addr: IpAddr, up: bool) -> Result<()> {
+    let status = tokio::time::timeout(
+        HOOK_TIMEOUT,
+        Command::new(program)
+            .args(args)
+            .env("FLUX_VIP", addr.to_string())
+            .env("FLUX_VIP_STATE", if up { "up" } else { "down" })
+            // a hook that timed out must not linger
+            .kill_on_drop(true)
+            .status(),
+    )
+    .await
+    .map_err(|_| anyhow!("`{}` timed out after {:?}", program, HOOK_TIMEOUT))??;
+
+    if !status.success() {
+        return Err(anyhow!("`{}` exited with {}", program, status));
+    }
+    Ok(())
+}
@@ -0,0 +1,121 @@
+mod common;
+
+use common::{backend_pool, eventually, gossip_config, start_node};
+use flux::admin::AdminServer;
+use flux::backend::{Backend, BackendPools, DEFAULT_POOL, OverrideMode, SharedBackendPool};
+use flux::gossip::GossipLayer;
+use std::net::SocketAddr;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+
+const BACKEND: &str = "127.0.0.1:9";
+
+fn backend() -> SocketAddr {
+    BACKEND.parse().unwrap()
+}
+
+async fn start(name: &str, seed_nodes: &[SocketAddr]) -> (GossipLayer, SharedBackendPool) {
+    let config = gossip_config(seed_nodes, "push_pull_interval_ms = 200");
+    let backends = backend_pool(name, vec![Backend::new(backend(), 1)]);
+    let layer = start_node(name, &config, backends.clone()).await;
+    layer.join_cluster().await.unwrap();
+    (layer, backends)
+}
+
+struct Client {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl Client {
+    async fn connect(addr: SocketAddr) -> Self {
+        let (reader, writer) = TcpStream::connect(addr).await.unwrap().into_split();
+        Client {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        }
+    }
+
+    async fn send(&mut self, command: &str) -> String {
+        self.writer
+            .write_all(format!("{command}\n").as_bytes())
+            .await
+            .unwrap();
+        self.lines.next_line().await.unwrap().unwrap()
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn admin_commands_reach_every_node() {
+    let (first, first_backends) = start("node-0", &[]).await;
+    let (second, second_backends) = start("node-1", &[first.local_member().addr]).await;
+
+    let mut pools = BackendPools::new();
+    pools.insert(DEFAULT_POOL, first_backends.clone());
+    let admin = AdminServer::bind("127.0.0.1:0".parse().unwrap(), first.clone(), pools)
+        .await
+        .unwrap();
+    let mut client = Client::connect(admin.local_addr().unwrap()).await;
+    tokio::spawn(admin.run());
+
+    assert_eq!(client.send(&format!("maint default {BACKEND}")).await, "ok");
+    eventually("maintenance to reach the other node", || async {
+        second_backends.read().await.select_backend().is_none()
+    })
+    .await;
+    assert!(first_backends.read().await.select_backend().is_none());
+
+    assert_eq!(
+        client.send(&format!("drain default {BACKEND} 60000")).await,
+        "ok"
+    );
+    eventually("the drain to replace it", || async {
+        second
+            .backend_override(DEFAULT_POOL, backend())
+            .is_some_and(|o| o.mode == OverrideMode::Drain)
+    })
+    .await;
+
+    assert_eq!(client.send(&format!("clear default {BACKEND}")).await, "ok");
+    eventually("the backend to be back in rotation", || async {
+        second_backends.read().await.select_backend().is_some()
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn bad_admin_commands_are_answered_with_an_error() {
+    let (layer, backends) = start("node-0", &[]).await;
+    let mut pools = BackendPools::new();
+    pools.insert(DEFAULT_POOL, backends);
+    let admin = AdminServer::bind("127.0.0.1:0".parse().unwrap(), layer.clone(), pools)
+        .await
+        .unwrap();
+    let mut client = Client::connect(admin.local_addr().unwrap()).await;
+    tokio::spawn(admin.run());
+
+    for command in [
+        "reboot default 127.0.0.1:9".to_string(),
+        "drain default".to_string(),
+        format!("drain default {BACKEND} soon"),
+        format!("drain web {BACKEND}"),
+        "drain default 127.0.0.1:10".to_string(),
+        format!("clear default {BACKEND} 1000"),
+    ] {
+        let reply = client.send(&command).await;
+        assert!(reply.starts_with("error: "), "{command}: {reply}");
+    }
+    // nothing was set, and the connection still works
+    assert!(layer.backend_override(DEFAULT_POOL, backend()).is_none());
+    assert_eq!(
+        client.send(&format!("enable default {BACKEND}")).await,
+        "ok"
+    );
+    assert_eq!(
+        layer
+            .backend_override(DEFAULT_POOL, backend())
+            .map(|o| o.mode),
+        Some(OverrideMode::Enable)
+    );
+}
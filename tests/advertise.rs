@@ -0,0 +1,50 @@
+mod common;
+
+use common::{backend_pool, gossip_config};
+use flux::gossip::{GossipLayer, MemberId, advertise_addr};
+use std::net::SocketAddr;
+
+async fn layer(settings: &str) -> GossipLayer {
+    let config = gossip_config(&[], settings);
+    let id = MemberId::new("node-0".to_string());
+    GossipLayer::new(id, &config, backend_pool("node-0", vec![]))
+        .await
+        .unwrap()
+}
+
+#[test]
+fn specific_addresses_are_advertised_as_is() {
+    let addr: SocketAddr = "127.0.0.1:7946".parse().unwrap();
+    assert_eq!(advertise_addr(addr), addr);
+}
+
+#[test]
+fn wildcards_become_an_interface_address() {
+    let addr = advertise_addr("0.0.0.0:7946".parse().unwrap());
+    assert!(!addr.ip().is_unspecified());
+    assert!(addr.is_ipv4());
+    assert_eq!(addr.port(), 7946);
+}
+
+#[tokio::test]
+async fn a_wildcard_bind_advertises_the_port_it_got() {
+    let layer = layer(r#"bind_addr = "0.0.0.0:0""#).await;
+    let addr = layer.local_member().addr;
+    assert!(!addr.ip().is_unspecified());
+    assert_ne!(addr.port(), 0);
+}
+
+#[tokio::test]
+async fn an_explicit_advertise_addr_wins() {
+    let layer = layer(
+        r#"
+        bind_addr = "0.0.0.0:0"
+        advertise_addr = "203.0.113.7:7946"
+        "#,
+    )
+    .await;
+    assert_eq!(
+        layer.local_member().addr,
+        "203.0.113.7:7946".parse().unwrap()
+    );
+}
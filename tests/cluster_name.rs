@@ -0,0 +1,33 @@
+mod common;
+
+use common::{backend_pool, eventually, gossip_config, start_node};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+#[tokio::test]
+async fn nodes_of_another_cluster_are_rejected() {
+    let ours = start_node(
+        "node-0",
+        &gossip_config(&[], "cluster_name = \"flux\""),
+        backend_pool("node-0", vec![]),
+    )
+    .await;
+    let theirs = start_node(
+        "node-1",
+        &gossip_config(&[ours.local_member().addr], "cluster_name = \"staging\""),
+        backend_pool("node-1", vec![]),
+    )
+    .await;
+    let _ = theirs.join_cluster().await;
+
+    let metrics = ours.metrics();
+    eventually("a foreign message to be counted", || async {
+        metrics.foreign_cluster_messages.load(Ordering::Relaxed) > 0
+    })
+    .await;
+    // it keeps trying, and never gets in
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert!(ours.alive_members().is_empty());
+    assert!(theirs.alive_members().is_empty());
+    assert!(metrics.foreign_cluster_messages.load(Ordering::Relaxed) > 1);
+}
@@ -0,0 +1,114 @@
+// helpers shared by the integration tests, each of which only uses some
+#![allow(dead_code)]
+
+use flux::backend::{Backend, BackendPool, BackendPools, SharedBackendPool};
+use flux::config::GossipConfig;
+use flux::gossip::{GossipLayer, HybridClock, MemberId, MemoryNetwork, node_addr};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tokio::time::Instant;
+
+// short rounds and timeouts, so test clusters form and notice failures fast
+const BASE_CONFIG: &str = r#"
+bind_addr = "127.0.0.1:0"
+gossip_interval_ms = 50
+ping_timeout_ms = 50
+suspect_timeout_ms = 500
+"#;
+
+// the base config with `settings`, more TOML, on top of it
+pub fn gossip_config(seed_nodes: &[SocketAddr], settings: &str) -> GossipConfig {
+    let mut table: toml::Table = toml::from_str(BASE_CONFIG).unwrap();
+    table.extend(toml::from_str::<toml::Table>(settings).unwrap());
+    let mut config: GossipConfig = toml::Value::Table(table).try_into().unwrap();
+    config.seed_nodes = seed_nodes.to_vec();
+    config
+}
+
+pub fn backend_pool(name: &str, backends: Vec<Backend>) -> SharedBackendPool {
+    Arc::new(RwLock::new(BackendPool::new(
+        backends,
+        MemberId::new(name.to_string()),
+        Arc::new(HybridClock::new()),
+    )))
+}
+
+// a running layer on a UDP socket of its own, it still has to join
+pub async fn start_node(
+    name: &str,
+    config: &GossipConfig,
+    backend_pools: impl Into<BackendPools>,
+) -> GossipLayer {
+    let layer = GossipLayer::new(MemberId::new(name.to_string()), config, backend_pools)
+        .await
+        .unwrap();
+    let runner = layer.clone();
+    tokio::spawn(async move { runner.run().await });
+    layer
+}
+
+// a layer named node-<index> at node_addr(index), not running yet
+pub async fn memory_layer(
+    network: &MemoryNetwork,
+    index: usize,
+    config: &GossipConfig,
+    backend_pools: impl Into<BackendPools>,
+) -> GossipLayer {
+    let id = MemberId::new(format!("node-{index}"));
+    let transport = Arc::new(network.bind(node_addr(index)).unwrap());
+    GossipLayer::with_transport(id, config, backend_pools, transport)
+        .await
+        .unwrap()
+}
+
+// the same, running. it still has to join
+pub async fn start_memory_node(
+    network: &MemoryNetwork,
+    index: usize,
+    config: &GossipConfig,
+    backend_pools: impl Into<BackendPools>,
+) -> GossipLayer {
+    let layer = memory_layer(network, index, config, backend_pools).await;
+    let runner = layer.clone();
+    tokio::spawn(async move { runner.run().await });
+    layer
+}
+
+pub async fn eventually<F, Fut>(what: &str, mut check: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !check().await {
+        assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+pub fn alive_counts(layers: &[GossipLayer]) -> Vec<usize> {
+    layers
+        .iter()
+        .map(|layer| layer.alive_members().len())
+        .collect()
+}
+
+pub async fn wait_for_alive_counts(layers: &[GossipLayer], expected: &[usize], timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let counts = alive_counts(layers);
+        if counts == expected {
+            return;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "cluster did not converge, alive counts {:?} expected {:?}",
+            counts,
+            expected
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
@@ -0,0 +1,181 @@
+mod common;
+
+use common::{backend_pool, eventually, gossip_config};
+use flux::backend::{ActiveConnection, Backend, BackendPool, DEFAULT_POOL, SharedBackendPool};
+use flux::gossip::{HybridClock, MemberId, decode_load, encode_load, load_key};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tokio::time::Instant;
+
+const BACKEND: &str = "127.0.0.1:9";
+
+fn backend() -> SocketAddr {
+    BACKEND.parse().unwrap()
+}
+
+fn pool(name: &str, max_connections: u32) -> BackendPool {
+    BackendPool::new(
+        vec![Backend {
+            max_connections: Some(max_connections),
+            ..Backend::new(backend(), 1)
+        }],
+        MemberId::new(name.to_string()),
+        Arc::new(HybridClock::new()),
+    )
+}
+
+fn acquire_all(pool: &BackendPool) -> Vec<ActiveConnection> {
+    std::iter::from_fn(|| pool.acquire_backend()).collect()
+}
+
+struct Node {
+    addr: SocketAddr,
+    backends: SharedBackendPool,
+}
+
+async fn start_node(name: &str, seed_nodes: &[SocketAddr], max_connections: u32) -> Node {
+    let config = gossip_config(seed_nodes, "load_report_interval_ms = 100");
+    let backends = Arc::new(RwLock::new(pool(name, max_connections)));
+    let layer = common::start_node(name, &config, backends.clone()).await;
+    let addr = layer.local_member().addr;
+
+    let reporter = layer.clone();
+    tokio::spawn(async move { reporter.start_load_report_loop().await });
+    layer.join_cluster().await.unwrap();
+
+    Node { addr, backends }
+}
+
+#[test]
+fn connections_count_against_the_limit_until_dropped() {
+    let pool = pool("node-0", 3);
+    let mut connections = acquire_all(&pool);
+    assert_eq!(connections.len(), 3);
+    assert_eq!(pool.local_connections(), vec![(backend(), 3)]);
+
+    connections.pop();
+    assert!(pool.acquire_backend().is_some());
+    assert_eq!(pool.local_connections(), vec![(backend(), 2)]);
+}
+
+#[test]
+fn only_limited_backends_are_reported() {
+    let unlimited: SocketAddr = "127.0.0.1:10".parse().unwrap();
+    let pool = BackendPool::new(
+        vec![
+            Backend {
+                max_connections: Some(5),
+                ..Backend::new(backend(), 1)
+            },
+            Backend::new(unlimited, 1),
+        ],
+        MemberId::new("node-0".to_string()),
+        Arc::new(HybridClock::new()),
+    );
+    let connections: Vec<_> = (0..4).filter_map(|_| pool.acquire_backend()).collect();
+    assert_eq!(connections.len(), 4);
+    assert_eq!(pool.local_connections(), vec![(backend(), 2)]);
+}
+
+#[test]
+fn load_reports_keep_the_busiest_backends_that_fit() {
+    let connections: Vec<(String, SocketAddr, u32)> = (0..100)
+        .map(|i| {
+            let addr = SocketAddr::from(([127, 0, 0, 1], 10000 + i));
+            (DEFAULT_POOL.to_string(), addr, 1 + i as u32)
+        })
+        .collect();
+    let encoded = encode_load(&connections);
+    assert!(encoded.len() <= 400);
+
+    let decoded = decode_load(&encoded);
+    assert!(decoded.len() > 1 && decoded.len() < connections.len());
+    let busiest: Vec<_> = connections
+        .iter()
+        .rev()
+        .take(decoded.len())
+        .cloned()
+        .collect();
+    assert_eq!(decoded, busiest);
+}
+
+#[tokio::test]
+async fn load_of_many_busy_backends_is_still_published() {
+    // a connection to each of them is more than a kv entry holds
+    let backends = (0..40)
+        .map(|i| Backend {
+            max_connections: Some(10),
+            ..Backend::new(SocketAddr::from(([127, 0, 0, 1], 10000 + i)), 1)
+        })
+        .collect();
+    let backends = backend_pool("node-0", backends);
+    let config = gossip_config(&[], "load_report_interval_ms = 100");
+    let layer = common::start_node("node-0", &config, backends.clone()).await;
+    let reporter = layer.clone();
+    tokio::spawn(async move { reporter.start_load_report_loop().await });
+
+    let connections: Vec<_> = {
+        let pool = backends.read().await;
+        (0..40).filter_map(|_| pool.acquire_backend()).collect()
+    };
+    assert_eq!(connections.len(), 40);
+    let key = load_key(&layer.local_member().id);
+    eventually("the load to be published", || async {
+        layer
+            .kv_get(&key)
+            .is_some_and(|report| !decode_load(&report).is_empty())
+    })
+    .await;
+}
+
+#[test]
+fn headroom_is_shared_by_traffic() {
+    let mut pool = pool("node-0", 10);
+
+    // idle next to a node with 6 connections: a small share of the 4 left
+    pool.rebalance(&HashMap::from([(backend(), 6)]), 2);
+    let connections = acquire_all(&pool);
+    assert_eq!(connections.len(), 1);
+
+    // the other node went quiet, most of what is left is ours
+    pool.rebalance(&HashMap::from([(backend(), 0)]), 2);
+    let more = acquire_all(&pool);
+    assert_eq!(connections.len() + more.len(), 7);
+
+    // the others took the rest
+    drop(more);
+    pool.rebalance(&HashMap::from([(backend(), 9)]), 2);
+    assert!(pool.acquire_backend().is_none());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn limit_holds_across_the_cluster() {
+    let busy = start_node("node-0", &[], 4).await;
+    let idle = start_node("node-1", &[busy.addr], 4).await;
+
+    // the busy node grows its share as its counts reach the other node
+    let mut connections = Vec::new();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while connections.len() < 4 {
+        assert!(
+            Instant::now() < deadline,
+            "busy node never got the whole limit"
+        );
+        connections.extend(acquire_all(&*busy.backends.read().await));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    eventually("idle node to run out of budget", || async {
+        idle.backends.read().await.select_backend().is_none()
+    })
+    .await;
+    assert!(busy.backends.read().await.acquire_backend().is_none());
+
+    connections.clear();
+    eventually("idle node to get budget back", || async {
+        idle.backends.read().await.select_backend().is_some()
+    })
+    .await;
+}
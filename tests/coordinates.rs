@@ -0,0 +1,58 @@
+mod common;
+
+use common::{backend_pool, eventually, gossip_config};
+use flux::gossip::{Coordinate, GossipLayer, MemberId};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+async fn start_node(name: &str, seed_nodes: &[SocketAddr]) -> (GossipLayer, SocketAddr) {
+    let config = gossip_config(seed_nodes, "gossip_interval_ms = 20");
+    let layer = common::start_node(name, &config, backend_pool(name, vec![])).await;
+    let addr = layer.local_member().addr;
+    (layer, addr)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn coordinates_converge_on_loopback() {
+    let (seed, seed_addr) = start_node("node-0", &[]).await;
+    let mut nodes = vec![seed];
+    for i in 1..3 {
+        nodes.push(start_node(&format!("node-{i}"), &[seed_addr]).await.0);
+    }
+    futures::future::join_all(nodes.iter().map(|layer| layer.join_cluster())).await;
+
+    let ids: Vec<MemberId> = (0..3).map(|i| MemberId::new(format!("node-{i}"))).collect();
+    let initial_error = nodes[0].coordinate().error;
+
+    eventually("coordinates to settle", || async {
+        for (i, layer) in nodes.iter().enumerate() {
+            if layer.coordinate().error >= initial_error {
+                return false;
+            }
+            for (j, id) in ids.iter().enumerate() {
+                if i == j {
+                    continue;
+                }
+                match layer.estimate_rtt(id) {
+                    // everything is on loopback
+                    Some(rtt) if rtt < Duration::from_millis(50) => {}
+                    _ => return false,
+                }
+            }
+        }
+        true
+    })
+    .await;
+
+    assert!(nodes[0].member_coordinate(&ids[1]).is_some());
+}
+
+#[test]
+fn distances_saturate_instead_of_overflowing() {
+    let far = Coordinate {
+        vec: [f64::MAX; 8],
+        ..Default::default()
+    };
+    assert!(!far.is_valid());
+    assert_eq!(far.distance_to(&Coordinate::default()), Duration::MAX);
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use common::{backend_pool, gossip_config, wait_for_alive_counts};
+use flux::gossip::{GossipLayer, MemberId, UserEvent};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+
+async fn start_node(name: &str, seed_nodes: &[SocketAddr]) -> GossipLayer {
+    let config = gossip_config(seed_nodes, "");
+    common::start_node(name, &config, backend_pool(name, vec![])).await
+}
+
+async fn collect(rx: &mut broadcast::Receiver<UserEvent>, until: Instant) -> Vec<UserEvent> {
+    let mut events = Vec::new();
+    while let Ok(Ok(event)) = tokio::time::timeout_at(until, rx.recv()).await {
+        events.push(event);
+    }
+    events
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn events_reach_every_node_once() {
+    let seed = start_node("node-0", &[]).await;
+    let seed_addr = seed.local_member().addr;
+    let mut nodes = vec![seed];
+    for i in 1..5 {
+        nodes.push(start_node(&format!("node-{i}"), &[seed_addr]).await);
+    }
+    futures::future::join_all(nodes.iter().map(|layer| layer.join_cluster())).await;
+    wait_for_alive_counts(&nodes, &[4; 5], Duration::from_secs(10)).await;
+
+    let mut receivers: Vec<_> = nodes.iter().map(|layer| layer.subscribe_events()).collect();
+
+    nodes[0]
+        .broadcast_event("clear-cache", b"tenant-1".to_vec())
+        .await
+        .unwrap();
+    nodes[3]
+        .broadcast_event("deploy-started", vec![])
+        .await
+        .unwrap();
+
+    // long enough for every retransmission to have happened
+    let until = Instant::now() + Duration::from_secs(3);
+    for rx in &mut receivers {
+        let events = collect(rx, until).await;
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for event in &events {
+            *counts.entry(event.name.as_str()).or_default() += 1;
+        }
+        assert_eq!(counts.get("clear-cache"), Some(&1), "{:?}", events);
+        assert_eq!(counts.get("deploy-started"), Some(&1), "{:?}", events);
+        assert_eq!(events.len(), 2);
+
+        let clear = events.iter().find(|e| e.name == "clear-cache").unwrap();
+        assert_eq!(clear.payload, b"tenant-1");
+        assert_eq!(clear.origin, MemberId::new("node-0".to_string()));
+    }
+}
+
+#[tokio::test]
+async fn oversized_events_are_rejected() {
+    let layer = start_node("node-0", &[]).await;
+    assert!(layer.broadcast_event("big", vec![0; 1024]).await.is_err());
+}
@@ -0,0 +1,77 @@
+use flux::backend::{Backend, BackendPool, DEFAULT_POOL};
+use flux::gossip::{BackendUpdate, HybridClock, HybridTimestamp, MemberId};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn wall_ms(offset: Duration) -> u64 {
+    (SystemTime::now() + offset)
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+fn at(wall_ms: u64, logical: u32) -> HybridTimestamp {
+    HybridTimestamp { wall_ms, logical }
+}
+
+#[test]
+fn local_timestamps_only_go_up() {
+    let clock = HybridClock::new();
+    let mut last = clock.now();
+    for _ in 0..1000 {
+        let next = clock.now();
+        assert!(next > last);
+        last = next;
+    }
+}
+
+#[test]
+fn observed_timestamps_order_before_later_ones() {
+    let clock = HybridClock::new();
+
+    // a peer a little ahead of us, within the same millisecond
+    let remote = at(wall_ms(Duration::from_secs(5)), 7);
+    assert!(clock.observe(remote));
+    let next = clock.now();
+    assert!(next > remote);
+    assert_eq!(next.wall_ms, remote.wall_ms);
+
+    // one behind us doesn't move the clock back
+    assert!(clock.observe(at(1, 0)));
+    assert!(clock.now() > next);
+}
+
+#[test]
+fn timestamps_too_far_ahead_are_rejected() {
+    let clock = HybridClock::new();
+    let skewed = at(wall_ms(Duration::from_secs(3600)), 0);
+    assert!(!clock.observe(skewed));
+    assert!(clock.now() < skewed);
+}
+
+#[test]
+fn backend_updates_from_a_skewed_clock_are_dropped() {
+    let backend: SocketAddr = "127.0.0.1:9".parse().unwrap();
+    let clock = Arc::new(HybridClock::new());
+    let mut pool = BackendPool::new(
+        vec![Backend::new(backend, 1)],
+        MemberId::new("node-0".to_string()),
+        clock.clone(),
+    );
+    let update = |is_healthy, version| BackendUpdate {
+        pool: DEFAULT_POOL.to_string(),
+        backend_addr: backend,
+        is_healthy,
+        from_member: MemberId::new("node-1".to_string()),
+        version,
+    };
+
+    pool.apply_backend_update(&update(false, at(wall_ms(Duration::from_secs(3600)), 0)));
+    assert!(pool.select_backend().is_some());
+    assert!(pool.get_backend_health_updates(DEFAULT_POOL).is_empty());
+
+    // so an update from a correct clock still gets through
+    pool.apply_backend_update(&update(false, clock.now()));
+    assert!(pool.select_backend().is_none());
+}
@@ -0,0 +1,152 @@
+mod common;
+
+use common::{backend_pool, eventually, gossip_config};
+use flux::backend::{Backend, DEFAULT_POOL, SharedBackendPool};
+use flux::gossip::{GossipLayer, MemberId, load_key, rate_limit_key};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+const BACKEND: &str = "127.0.0.1:9";
+
+struct Node {
+    addr: SocketAddr,
+    layer: GossipLayer,
+    backends: SharedBackendPool,
+}
+
+async fn start_node(name: &str, seed_nodes: &[SocketAddr]) -> Node {
+    let config = gossip_config(seed_nodes, "push_pull_interval_ms = 200");
+    let backends = backend_pool(name, vec![Backend::new(BACKEND.parse().unwrap(), 1)]);
+    let layer = common::start_node(name, &config, backends.clone()).await;
+    let addr = layer.local_member().addr;
+    layer.join_cluster().await.unwrap();
+
+    Node {
+        addr,
+        layer,
+        backends,
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn writes_and_deletes_replicate() {
+    let seed = start_node("node-0", &[]).await;
+    let mut nodes = vec![seed];
+    for i in 1..4 {
+        nodes.push(start_node(&format!("node-{i}"), &[nodes[0].addr]).await);
+    }
+
+    nodes[0]
+        .layer
+        .kv_set("metrics/rps", b"1200".to_vec())
+        .await
+        .unwrap();
+    eventually("write to replicate", || async {
+        nodes
+            .iter()
+            .all(|n| n.layer.kv_get("metrics/rps").as_deref() == Some(b"1200".as_slice()))
+    })
+    .await;
+
+    // a later write from another node wins everywhere
+    nodes[2]
+        .layer
+        .kv_set("metrics/rps", b"900".to_vec())
+        .await
+        .unwrap();
+    eventually("overwrite to replicate", || async {
+        nodes
+            .iter()
+            .all(|n| n.layer.kv_get("metrics/rps").as_deref() == Some(b"900".as_slice()))
+    })
+    .await;
+
+    nodes[1].layer.kv_delete("metrics/rps").await;
+    eventually("delete to replicate", || async {
+        nodes
+            .iter()
+            .all(|n| n.layer.kv_get("metrics/rps").is_none())
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn late_joiner_catches_up_through_push_pull() {
+    let seed = start_node("node-0", &[]).await;
+    let other = start_node("node-1", &[seed.addr]).await;
+
+    seed.layer.kv_set("config/a", b"1".to_vec()).await.unwrap();
+    other.layer.kv_set("config/b", b"2".to_vec()).await.unwrap();
+
+    // long after the piggybacked updates stopped being retransmitted
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let late = start_node("node-2", &[seed.addr]).await;
+
+    eventually("push-pull to deliver the store", || async {
+        late.layer.kv_scan("config/").len() == 2
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn drain_flag_takes_backend_out_everywhere() {
+    let seed = start_node("node-0", &[]).await;
+    let mut nodes = vec![seed];
+    for i in 1..3 {
+        nodes.push(start_node(&format!("node-{i}"), &[nodes[0].addr]).await);
+    }
+    let backend: SocketAddr = BACKEND.parse().unwrap();
+
+    for node in &nodes {
+        assert!(node.backends.read().await.select_backend().is_some());
+    }
+
+    nodes[1]
+        .layer
+        .drain_backend(DEFAULT_POOL, backend, true)
+        .await
+        .unwrap();
+    eventually("drain to apply", || async {
+        for node in &nodes {
+            if node.backends.read().await.select_backend().is_some() {
+                return false;
+            }
+        }
+        true
+    })
+    .await;
+
+    nodes[2]
+        .layer
+        .drain_backend(DEFAULT_POOL, backend, false)
+        .await
+        .unwrap();
+    eventually("undrain to apply", || async {
+        for node in &nodes {
+            if node.backends.read().await.select_backend().is_none() {
+                return false;
+            }
+        }
+        true
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn reports_of_departed_members_are_reaped() {
+    let node = start_node("node-0", &[]).await;
+    let ours = load_key(&node.layer.local_member().id);
+    let departed = [
+        load_key(&MemberId::new("node-9".to_string())),
+        rate_limit_key("web", &MemberId::new("node-9".to_string())),
+    ];
+    for key in departed.iter().chain([&ours]) {
+        node.layer.kv_set(key, b"1".to_vec()).await.unwrap();
+    }
+
+    node.layer.reap_reports().await;
+    for key in &departed {
+        assert_eq!(node.layer.kv_get(key), None);
+    }
+    assert!(node.layer.kv_get(&ours).is_some());
+}
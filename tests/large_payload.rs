@@ -0,0 +1,88 @@
+mod common;
+
+use common::{backend_pool, gossip_config};
+use flux::gossip::{GossipLayer, MemberId};
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::time::Instant;
+
+async fn start_node(
+    name: &str,
+    seed_nodes: &[SocketAddr],
+    settings: &str,
+) -> (GossipLayer, SocketAddr) {
+    let config = gossip_config(seed_nodes, &format!("gossip_interval_ms = 100\n{settings}"));
+    let layer = common::start_node(name, &config, backend_pool(name, vec![])).await;
+    let addr = layer.local_member().addr;
+    (layer, addr)
+}
+
+// a tag far larger than a UDP packet, that lz4 can't shrink below one either
+fn large_tag() -> String {
+    let mut x: u64 = 0x2545_f491_4f6c_dd1d;
+    (0..8192)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            char::from(b'a' + (x % 26) as u8)
+        })
+        .collect()
+}
+
+async fn wait_for_tag(
+    layer: &GossipLayer,
+    id: &MemberId,
+    expected: &str,
+    timeout: Duration,
+) -> bool {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        let member = layer.member(id);
+        if member.is_some_and(|m| m.tag("blob") == Some(expected)) {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    false
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn oversized_updates_are_streamed() {
+    let blob = large_tag();
+    let (seed, seed_addr) = start_node("seed", &[], "").await;
+    let (layer, _) = start_node("large", &[seed_addr], &format!("[tags]\nblob = \"{blob}\"")).await;
+    layer.join_cluster().await.unwrap();
+
+    assert!(
+        wait_for_tag(
+            &seed,
+            &MemberId::new("large".to_string()),
+            &blob,
+            Duration::from_secs(10)
+        )
+        .await,
+        "seed never received the large member's tags"
+    );
+    assert!(layer.metrics().stream_messages.load(Ordering::Relaxed) > 0);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn oversized_updates_are_trimmed_without_stream_fallback() {
+    let blob = large_tag();
+    let (seed, seed_addr) = start_node("seed", &[], "stream_fallback = false").await;
+    let (layer, _) = start_node(
+        "large",
+        &[seed_addr],
+        &format!("stream_fallback = false\n[tags]\nblob = \"{blob}\""),
+    )
+    .await;
+    layer.join_cluster().await.unwrap();
+
+    let large_id = MemberId::new("large".to_string());
+    assert!(seed.member(&large_id).is_some());
+    assert!(!wait_for_tag(&seed, &large_id, &blob, Duration::from_secs(2)).await);
+    assert!(layer.metrics().trimmed_messages.load(Ordering::Relaxed) > 0);
+    assert_eq!(layer.metrics().stream_messages.load(Ordering::Relaxed), 0);
+}
@@ -0,0 +1,161 @@
+mod common;
+
+use common::{backend_pool, eventually, gossip_config, start_memory_node};
+use flux::gossip::{GossipLayer, Member, MemberId, MemberState, MemoryNetwork, node_addr};
+use flux::leader::{LeaderElection, Term};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+
+const LEASE: Duration = Duration::from_millis(300);
+
+fn member(name: &str, incarnation: u64) -> Member {
+    Member {
+        id: MemberId::new(name.to_string()),
+        addr: node_addr(0),
+        state: MemberState::Alive,
+        incarnation,
+        tags: Default::default(),
+    }
+}
+
+// counts the running copies of a task, aborted ones go away with the guard
+struct Running(Arc<AtomicUsize>);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn register(election: &LeaderElection) -> Arc<AtomicUsize> {
+    let running = Arc::new(AtomicUsize::new(0));
+    let counter = running.clone();
+    election.register("singleton", move |_: Term| {
+        let counter = counter.clone();
+        async move {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let _guard = Running(counter);
+            futures::future::pending::<()>().await;
+        }
+    });
+    running
+}
+
+// lets spawned and aborted tasks get polled
+async fn settle() {
+    tokio::time::sleep(Duration::from_millis(1)).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn lowest_id_leads_after_a_lease() {
+    let election = LeaderElection::new(LEASE);
+    let running = register(&election);
+    let local = MemberId::new("node-1".to_string());
+
+    election.update(Instant::now(), &local, 0, &[member("node-2", 0)]);
+    assert_eq!(election.leader(), Some(local.clone()));
+    assert!(!election.is_leader());
+
+    tokio::time::sleep(LEASE).await;
+    election.update(Instant::now(), &local, 0, &[member("node-2", 0)]);
+    settle().await;
+    assert!(election.is_leader());
+    assert_eq!(running.load(Ordering::SeqCst), 1);
+
+    // a lower id shows up, we step down at once
+    election.update(Instant::now(), &local, 0, &[member("node-0", 0)]);
+    settle().await;
+    assert_eq!(election.leader(), Some(MemberId::new("node-0".to_string())));
+    assert!(!election.is_leader());
+    assert_eq!(running.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_refuted_suspicion_is_a_new_term() {
+    let election = LeaderElection::new(LEASE);
+    let running = register(&election);
+    let local = MemberId::new("node-0".to_string());
+
+    election.update(Instant::now(), &local, 1, &[]);
+    tokio::time::sleep(LEASE).await;
+    election.update(Instant::now(), &local, 1, &[]);
+    assert_eq!(election.term().map(|term| term.incarnation), Some(1));
+
+    // we had to refute a suspicion, someone else may have led meanwhile
+    election.update(Instant::now(), &local, 2, &[]);
+    settle().await;
+    assert_eq!(election.term(), None);
+    assert_eq!(running.load(Ordering::SeqCst), 0);
+
+    tokio::time::sleep(LEASE).await;
+    election.update(Instant::now(), &local, 2, &[]);
+    settle().await;
+    assert_eq!(election.term().map(|term| term.incarnation), Some(2));
+    assert_eq!(running.load(Ordering::SeqCst), 1);
+}
+
+async fn start_node(network: &MemoryNetwork, index: usize) -> (GossipLayer, Arc<AtomicUsize>) {
+    let seeds = if index > 0 {
+        vec![node_addr(0)]
+    } else {
+        vec![]
+    };
+    let config = gossip_config(&seeds, "");
+    let name = format!("node-{index}");
+    let layer = start_memory_node(network, index, &config, backend_pool(&name, vec![])).await;
+    layer.join_cluster().await.unwrap();
+
+    let election = Arc::new(LeaderElection::new(LEASE));
+    let running = register(&election);
+    let elector = layer.clone();
+    tokio::spawn(async move { elector.start_leader_election_loop(election).await });
+    (layer, running)
+}
+
+fn running(nodes: &[Arc<AtomicUsize>]) -> Vec<usize> {
+    nodes
+        .iter()
+        .map(|running| running.load(Ordering::SeqCst))
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn task_moves_when_the_leader_fails() {
+    let network = MemoryNetwork::new(11);
+    let mut nodes = Vec::new();
+    for index in 0..3 {
+        nodes.push(start_node(&network, index).await.1);
+    }
+
+    eventually("the lowest id to run the task", || async {
+        running(&nodes) == [1, 0, 0]
+    })
+    .await;
+
+    network.disconnect(node_addr(0));
+    eventually("the next lowest to take over", || async {
+        running(&nodes[1..]) == [1, 0]
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn tag_changes_leave_the_task_running() {
+    let network = MemoryNetwork::new(12);
+    let (layer, running) = start_node(&network, 0).await;
+    eventually("the task to start", || async {
+        running.load(Ordering::SeqCst) == 1
+    })
+    .await;
+
+    let mut tags = layer.local_member().tags;
+    tags.insert("role".to_string(), "edge".to_string());
+    layer.set_tags(tags);
+    assert_eq!(layer.local_member().incarnation, 1);
+
+    // a few election rounds, still well within a lease
+    tokio::time::sleep(LEASE / 2).await;
+    assert_eq!(running.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,146 @@
+use flux::gossip::{
+    Awareness, Member, MemberId, MemberList, MemberState, MemberUpdate, Suspicion, Tags, node_addr,
+};
+use std::time::Duration;
+use tokio::time::Instant;
+
+fn member(name: &str) -> MemberId {
+    MemberId::new(name.to_string())
+}
+
+fn node(index: usize) -> MemberId {
+    member(&format!("node-{index}"))
+}
+
+fn alive(index: usize) -> Member {
+    Member {
+        id: node(index),
+        addr: node_addr(index),
+        state: MemberState::Alive,
+        incarnation: 0,
+        tags: Tags::new(),
+    }
+}
+
+// node-0's view of node-1 to node-5. suspicions last 6s, down to 1s with
+// 3 confirmations
+fn member_list(now: Instant) -> MemberList {
+    let mut members = MemberList::new(alive(0), Duration::from_secs(1), 6, 3, now);
+    for index in 1..6 {
+        members.upsert_member(alive(index), now);
+    }
+    members
+}
+
+fn suspected_by(accuser: usize) -> MemberUpdate {
+    MemberUpdate {
+        state: MemberState::Suspect,
+        accuser: Some(node(accuser)),
+        ..MemberUpdate::from(&alive(5))
+    }
+}
+
+#[test]
+fn suspicion_timeout_shrinks_with_distinct_confirmations() {
+    let min = Duration::from_secs(2);
+    let max = Duration::from_secs(12);
+    let mut suspicion = Suspicion::new(member("accuser"), 3, min, max, Instant::now());
+    assert_eq!(suspicion.timeout(), max);
+
+    // the accuser and repeated confirmations don't count
+    assert!(!suspicion.confirm(&member("accuser")));
+    assert!(suspicion.confirm(&member("b")));
+    assert!(!suspicion.confirm(&member("b")));
+    assert_eq!(suspicion.confirmations(), 1);
+
+    let after_one = suspicion.timeout();
+    assert!(after_one < max && after_one > min);
+
+    assert!(suspicion.confirm(&member("c")));
+    let after_two = suspicion.timeout();
+    assert!(after_two < after_one && after_two > min);
+
+    assert!(suspicion.confirm(&member("d")));
+    assert_eq!(suspicion.timeout(), min);
+
+    // past the expected count nothing changes
+    assert!(!suspicion.confirm(&member("e")));
+    assert_eq!(suspicion.timeout(), min);
+}
+
+#[test]
+fn suspicion_expires_after_its_timeout() {
+    let now = Instant::now();
+    let suspicion = Suspicion::new(
+        member("accuser"),
+        3,
+        Duration::from_secs(2),
+        Duration::from_secs(12),
+        now,
+    );
+    assert!(!suspicion.is_expired(now + Duration::from_secs(11)));
+    assert!(suspicion.is_expired(now + Duration::from_secs(13)));
+}
+
+#[test]
+fn awareness_scales_probe_interval_and_timeout() {
+    let awareness = Awareness::new(8);
+    let interval = Duration::from_secs(1);
+    let timeout = Duration::from_millis(500);
+    assert_eq!(awareness.scale_timeout(interval), interval);
+
+    awareness.apply_delta(2);
+    assert_eq!(awareness.scale_timeout(interval), interval * 3);
+    assert_eq!(awareness.scale_timeout(timeout), timeout * 3);
+
+    // the score stays within 0..max
+    awareness.apply_delta(100);
+    assert_eq!(awareness.score(), 7);
+    assert_eq!(awareness.scale_timeout(interval), interval * 8);
+    awareness.apply_delta(-100);
+    assert_eq!(awareness.score(), 0);
+    assert_eq!(awareness.scale_timeout(timeout), timeout);
+}
+
+#[test]
+fn passing_a_suspicion_on_does_not_confirm_it() {
+    let now = Instant::now();
+    let mut members = member_list(now);
+
+    // node-1's suspicion, heard from three members
+    for from in 1..4 {
+        members.apply_member_update(suspected_by(1), &node(from), now);
+    }
+    members.check_suspect_timeouts(now + Duration::from_secs(4));
+    assert_eq!(
+        members.get_member_state(&node(5)),
+        Some(MemberState::Suspect)
+    );
+    // and passed on naming node-1
+    assert_eq!(
+        members.get_member_update(&node(5)).unwrap().accuser,
+        Some(node(1))
+    );
+
+    // two members that suspect it themselves, heard from the same one
+    for accuser in 2..4 {
+        members.apply_member_update(suspected_by(accuser), &node(4), now);
+    }
+    members.check_suspect_timeouts(now + Duration::from_secs(4));
+    assert_eq!(members.get_member_state(&node(5)), Some(MemberState::Dead));
+}
+
+#[test]
+fn our_own_failed_probe_confirms_a_suspicion() {
+    let now = Instant::now();
+    let mut members = member_list(now);
+    members.apply_member_update(suspected_by(1), &node(1), now);
+
+    members.mark_suspect(&node(5), now);
+    assert_eq!(
+        members.get_member_update(&node(5)).unwrap().accuser,
+        Some(node(0))
+    );
+    members.check_suspect_timeouts(now + Duration::from_secs(4));
+    assert_eq!(members.get_member_state(&node(5)), Some(MemberState::Dead));
+}
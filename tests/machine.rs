@@ -0,0 +1,357 @@
+use flux::config::GossipConfig;
+use flux::gossip::{
+    Coordinate, GossipMachine, GossipMessage, HybridClock, Member, MemberId, MemberState,
+    MemberUpdate, Output, Tags, node_addr,
+};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+fn config() -> GossipConfig {
+    toml::from_str(
+        r#"
+        bind_addr = "127.0.0.1:0"
+        gossip_interval_ms = 100
+        ping_timeout_ms = 50
+        suspect_timeout_ms = 500
+        "#,
+    )
+    .unwrap()
+}
+
+fn member(index: usize) -> Member {
+    Member {
+        id: MemberId::new(format!("node-{index}")),
+        addr: node_addr(index),
+        state: MemberState::Alive,
+        incarnation: 0,
+        tags: Tags::new(),
+    }
+}
+
+fn machine(index: usize, now: Instant) -> GossipMachine {
+    let mut machine =
+        GossipMachine::new(member(index), &config(), Arc::new(HybridClock::new()), now);
+    machine.seed_rng(index as u64);
+    machine
+}
+
+fn sends(machine: &mut GossipMachine) -> Vec<(SocketAddr, GossipMessage)> {
+    machine
+        .take_outputs()
+        .into_iter()
+        .filter_map(|output| match output {
+            Output::Send(addr, message) => Some((addr, message)),
+            _ => None,
+        })
+        .collect()
+}
+
+// hands every message `from` queued for one of `to` over to it, returns
+// what wasn't delivered
+fn deliver(
+    from: &mut GossipMachine,
+    to: &mut [&mut GossipMachine],
+    now: Instant,
+) -> Vec<(SocketAddr, GossipMessage)> {
+    let src_addr = from.local_member().addr;
+    let mut undelivered = Vec::new();
+    for (addr, message) in sends(from) {
+        match to.iter_mut().find(|m| m.local_member().addr == addr) {
+            Some(machine) => machine.handle_message(now, message, src_addr),
+            None => undelivered.push((addr, message)),
+        }
+    }
+    undelivered
+}
+
+// `a` joins through `others` and hears back from each of them
+fn introduce(a: &mut GossipMachine, others: &mut [&mut GossipMachine], now: Instant) {
+    let addrs: Vec<SocketAddr> = others.iter().map(|m| m.local_member().addr).collect();
+    a.join(&addrs);
+    deliver(a, others, now);
+    for other in others.iter_mut() {
+        deliver(other, &mut [&mut *a], now);
+    }
+}
+
+#[test]
+fn ping_is_acked() {
+    let now = Instant::now();
+    let (mut a, mut b) = (machine(0, now), machine(1, now));
+
+    a.join(&[node_addr(1)]);
+    let sent = sends(&mut a);
+    assert!(matches!(sent[..], [(addr, GossipMessage::Ping { .. })] if addr == node_addr(1)));
+    for (_, message) in sent {
+        b.handle_message(now, message, node_addr(0));
+    }
+    assert_eq!(
+        b.members().get_member_state(&member(0).id),
+        Some(MemberState::Alive)
+    );
+
+    let sent = sends(&mut b);
+    assert!(matches!(sent[..], [(addr, GossipMessage::Ack { .. })] if addr == node_addr(0)));
+    for (_, message) in sent {
+        a.handle_message(now, message, node_addr(1));
+    }
+    assert_eq!(
+        a.members().get_member_state(&member(1).id),
+        Some(MemberState::Alive)
+    );
+}
+
+#[test]
+fn missed_ack_leads_to_indirect_pings() {
+    let now = Instant::now();
+    let (mut a, mut b, mut c) = (machine(0, now), machine(1, now), machine(2, now));
+    introduce(&mut a, &mut [&mut b, &mut c], now);
+
+    // the first round probes someone, who never answers
+    let round = a.poll_timeout();
+    a.handle_timeout(round);
+    let target = sends(&mut a)
+        .into_iter()
+        .find_map(|(addr, message)| matches!(message, GossipMessage::Ping { .. }).then_some(addr))
+        .expect("no probe sent");
+    let prober = if target == node_addr(1) {
+        node_addr(2)
+    } else {
+        node_addr(1)
+    };
+
+    let round = a.poll_timeout();
+    assert_eq!(round, now + Duration::from_millis(200));
+    a.handle_timeout(round);
+    let indirect: Vec<_> = sends(&mut a)
+        .into_iter()
+        .filter_map(|(addr, message)| match message {
+            GossipMessage::IndirectPing { target_addr, .. } => Some((addr, target_addr)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(indirect, vec![(prober, target)]);
+}
+
+#[test]
+fn relayed_ping_is_acked_as_soon_as_the_target_answers() {
+    let now = Instant::now();
+    let (mut b, mut c) = (machine(1, now), machine(2, now));
+
+    b.handle_message(
+        now,
+        GossipMessage::IndirectPing {
+            from: member(0).id,
+            from_addr: node_addr(0),
+            target_id: member(2).id,
+            target_addr: node_addr(2),
+        },
+        node_addr(0),
+    );
+    assert!(deliver(&mut b, &mut [&mut c], now).is_empty());
+    deliver(&mut c, &mut [&mut b], now);
+
+    let sent = sends(&mut b);
+    assert!(matches!(
+        sent[..],
+        [(addr, GossipMessage::IndirectAck { target_responded: true, .. })] if addr == node_addr(0)
+    ));
+}
+
+#[test]
+fn relayed_ping_is_nacked_when_the_target_stays_silent() {
+    let now = Instant::now();
+    let mut b = machine(1, now);
+
+    b.handle_message(
+        now,
+        GossipMessage::IndirectPing {
+            from: member(0).id,
+            from_addr: node_addr(0),
+            target_id: member(2).id,
+            target_addr: node_addr(2),
+        },
+        node_addr(0),
+    );
+    sends(&mut b);
+
+    let nacks = |b: &mut GossipMachine| {
+        sends(b)
+            .into_iter()
+            .filter(|(_, message)| matches!(message, GossipMessage::IndirectAck { .. }))
+            .collect::<Vec<_>>()
+    };
+    b.handle_timeout(now + Duration::from_millis(499));
+    assert!(nacks(&mut b).is_empty());
+
+    b.handle_timeout(now + Duration::from_millis(500));
+    let nacks = nacks(&mut b);
+    assert!(matches!(
+        nacks[..],
+        [(addr, GossipMessage::IndirectAck { target_responded: false, .. })] if addr == node_addr(0)
+    ));
+}
+
+#[test]
+fn false_accusation_is_refuted() {
+    let now = Instant::now();
+    let mut a = machine(0, now);
+
+    let accused = Member {
+        state: MemberState::Suspect,
+        ..member(0)
+    };
+    a.handle_message(
+        now,
+        GossipMessage::Ping {
+            from: member(1).id,
+            from_addr: node_addr(1),
+            incarnation: 0,
+            coordinate: Default::default(),
+            member_updates: vec![MemberUpdate::from(&accused)],
+            backend_updates: vec![],
+        },
+        node_addr(1),
+    );
+
+    assert_eq!(a.local_member().incarnation, 1);
+    assert_eq!(a.awareness().score(), 1);
+
+    // the ack carries the refutation back to the accuser
+    let sent = sends(&mut a);
+    let [(_, GossipMessage::Ack { member_updates, .. })] = &sent[..] else {
+        panic!("expected a single ack, got {sent:?}");
+    };
+    assert!(member_updates.iter().any(|u| u.member_id == member(0).id
+        && u.state == MemberState::Alive
+        && u.incarnation == 1));
+}
+
+#[test]
+fn packets_from_another_cluster_are_counted_and_dropped() {
+    let now = Instant::now();
+    let mut a = machine(0, now);
+    let ping = GossipMessage::Ping {
+        from: member(1).id,
+        from_addr: node_addr(1),
+        incarnation: 0,
+        coordinate: Default::default(),
+        member_updates: vec![],
+        backend_updates: vec![],
+    };
+    let (packet, _) = ping.encode("staging", false).unwrap();
+
+    // far more senders than get a warning of their own
+    for i in 0..2000u16 {
+        let [hi, lo] = i.to_be_bytes();
+        a.handle_packet(now, &packet, SocketAddr::from(([10, 0, hi, lo], 7946)));
+    }
+    assert_eq!(
+        a.metrics()
+            .foreign_cluster_messages
+            .load(std::sync::atomic::Ordering::Relaxed),
+        2000
+    );
+    assert!(sends(&mut a).is_empty());
+    assert!(a.members().get_member(&member(1).id).is_none());
+}
+
+#[test]
+fn bad_coordinates_do_not_stop_probing() {
+    let now = Instant::now();
+    let (mut a, mut b, mut c, mut d) = (
+        machine(0, now),
+        machine(1, now),
+        machine(2, now),
+        machine(3, now),
+    );
+    introduce(&mut a, &mut [&mut b, &mut c, &mut d], now);
+
+    // one peer's coordinate is infinite, one's is far out, one's is NaN
+    let bad = [
+        Coordinate {
+            vec: [f64::INFINITY; 8],
+            ..Default::default()
+        },
+        Coordinate {
+            vec: [1.0e300; 8],
+            ..Default::default()
+        },
+        Coordinate {
+            height: f64::NAN,
+            ..Default::default()
+        },
+    ];
+    for (index, coordinate) in (1..4).zip(bad) {
+        a.handle_message(
+            now,
+            GossipMessage::Ping {
+                from: member(index).id,
+                from_addr: node_addr(index),
+                incarnation: 0,
+                coordinate,
+                member_updates: vec![],
+                backend_updates: vec![],
+            },
+            node_addr(index),
+        );
+    }
+    sends(&mut a);
+    for index in 1..4 {
+        let stored = a.members().get_coordinate(&member(index).id);
+        assert!(stored.is_none_or(|c| c.is_valid()));
+    }
+
+    // a probe that goes unanswered still gets its indirect pings out
+    let round = a.poll_timeout();
+    a.handle_timeout(round);
+    sends(&mut a);
+    let round = a.poll_timeout();
+    a.handle_timeout(round);
+    let indirect = sends(&mut a)
+        .into_iter()
+        .filter(|(_, message)| matches!(message, GossipMessage::IndirectPing { .. }))
+        .count();
+    assert_eq!(indirect, 2);
+}
+
+#[test]
+fn awareness_spaces_out_probe_rounds() {
+    let now = Instant::now();
+    let (mut a, mut b) = (machine(0, now), machine(1, now));
+    introduce(&mut a, &mut [&mut b], now);
+
+    // a refuted accusation means our own view may be off
+    let accused = Member {
+        state: MemberState::Suspect,
+        ..member(0)
+    };
+    a.handle_message(
+        now,
+        GossipMessage::Ping {
+            from: member(1).id,
+            from_addr: node_addr(1),
+            incarnation: 0,
+            coordinate: Default::default(),
+            member_updates: vec![MemberUpdate::from(&accused)],
+            backend_updates: vec![],
+        },
+        node_addr(1),
+    );
+    sends(&mut a);
+    assert_eq!(a.awareness().score(), 1);
+
+    let round = a.poll_timeout();
+    a.handle_timeout(round);
+    assert_eq!(a.poll_timeout(), round + Duration::from_millis(200));
+
+    // an answered probe brings it back
+    deliver(&mut a, &mut [&mut b], round);
+    deliver(&mut b, &mut [&mut a], round);
+    assert_eq!(a.awareness().score(), 0);
+    let round = a.poll_timeout();
+    a.handle_timeout(round);
+    assert_eq!(a.poll_timeout(), round + Duration::from_millis(100));
+}
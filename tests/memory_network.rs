@@ -0,0 +1,58 @@
+mod common;
+
+use common::{backend_pool, gossip_config, start_memory_node, wait_for_alive_counts};
+use flux::gossip::{GossipLayer, MemoryNetwork, NetworkConditions, node_addr};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+async fn start_node(network: &MemoryNetwork, i: usize) -> GossipLayer {
+    let config = gossip_config(
+        &[node_addr(0)],
+        r#"
+        gossip_interval_ms = 100
+        reconnect_interval_ms = 200
+        "#,
+    );
+    let layer = start_memory_node(
+        network,
+        i,
+        &config,
+        backend_pool(&format!("node-{i}"), vec![]),
+    )
+    .await;
+    let reconnector = layer.clone();
+    tokio::spawn(async move { reconnector.start_reconnect_loop().await });
+    layer
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn lossy_network_partitions_and_heals() {
+    let network = MemoryNetwork::new(7);
+    network.set_conditions(NetworkConditions {
+        latency: Duration::from_millis(2),
+        jitter: Duration::from_millis(5),
+        loss: 0.05,
+    });
+
+    let mut nodes = Vec::new();
+    for i in 0..5 {
+        nodes.push(start_node(&network, i).await);
+    }
+    futures::future::join_all(nodes.iter().map(|node| node.join_cluster())).await;
+    wait_for_alive_counts(&nodes, &[4; 5], Duration::from_secs(10)).await;
+
+    let majority: Vec<SocketAddr> = (0..3).map(node_addr).collect();
+    let minority: Vec<SocketAddr> = (3..5).map(node_addr).collect();
+    network.partition(&majority, &minority);
+    wait_for_alive_counts(&nodes, &[2, 2, 2, 1, 1], Duration::from_secs(20)).await;
+
+    network.heal();
+    wait_for_alive_counts(&nodes, &[4; 5], Duration::from_secs(20)).await;
+}
+
+#[tokio::test]
+async fn addresses_are_exclusive() {
+    let network = MemoryNetwork::new(0);
+    let _first = network.bind(node_addr(0)).unwrap();
+    assert!(network.bind(node_addr(0)).is_err());
+}
@@ -0,0 +1,188 @@
+mod common;
+
+use common::{eventually, gossip_config};
+use flux::backend::{
+    Backend, BackendOverride, BackendPool, DEFAULT_POOL, OverrideMode, SharedBackendPool,
+};
+use flux::gossip::{GossipLayer, HybridClock, MemberId};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+const BACKEND: &str = "127.0.0.1:9";
+
+fn backend() -> SocketAddr {
+    BACKEND.parse().unwrap()
+}
+
+fn pool(name: &str) -> BackendPool {
+    BackendPool::new(
+        vec![Backend::new(backend(), 1)],
+        MemberId::new(name.to_string()),
+        Arc::new(HybridClock::new()),
+    )
+}
+
+struct Node {
+    addr: SocketAddr,
+    layer: GossipLayer,
+    backends: SharedBackendPool,
+}
+
+async fn start_node(name: &str, seed_nodes: &[SocketAddr]) -> Node {
+    let config = gossip_config(seed_nodes, "push_pull_interval_ms = 200");
+    let backends = Arc::new(RwLock::new(pool(name)));
+    let layer = common::start_node(name, &config, backends.clone()).await;
+    let addr = layer.local_member().addr;
+    layer.join_cluster().await.unwrap();
+
+    Node {
+        addr,
+        layer,
+        backends,
+    }
+}
+
+async fn in_rotation(nodes: &[&Node]) -> Vec<bool> {
+    let mut rotation = Vec::new();
+    for node in nodes {
+        rotation.push(node.backends.read().await.select_backend().is_some());
+    }
+    rotation
+}
+
+#[test]
+fn overrides_round_trip() {
+    for value in ["drain", "maint 1760000000000", "enable"] {
+        let decoded = BackendOverride::decode(value).unwrap();
+        assert_eq!(decoded.encode(), value.as_bytes());
+    }
+    assert!(BackendOverride::decode("reboot").is_none());
+    assert!(BackendOverride::decode("drain soon").is_none());
+}
+
+#[test]
+fn overrides_win_over_health_checks() {
+    let mut pool = pool("node-0");
+    pool.update_health(backend(), false);
+    pool.update_health(backend(), false);
+    assert!(pool.select_backend().is_none());
+
+    pool.set_override(
+        backend(),
+        Some(BackendOverride::new(OverrideMode::Enable, None)),
+    );
+    assert!(pool.select_backend().is_some());
+
+    pool.update_health(backend(), true);
+    pool.update_health(backend(), true);
+    pool.set_override(
+        backend(),
+        Some(BackendOverride::new(OverrideMode::Drain, None)),
+    );
+    assert!(pool.select_backend().is_none());
+    assert_eq!(pool.get_checked_backends().len(), 1);
+
+    pool.set_override(
+        backend(),
+        Some(BackendOverride::new(OverrideMode::Maint, None)),
+    );
+    assert!(pool.select_backend().is_none());
+    assert!(pool.get_checked_backends().is_empty());
+
+    pool.set_override(backend(), None);
+    assert!(pool.select_backend().is_some());
+}
+
+#[tokio::test]
+async fn overrides_expire() {
+    let mut pool = pool("node-0");
+    let ttl = Duration::from_millis(200);
+    pool.set_override(
+        backend(),
+        Some(BackendOverride::new(OverrideMode::Drain, Some(ttl))),
+    );
+    assert!(pool.select_backend().is_none());
+
+    tokio::time::sleep(ttl + Duration::from_millis(50)).await;
+    assert!(pool.select_backend().is_some());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn override_reaches_every_node_and_is_cleared_from_any() {
+    let seed = start_node("node-0", &[]).await;
+    let mut nodes = vec![seed];
+    for i in 1..3 {
+        nodes.push(start_node(&format!("node-{i}"), &[nodes[0].addr]).await);
+    }
+
+    nodes[1]
+        .layer
+        .set_backend_override(DEFAULT_POOL, backend(), OverrideMode::Maint, None)
+        .await
+        .unwrap();
+    eventually("maintenance to apply", || async {
+        in_rotation(&nodes.iter().collect::<Vec<_>>()).await == [false; 3]
+    })
+    .await;
+
+    // a node that wasn't around when it was set still picks it up
+    let late = start_node("node-3", &[nodes[0].addr]).await;
+    eventually("late joiner to pick up the override", || async {
+        !in_rotation(&[&late]).await[0]
+    })
+    .await;
+    assert_eq!(
+        late.layer
+            .backend_override(DEFAULT_POOL, backend())
+            .map(|o| o.mode),
+        Some(OverrideMode::Maint)
+    );
+    nodes.push(late);
+
+    nodes[2]
+        .layer
+        .clear_backend_override(DEFAULT_POOL, backend())
+        .await;
+    eventually("override to be cleared", || async {
+        in_rotation(&nodes.iter().collect::<Vec<_>>()).await == [true; 4]
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_overrides_are_applied_in_order() {
+    let node = start_node("node-0", &[]).await;
+    // the flushes pile up behind a reader, with their outputs in hand
+    let reader = node.backends.read().await;
+    let setters: Vec<_> = (0..50)
+        .map(|i| {
+            let layer = node.layer.clone();
+            let mode = if i % 2 == 0 {
+                OverrideMode::Drain
+            } else {
+                OverrideMode::Maint
+            };
+            tokio::spawn(async move {
+                layer
+                    .set_backend_override(DEFAULT_POOL, backend(), mode, None)
+                    .await
+                    .unwrap()
+            })
+        })
+        .collect();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    drop(reader);
+    for setter in setters {
+        setter.await.unwrap();
+    }
+
+    // whatever won in the store is what the pool has
+    let stored = node.layer.backend_override(DEFAULT_POOL, backend());
+    let in_maintenance = node.backends.read().await.get_checked_backends().is_empty();
+    assert_eq!(
+        in_maintenance,
+        stored.map(|o| o.mode) == Some(OverrideMode::Maint)
+    );
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use common::{alive_counts, backend_pool, gossip_config, start_memory_node, wait_for_alive_counts};
+use flux::gossip::{GossipLayer, MemberState, MemoryNetwork, node_addr};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::time::Instant;
+
+async fn start_node(network: &MemoryNetwork, index: usize, reconnect: bool) -> GossipLayer {
+    let config = gossip_config(
+        &[node_addr(0)],
+        r#"
+        gossip_interval_ms = 100
+        reconnect_interval_ms = 200
+        "#,
+    );
+    let name = format!("node-{index}");
+    let layer = start_memory_node(network, index, &config, backend_pool(&name, vec![])).await;
+    if reconnect {
+        let reconnector = layer.clone();
+        tokio::spawn(async move { reconnector.start_reconnect_loop().await });
+    }
+    layer
+}
+
+// forms a cluster of five and cuts it into halves of three and two, each of
+// which has declared the other side dead. suspects are still probed, those
+// would find their way back without any reconnecting
+async fn split_cluster(network: &MemoryNetwork, reconnect: bool) -> Vec<GossipLayer> {
+    let mut nodes = Vec::new();
+    for i in 0..5 {
+        nodes.push(start_node(network, i, reconnect).await);
+    }
+    futures::future::join_all(nodes.iter().map(|node| node.join_cluster())).await;
+    wait_for_alive_counts(&nodes, &[4; 5], Duration::from_secs(10)).await;
+
+    let majority: Vec<SocketAddr> = (0..3).map(node_addr).collect();
+    let minority: Vec<SocketAddr> = (3..5).map(node_addr).collect();
+    network.partition(&majority, &minority);
+    let (majority, minority) = nodes.split_at(3);
+
+    let deadline = Instant::now() + Duration::from_secs(20);
+    let all_dead = |side: &[GossipLayer], other: &[GossipLayer]| {
+        side.iter().all(|node| {
+            other.iter().all(|peer| {
+                node.member(&peer.local_member().id).map(|m| m.state) == Some(MemberState::Dead)
+            })
+        })
+    };
+    while !(all_dead(majority, minority) && all_dead(minority, majority)) {
+        assert!(Instant::now() < deadline, "the halves never split");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    // a refutation still going round a half can bring a member back for
+    // another suspicion. give those time to run out
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    assert!(all_dead(majority, minority) && all_dead(minority, majority));
+    assert_eq!(alive_counts(&nodes), vec![2, 2, 2, 1, 1]);
+    nodes
+}
+
+#[tokio::test(start_paused = true)]
+async fn partitioned_cluster_heals() {
+    let network = MemoryNetwork::new(1);
+    let nodes = split_cluster(&network, true).await;
+
+    // reconnect attempts keep failing while the partition lasts
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    assert_eq!(alive_counts(&nodes), vec![2, 2, 2, 1, 1]);
+
+    network.heal();
+    wait_for_alive_counts(&nodes, &[4; 5], Duration::from_secs(15)).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn halves_stay_apart_without_reconnect_attempts() {
+    let network = MemoryNetwork::new(2);
+    let nodes = split_cluster(&network, false).await;
+
+    // nobody probes or gossips to dead members, so only the reconnect loop
+    // brings the halves back together
+    network.heal();
+    tokio::time::sleep(Duration::from_secs(15)).await;
+    assert_eq!(alive_counts(&nodes), vec![2, 2, 2, 1, 1]);
+}
@@ -0,0 +1,228 @@
+mod common;
+
+use common::{eventually, gossip_config};
+use flux::backend::{Backend, BackendPool, BackendPools, DEFAULT_POOL, OverrideMode};
+use flux::config::{Config, MAX_NAME_LEN, Strategy};
+use flux::gossip::{GossipLayer, HybridClock, MemberId};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+// both pools have the same backend, it is tracked separately in each
+const BACKEND: &str = "127.0.0.1:9";
+
+fn backend() -> SocketAddr {
+    BACKEND.parse().unwrap()
+}
+
+fn pool(name: &str, ports: &[u16]) -> BackendPool {
+    let backends = ports
+        .iter()
+        .map(|&port| Backend::new(SocketAddr::from(([127, 0, 0, 1], port)), 1))
+        .collect();
+    BackendPool::new(
+        backends,
+        MemberId::new(name.to_string()),
+        Arc::new(HybridClock::new()),
+    )
+}
+
+const GOSSIP: &str = r#"
+[gossip]
+bind_addr = "127.0.0.1:7946"
+gossip_interval_ms = 1000
+ping_timeout_ms = 500
+suspect_timeout_ms = 5000
+
+[health_check]
+check_interval_seconds = 5
+check_timeout_seconds = 2
+"#;
+
+fn config(services: &str) -> anyhow::Result<Config> {
+    let config: Config = toml::from_str(&format!("{services}{GOSSIP}"))?;
+    config.validate()?;
+    Ok(config)
+}
+
+#[test]
+fn a_single_service_is_the_default_frontend_and_pool() {
+    let config = config(
+        r#"
+        [server]
+        listen_addr = "127.0.0.1:8080"
+        min_healthy_backends = 2
+
+        [[backends]]
+        addr = "127.0.0.1:3000"
+        weight = 1
+        "#,
+    )
+    .unwrap();
+
+    let frontends = config.frontends();
+    assert_eq!(frontends.len(), 1);
+    assert_eq!(frontends[0].pool, DEFAULT_POOL);
+    let pools = config.pools();
+    assert_eq!(pools.len(), 1);
+    assert_eq!(pools[0].name, DEFAULT_POOL);
+    assert_eq!(pools[0].min_healthy_backends, 2);
+}
+
+#[test]
+fn frontends_route_to_named_pools() {
+    let config = config(
+        r#"
+        [[pools]]
+        name = "web"
+        [[pools.backends]]
+        addr = "127.0.0.1:3000"
+        weight = 1
+
+        [[pools]]
+        name = "api"
+        min_healthy_backends = 2
+        health_check = { check_interval_seconds = 1, check_timeout_seconds = 1 }
+        [[pools.backends]]
+        addr = "127.0.0.1:3000"
+        weight = 1
+
+        [[frontends]]
+        name = "web"
+        listen_addr = "127.0.0.1:8080"
+        pool = "web"
+
+        [[frontends]]
+        name = "api"
+        listen_addr = "127.0.0.1:8081"
+        pool = "api"
+        strategy = "least_connections"
+        "#,
+    )
+    .unwrap();
+
+    let frontends = config.frontends();
+    assert_eq!(frontends.len(), 2);
+    assert_eq!(frontends[0].strategy, Strategy::RoundRobin);
+    assert_eq!(frontends[1].strategy, Strategy::LeastConnections);
+    let pools = config.pools();
+    assert_eq!(pools.len(), 2);
+    assert!(pools[1].health_check.is_some());
+}
+
+#[test]
+fn broken_service_layouts_are_rejected() {
+    let pool = r#"
+        [[pools]]
+        name = "web"
+        [[pools.backends]]
+        addr = "127.0.0.1:3000"
+        weight = 1
+        "#;
+    let frontend = |name: &str, pool: &str| {
+        format!(
+            r#"
+            [[frontends]]
+            name = "{name}"
+            listen_addr = "127.0.0.1:8080"
+            pool = "{pool}"
+            "#
+        )
+    };
+
+    assert!(config(pool).is_err());
+    assert!(config(&format!("{pool}{}", frontend("web", "api"))).is_err());
+    assert!(config(&format!("{pool}{}", frontend("w/eb", "web"))).is_err());
+    let long = "w".repeat(MAX_NAME_LEN + 1);
+    assert!(config(&format!("{pool}{}", frontend(&long, "web"))).is_err());
+    assert!(config(&format!("{pool}{}", frontend(&long[1..], "web"))).is_ok());
+    assert!(
+        config(&format!(
+            "{pool}{}{}",
+            frontend("web", "web"),
+            frontend("web", "web")
+        ))
+        .is_err()
+    );
+    assert!(config(&format!("{pool}{}", frontend("web", "web"))).is_ok());
+}
+
+#[test]
+fn least_connections_goes_to_the_idlest_backend() {
+    let pool = pool("node-0", &[9, 10]);
+    let client = IpAddr::from([10, 1, 1, 1]);
+    let pick = || {
+        pool.acquire_backend_with(client, Strategy::LeastConnections)
+            .unwrap()
+    };
+
+    let first = pick();
+    // the other backend is idle as long as the first connection lives
+    for _ in 0..3 {
+        assert_ne!(pick().backend().addr, first.backend().addr);
+    }
+    let second = pick();
+    assert_ne!(second.backend().addr, first.backend().addr);
+    assert_ne!(pick().backend().addr, pick().backend().addr);
+}
+
+struct Node {
+    addr: SocketAddr,
+    layer: GossipLayer,
+    pools: BackendPools,
+}
+
+async fn start_node(name: &str, seed_nodes: &[SocketAddr]) -> Node {
+    let mut pools = BackendPools::new();
+    for pool_name in ["web", "api"] {
+        pools.insert(pool_name, Arc::new(RwLock::new(pool(name, &[9]))));
+    }
+    let layer = common::start_node(name, &gossip_config(seed_nodes, ""), pools.clone()).await;
+    let addr = layer.local_member().addr;
+    layer.join_cluster().await.unwrap();
+
+    Node { addr, layer, pools }
+}
+
+impl Node {
+    async fn routes(&self, pool: &str) -> bool {
+        let pool = self.pools.get(pool).unwrap();
+        pool.read().await.select_backend().is_some()
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn health_and_overrides_are_gossiped_per_pool() {
+    let first = start_node("node-0", &[]).await;
+    let second = start_node("node-1", &[first.addr]).await;
+
+    // failing in one pool's health checks leaves the other pool alone
+    {
+        let mut web = first.pools.get("web").unwrap().write().await;
+        web.update_health(backend(), false);
+        web.update_health(backend(), false);
+    }
+    eventually("the web pool to stop routing", || async {
+        !second.routes("web").await
+    })
+    .await;
+    assert!(second.routes("api").await);
+
+    first
+        .layer
+        .set_backend_override("api", backend(), OverrideMode::Drain, None)
+        .await
+        .unwrap();
+    eventually("the api pool to drain", || async {
+        !second.routes("api").await
+    })
+    .await;
+    assert_eq!(
+        second
+            .layer
+            .backend_override("api", backend())
+            .map(|o| o.mode),
+        Some(OverrideMode::Drain)
+    );
+    assert!(second.layer.backend_override("web", backend()).is_none());
+}
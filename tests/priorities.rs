@@ -0,0 +1,81 @@
+use flux::backend::{Backend, BackendPool};
+use flux::gossip::{HybridClock, MemberId};
+use std::collections::BTreeSet;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn backend(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
+
+// two primaries, 9 and 10, and two backups, 11 and 12
+fn pool(min_healthy_backends: usize) -> BackendPool {
+    let backends = [(9, 0), (10, 0), (11, 1), (12, 1)]
+        .into_iter()
+        .map(|(port, priority)| Backend {
+            priority,
+            ..Backend::new(backend(port), 1)
+        })
+        .collect();
+    let mut pool = BackendPool::new(
+        backends,
+        MemberId::new("node-0".to_string()),
+        Arc::new(HybridClock::new()),
+    );
+    pool.set_min_healthy_backends(min_healthy_backends);
+    pool
+}
+
+fn routed_to(pool: &BackendPool) -> BTreeSet<u16> {
+    (0..8)
+        .map(|_| pool.select_backend().unwrap().addr.port())
+        .collect()
+}
+
+fn set_health(pool: &mut BackendPool, port: u16, healthy: bool) {
+    pool.update_health(backend(port), healthy);
+    pool.update_health(backend(port), healthy);
+}
+
+#[test]
+fn backups_only_get_traffic_when_primaries_fail() {
+    let mut pool = pool(1);
+    assert_eq!(routed_to(&pool), BTreeSet::from([9, 10]));
+
+    set_health(&mut pool, 9, false);
+    assert_eq!(routed_to(&pool), BTreeSet::from([10]));
+
+    set_health(&mut pool, 10, false);
+    assert_eq!(routed_to(&pool), BTreeSet::from([11, 12]));
+
+    set_health(&mut pool, 9, true);
+    assert_eq!(routed_to(&pool), BTreeSet::from([9]));
+}
+
+#[test]
+fn threshold_fails_over_early() {
+    let mut pool = pool(2);
+    set_health(&mut pool, 9, false);
+    assert_eq!(routed_to(&pool), BTreeSet::from([11, 12]));
+
+    // no priority has enough, everything still up helps out
+    set_health(&mut pool, 11, false);
+    assert_eq!(routed_to(&pool), BTreeSet::from([10, 12]));
+}
+
+#[test]
+fn pinned_clients_leave_the_backups_once_primaries_recover() {
+    let mut pool = pool(1);
+    pool.enable_sticky_sessions(Duration::from_secs(60), 100);
+    let client = IpAddr::from([10, 1, 1, 1]);
+
+    set_health(&mut pool, 9, false);
+    set_health(&mut pool, 10, false);
+    let backup = pool.acquire_backend_for(client).unwrap().backend().addr;
+    assert!([backend(11), backend(12)].contains(&backup));
+
+    set_health(&mut pool, 10, true);
+    let primary = pool.acquire_backend_for(client).unwrap().backend().addr;
+    assert_eq!(primary, backend(10));
+}
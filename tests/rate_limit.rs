@@ -0,0 +1,191 @@
+mod common;
+
+use common::{backend_pool, gossip_config, start_memory_node};
+use flux::config::{MAX_NAME_LEN, RateLimitConfig};
+use flux::gossip::{MemberId, MemoryNetwork, node_addr, rate_limit_key};
+use flux::ratelimit::{RateLimiter, UNREPORTED_KEY, decode_report, encode_report};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::time::Instant;
+
+fn limiter(settings: &str) -> RateLimiter {
+    let config: RateLimitConfig = toml::from_str(settings).unwrap();
+    RateLimiter::new(config)
+}
+
+async fn allowed(limiter: &RateLimiter, key: &str, attempts: usize) -> usize {
+    let mut allowed = 0;
+    for _ in 0..attempts {
+        if limiter.acquire(key).await {
+            allowed += 1;
+        }
+    }
+    allowed
+}
+
+async fn start_node(network: &MemoryNetwork, index: usize, limiter: Arc<RateLimiter>) {
+    let seeds = if index > 0 {
+        vec![node_addr(0)]
+    } else {
+        vec![]
+    };
+    let config = gossip_config(&seeds, "");
+    let name = format!("node-{index}");
+    let layer = start_memory_node(network, index, &config, backend_pool(&name, vec![])).await;
+    layer.join_cluster().await.unwrap();
+    tokio::spawn(async move { layer.start_rate_limit_loop("web", limiter).await });
+}
+
+#[tokio::test(start_paused = true)]
+async fn burst_then_rate() {
+    let limiter = limiter("rate = 2.0\nburst = 3");
+
+    assert_eq!(allowed(&limiter, "10.1.1.1", 5).await, 3);
+    // other clients have their own bucket
+    assert_eq!(allowed(&limiter, "10.1.1.2", 1).await, 1);
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert_eq!(allowed(&limiter, "10.1.1.1", 5).await, 2);
+
+    let metrics = limiter.metrics();
+    assert_eq!(metrics.allowed.load(Ordering::Relaxed), 6);
+    assert_eq!(metrics.rejected.load(Ordering::Relaxed), 5);
+}
+
+#[tokio::test(start_paused = true)]
+async fn delay_waits_for_a_token() {
+    let limiter = limiter("rate = 10.0\nburst = 1\non_limit = \"delay\"\nmax_delay_ms = 250");
+
+    let start = Instant::now();
+    assert_eq!(allowed(&limiter, "10.1.1.1", 3).await, 3);
+    assert_eq!(start.elapsed(), Duration::from_millis(200));
+
+    // three queued up would have to wait 300ms
+    let (a, b, c) = tokio::join!(
+        limiter.acquire("10.1.1.1"),
+        limiter.acquire("10.1.1.1"),
+        limiter.acquire("10.1.1.1"),
+    );
+    assert_eq!((a, b, c), (true, true, false));
+
+    let metrics = limiter.metrics();
+    assert_eq!(metrics.delayed.load(Ordering::Relaxed), 4);
+    assert_eq!(metrics.rejected.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn busy_keys_elsewhere_get_a_small_share_here() {
+    let limiter = limiter("rate = 1.0\nburst = 100");
+    limiter.rebalance(HashMap::from([("10.1.1.1".to_string(), 48)]), 2);
+
+    // 1 / (48 + 2) of the burst
+    assert_eq!(allowed(&limiter, "10.1.1.1", 10).await, 2);
+    // nobody reported this one, half of the burst for each of us
+    assert_eq!(allowed(&limiter, "10.1.1.2", 100).await, 50);
+}
+
+#[test]
+fn reports_round_trip_and_fit_an_entry() {
+    let report = vec![("10.1.1.1".to_string(), 12), ("[::1]:8080".to_string(), 3)];
+    assert_eq!(decode_report(&encode_report(&report)), report);
+
+    let big: Vec<(String, u64)> = (0..100).map(|i| (format!("10.1.1.{i}"), 1000)).collect();
+    let encoded = encode_report(&big);
+    assert!(encoded.len() <= 400);
+    let mut decoded = decode_report(&encoded);
+    // the keys that didn't fit are counted at the end
+    let (marker, left_out) = decoded.pop().unwrap();
+    assert_eq!(marker, UNREPORTED_KEY);
+    assert_eq!(decoded[..], big[..decoded.len()]);
+    assert_eq!(decoded.len() as u64 + left_out, big.len() as u64);
+}
+
+#[tokio::test]
+async fn a_full_report_fits_under_the_longest_key() {
+    let network = MemoryNetwork::new(1);
+    let config = gossip_config(&[], "");
+    let layer = start_memory_node(&network, 0, &config, backend_pool("node-0", vec![])).await;
+
+    let addr: SocketAddr = "[ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff%4294967295]:65535"
+        .parse()
+        .unwrap();
+    let key = rate_limit_key(&"f".repeat(MAX_NAME_LEN), &MemberId::generate(addr));
+    let big: Vec<(String, u64)> = (0..100)
+        .map(|i| (format!("[ffff::{i}]:65535"), u64::MAX))
+        .collect();
+    layer.kv_set(&key, encode_report(&big)).await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn keys_left_out_of_a_report_get_an_even_split() {
+    let settings = "rate = 1.0\nburst = 90";
+    // the other two nodes each had more busy clients than fit a report
+    let report: Vec<(String, u64)> = (0..100)
+        .map(|i| (format!("10.1.2.{i}"), 10 + i as u64))
+        .collect();
+    let mut remote = HashMap::new();
+    for _ in 0..2 {
+        for (key, count) in decode_report(&encode_report(&report)) {
+            *remote.entry(key).or_default() += count;
+        }
+    }
+    let reported = decode_report(&encode_report(&report));
+    let unreported = &report[reported.len() - 1].0;
+    assert!(!remote.contains_key(unreported));
+
+    // busy here too last interval, it would otherwise get 11/13 of the burst
+    let here = limiter(settings);
+    assert_eq!(allowed(&here, unreported, 10).await, 10);
+    here.take_report();
+    here.rebalance(remote.clone(), 3);
+    tokio::time::sleep(Duration::from_secs(1000)).await;
+    assert_eq!(allowed(&here, unreported, 90).await, 30);
+
+    // without any cut short report, the usual share
+    remote.remove(UNREPORTED_KEY);
+    let here = limiter(settings);
+    assert_eq!(allowed(&here, unreported, 10).await, 10);
+    here.take_report();
+    here.rebalance(remote, 3);
+    tokio::time::sleep(Duration::from_secs(1000)).await;
+    assert_eq!(allowed(&here, unreported, 90).await, 76);
+}
+
+#[tokio::test(start_paused = true)]
+async fn rate_holds_across_the_cluster() {
+    let network = MemoryNetwork::new(5);
+    let settings = "rate = 20.0\nburst = 5\nreport_interval_ms = 100";
+    let limiters = [Arc::new(limiter(settings)), Arc::new(limiter(settings))];
+    for (index, limiter) in limiters.iter().enumerate() {
+        start_node(&network, index, limiter.clone()).await;
+    }
+
+    // the same client hammers both nodes for 5 seconds
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let clients: Vec<_> = limiters
+        .iter()
+        .map(|limiter| {
+            let limiter = limiter.clone();
+            tokio::spawn(async move {
+                let mut allowed = 0;
+                while Instant::now() < deadline {
+                    if limiter.acquire("10.1.1.1").await {
+                        allowed += 1;
+                    }
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                }
+                allowed
+            })
+        })
+        .collect();
+    let mut total = 0;
+    for client in clients {
+        total += client.await.unwrap();
+    }
+
+    // 20/s for 5s plus the burst, not twice that
+    assert!((90..=130).contains(&total), "{total} connections allowed");
+}
@@ -0,0 +1,176 @@
+mod common;
+
+use common::gossip_config;
+use flux::gossip::SeedResolver;
+use hickory_resolver::proto::op::{Message, ResponseCode};
+use hickory_resolver::proto::rr::rdata::{A, SRV};
+use hickory_resolver::proto::rr::{Name, RData, Record};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use tokio::net::UdpSocket;
+
+fn resolver(sources: &str, dns_server: Option<SocketAddr>) -> SeedResolver {
+    let mut config = gossip_config(&[addr(7000)], sources);
+    config.dns_servers = dns_server.into_iter().collect();
+    SeedResolver::new(&config).unwrap()
+}
+
+fn addr(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
+
+fn seed_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("flux-seeds-{}-{}", std::process::id(), name))
+}
+
+// the file source only re-reads a file whose mtime moved
+fn write_seed_file(path: &PathBuf, content: &str, age: Duration) {
+    std::fs::write(path, content).unwrap();
+    let file = std::fs::File::options().write(true).open(path).unwrap();
+    file.set_modified(SystemTime::now() - age).unwrap();
+}
+
+#[tokio::test]
+async fn file_seeds_follow_changes_on_disk() {
+    let path = seed_file("changes");
+    write_seed_file(
+        &path,
+        "127.0.0.1:7001 # first\n\nnot-an-address\n127.0.0.1:7002\n",
+        Duration::from_secs(60),
+    );
+    let seeds = resolver(
+        &format!("seed_sources = [{{ type = \"file\", path = {:?} }}]", path),
+        None,
+    );
+
+    // the broken line is skipped, not the whole file
+    assert_eq!(seeds.resolve().await, [addr(7000), addr(7001), addr(7002)]);
+
+    write_seed_file(&path, "127.0.0.1:7003\n", Duration::ZERO);
+    assert_eq!(seeds.resolve().await, [addr(7000), addr(7003)]);
+
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(seeds.resolve().await, [addr(7000)]);
+}
+
+#[tokio::test]
+async fn command_seeds_are_read_from_stdout() {
+    let seeds = resolver(
+        r#"seed_sources = [{ type = "command", command = "sh", args = ["-c", "echo 127.0.0.1:7001; echo '# comment'; echo 127.0.0.1:7001; echo 127.0.0.1:7002"] }]"#,
+        None,
+    );
+    assert_eq!(seeds.resolve().await, [addr(7000), addr(7001), addr(7002)]);
+
+    let failing = resolver(
+        r#"seed_sources = [{ type = "command", command = "sh", args = ["-c", "echo 127.0.0.1:7001; exit 1"] }]"#,
+        None,
+    );
+    assert_eq!(failing.resolve().await, [addr(7000)]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn hung_commands_time_out() {
+    let seeds = resolver(
+        r#"seed_sources = [{ type = "command", command = "sleep", args = ["60"] }]"#,
+        None,
+    );
+    let started = std::time::Instant::now();
+    assert_eq!(seeds.resolve().await, [addr(7000)]);
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+// answers A and SRV queries from a fixed set of records, every other name
+// doesn't exist
+async fn stub_dns_server(records: Vec<Record>) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0; 1500];
+        loop {
+            let (len, from) = socket.recv_from(&mut buf).await.unwrap();
+            let Ok(query) = Message::from_vec(&buf[..len]) else {
+                continue;
+            };
+            let mut response = Message::response(query.metadata.id, query.metadata.op_code);
+            response.metadata.recursion_desired = query.metadata.recursion_desired;
+            response.metadata.recursion_available = true;
+            for question in &query.queries {
+                response.add_query(question.clone());
+                let answers: Vec<Record> = records
+                    .iter()
+                    .filter(|record| {
+                        record.name == question.name && record.record_type() == question.query_type
+                    })
+                    .cloned()
+                    .collect();
+                if !records.iter().any(|record| record.name == question.name) {
+                    response.metadata.response_code = ResponseCode::NXDomain;
+                }
+                response.add_answers(answers);
+            }
+            let _ = socket.send_to(&response.to_vec().unwrap(), from).await;
+        }
+    });
+    addr
+}
+
+fn name(name: &str) -> Name {
+    Name::from_ascii(name).unwrap()
+}
+
+fn a_record(host: &str, ip: Ipv4Addr) -> Record {
+    Record::from_rdata(name(host), 0, RData::A(A(ip)))
+}
+
+fn srv_record(service: &str, port: u16, target: &str) -> Record {
+    Record::from_rdata(
+        name(service),
+        0,
+        RData::SRV(SRV::new(0, 0, port, name(target))),
+    )
+}
+
+#[tokio::test]
+async fn dns_and_srv_seeds_come_from_the_configured_servers() {
+    let dns_server = stub_dns_server(vec![
+        a_record("seeds.flux.test.", Ipv4Addr::new(127, 0, 0, 11)),
+        a_record("seeds.flux.test.", Ipv4Addr::new(127, 0, 0, 12)),
+        a_record("node-1.flux.test.", Ipv4Addr::new(127, 0, 0, 21)),
+        srv_record("_gossip._udp.flux.test.", 7001, "node-1.flux.test."),
+        // the other target is gone, it doesn't take the source down
+        srv_record("_gossip._udp.flux.test.", 7002, "node-2.flux.test."),
+    ])
+    .await;
+
+    let dns = resolver(
+        r#"seed_sources = [{ type = "dns", name = "seeds.flux.test.", port = 7946 }]"#,
+        Some(dns_server),
+    );
+    let mut resolved = dns.resolve().await;
+    resolved.sort();
+    assert_eq!(
+        resolved,
+        [
+            addr(7000),
+            "127.0.0.11:7946".parse().unwrap(),
+            "127.0.0.12:7946".parse().unwrap()
+        ]
+    );
+
+    let srv = resolver(
+        r#"seed_sources = [{ type = "srv", name = "_gossip._udp.flux.test." }]"#,
+        Some(dns_server),
+    );
+    assert_eq!(
+        srv.resolve().await,
+        [addr(7000), "127.0.0.21:7001".parse().unwrap()]
+    );
+
+    // a name that doesn't exist only loses its own source
+    let missing = resolver(
+        r#"seed_sources = [{ type = "dns", name = "nobody.flux.test.", port = 7946 }]"#,
+        Some(dns_server),
+    );
+    assert_eq!(missing.resolve().await, [addr(7000)]);
+}
@@ -0,0 +1,111 @@
+use flux::config::GossipConfig;
+use flux::gossip::{MemberState, NetworkConditions, Simulation};
+use std::time::Duration;
+
+const NODES: usize = 8;
+
+fn config() -> GossipConfig {
+    toml::from_str(
+        r#"
+        bind_addr = "127.0.0.1:0"
+        gossip_interval_ms = 100
+        ping_timeout_ms = 50
+        suspect_timeout_ms = 500
+        reconnect_interval_ms = 200
+        "#,
+    )
+    .unwrap()
+}
+
+fn lan() -> NetworkConditions {
+    NetworkConditions {
+        latency: Duration::from_millis(2),
+        jitter: Duration::from_millis(3),
+        loss: 0.0,
+    }
+}
+
+async fn converged_cluster(seed: u64) -> Simulation {
+    let sim = Simulation::new(seed, NODES, &config()).await.unwrap();
+    sim.set_conditions(lan());
+    assert!(
+        sim.rounds_until_converged(100).await.is_some(),
+        "seed {seed}: cluster did not form"
+    );
+    sim
+}
+
+#[tokio::test(start_paused = true)]
+async fn crash_is_detected_by_every_live_node() {
+    for seed in 0..5 {
+        let mut sim = converged_cluster(seed).await;
+        sim.crash(3);
+
+        let rounds = sim.rounds_until_dead(&sim.live(), 3, 40).await;
+        assert!(
+            rounds.is_some(),
+            "seed {seed}: crash not detected in 40 rounds"
+        );
+        assert!(sim.false_deaths().is_empty(), "seed {seed}");
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn no_false_deaths_under_packet_loss() {
+    for seed in 0..3 {
+        let sim = converged_cluster(seed).await;
+        sim.set_conditions(NetworkConditions { loss: 0.1, ..lan() });
+
+        if let Some((after, deaths)) = sim.watch_false_deaths(300).await {
+            panic!("seed {seed}: live members declared dead after {after:?}: {deaths:?}");
+        }
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn slow_node_is_not_declared_dead() {
+    for seed in 0..3 {
+        let sim = converged_cluster(seed).await;
+        // pings to it and back take longer than the ping timeout
+        sim.slow_down(5, Duration::from_millis(30));
+
+        if let Some((after, deaths)) = sim.watch_false_deaths(150).await {
+            panic!("seed {seed}: slow member declared dead after {after:?}: {deaths:?}");
+        }
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn partition_is_detected_and_heals() {
+    let sim = converged_cluster(1).await;
+    let (majority, minority): (Vec<usize>, Vec<usize>) = (0..NODES).partition(|&i| i < 5);
+
+    sim.partition(&majority, &minority);
+    sim.rounds_until_dead(&majority, 7, 100)
+        .await
+        .expect("minority not detected by the majority");
+    assert_eq!(sim.state(7, 6), Some(MemberState::Alive));
+
+    sim.heal();
+    sim.rounds_until_converged(100)
+        .await
+        .expect("cluster did not merge after the partition healed");
+}
+
+#[tokio::test(start_paused = true)]
+async fn same_seed_same_run() {
+    async fn trace(seed: u64) -> Vec<Vec<Vec<Option<MemberState>>>> {
+        let mut sim = converged_cluster(seed).await;
+        sim.set_conditions(NetworkConditions { loss: 0.1, ..lan() });
+        sim.crash(2);
+
+        let mut trace = Vec::new();
+        for _ in 0..30 {
+            sim.run_rounds(1).await;
+            trace.push(sim.views());
+        }
+        trace
+    }
+
+    assert_eq!(trace(42).await, trace(42).await);
+}
@@ -0,0 +1,219 @@
+mod common;
+
+use common::{backend_pool, eventually, gossip_config, memory_layer};
+use flux::backend::{Backend, DEFAULT_POOL, SharedBackendPool};
+use flux::gossip::{
+    BackendUpdate, GossipLayer, HybridClock, HybridTimestamp, Member, MemberId, MemberState,
+    MemoryNetwork, Snapshot, SnapshotMember, node_addr,
+};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use tokio::task::JoinHandle;
+
+const BACKEND: &str = "127.0.0.1:3000";
+
+struct Node {
+    layer: GossipLayer,
+    backend_pool: SharedBackendPool,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+fn state_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("flux-{}-{}.state", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+async fn start_node(
+    network: &MemoryNetwork,
+    index: usize,
+    seeds: &[SocketAddr],
+    state_file: Option<&PathBuf>,
+) -> Node {
+    let mut config = gossip_config(
+        seeds,
+        r#"
+        gossip_interval_ms = 100
+        reconnect_interval_ms = 200
+        "#,
+    );
+    config.state_file = state_file.cloned();
+
+    let backend_pool = backend_pool(
+        &format!("node-{index}"),
+        vec![Backend::new(BACKEND.parse().unwrap(), 1)],
+    );
+    let layer = memory_layer(network, index, &config, backend_pool.clone()).await;
+
+    let runner = layer.clone();
+    let tasks = vec![tokio::spawn(async move { runner.run().await })];
+    layer.join_cluster().await.unwrap();
+
+    Node {
+        layer,
+        backend_pool,
+        tasks,
+    }
+}
+
+#[tokio::test]
+async fn missing_state_file_is_not_an_error() {
+    let path = state_file("missing");
+    assert!(Snapshot::load(&path).await.unwrap().is_none());
+}
+
+// the layout of version 1 files, before backend updates had a pool
+#[derive(serde::Serialize)]
+struct SnapshotV1 {
+    incarnation: u64,
+    members: Vec<SnapshotMember>,
+    backends: Vec<BackendUpdateV1>,
+}
+
+#[derive(serde::Serialize)]
+struct BackendUpdateV1 {
+    backend_addr: SocketAddr,
+    is_healthy: bool,
+    from_member: MemberId,
+    version: HybridTimestamp,
+}
+
+#[tokio::test]
+async fn state_files_of_an_older_version_are_ignored() {
+    let network = MemoryNetwork::new(3);
+    let path = state_file("v1");
+    let v1 = SnapshotV1 {
+        incarnation: 7,
+        members: vec![SnapshotMember {
+            id: MemberId::new("node-1".to_string()),
+            addr: node_addr(1),
+            incarnation: 3,
+        }],
+        backends: vec![BackendUpdateV1 {
+            backend_addr: BACKEND.parse().unwrap(),
+            is_healthy: false,
+            from_member: MemberId::new("node-1".to_string()),
+            version: HybridClock::new().now(),
+        }],
+    };
+    std::fs::write(&path, bincode::serialize(&(1u32, v1)).unwrap()).unwrap();
+
+    assert!(Snapshot::load(&path).await.is_err());
+    let node = start_node(&network, 0, &[], Some(&path)).await;
+    assert_eq!(node.layer.local_member().incarnation, 0);
+    assert!(node.backend_pool.read().await.select_backend().is_some());
+}
+
+#[tokio::test(start_paused = true)]
+async fn restarted_node_rejoins_through_its_snapshot() {
+    let network = MemoryNetwork::new(3);
+    let path = state_file("restart");
+
+    let node_0 = start_node(&network, 0, &[], None).await;
+    let node_1 = start_node(&network, 1, &[node_addr(0)], None).await;
+    let node_2 = start_node(&network, 2, &[node_addr(0)], Some(&path)).await;
+    eventually("the cluster to form", || async {
+        [&node_0, &node_1, &node_2]
+            .iter()
+            .all(|node| node.layer.alive_members().len() == 2)
+    })
+    .await;
+
+    // a backend health verdict node 2 heard from a peer
+    let clock = node_2.backend_pool.read().await.clock();
+    node_2
+        .backend_pool
+        .write()
+        .await
+        .apply_backend_update(&BackendUpdate {
+            pool: DEFAULT_POOL.to_string(),
+            backend_addr: BACKEND.parse().unwrap(),
+            is_healthy: false,
+            from_member: MemberId::new("node-0".to_string()),
+            version: clock.now(),
+        });
+    node_2.layer.save_snapshot().await.unwrap();
+
+    let Member {
+        id: id_2,
+        incarnation,
+        ..
+    } = node_2.layer.local_member();
+    for task in node_2.tasks {
+        task.abort();
+    }
+    drop(node_2.layer);
+    eventually("the crash to be noticed", || async {
+        node_0.layer.member(&id_2).map(|m| m.state) == Some(MemberState::Dead)
+    })
+    .await;
+
+    // no seeds this time, only the peers it remembers
+    let node_2 = start_node(&network, 2, &[], Some(&path)).await;
+    // at least one above where it left off, more if it had to refute anything
+    assert!(node_2.layer.local_member().incarnation > incarnation);
+    assert!(node_2.backend_pool.read().await.select_backend().is_none());
+
+    eventually("the restarted node to be alive again", || async {
+        [&node_0, &node_1].iter().all(|node| {
+            node.layer
+                .member(&id_2)
+                .is_some_and(|m| m.state == MemberState::Alive && m.incarnation > incarnation)
+        }) && node_2.layer.alive_members().len() == 2
+    })
+    .await;
+
+    let _ = std::fs::remove_file(&path);
+}
+
+async fn saved_incarnation(path: &Path, incarnation: u64) {
+    eventually(
+        &format!("incarnation {incarnation} to be saved"),
+        || async {
+            let saved = Snapshot::load(path).await.ok().flatten();
+            saved.is_some_and(|snapshot| snapshot.incarnation == incarnation)
+        },
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn a_raised_incarnation_is_saved_right_away() {
+    let network = MemoryNetwork::new(1);
+    let path = state_file("raised");
+    let node = start_node(&network, 0, &[], Some(&path)).await;
+
+    let snapshotter = node.layer.clone();
+    tokio::spawn(async move { snapshotter.start_snapshot_loop().await });
+    saved_incarnation(&path, 0).await;
+
+    // the next tick is 30s out, only the raise saves sooner
+    let mut tags = node.layer.local_member().tags;
+    tags.insert("role".to_string(), "edge".to_string());
+    node.layer.set_tags(tags);
+    let incarnation = node.layer.local_member().incarnation;
+    assert!(incarnation > 0);
+    saved_incarnation(&path, incarnation).await;
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn saving_replaces_the_file_and_leaves_no_temp_file() {
+    let network = MemoryNetwork::new(1);
+    let path = state_file("replaced");
+    let node = start_node(&network, 0, &[], Some(&path)).await;
+
+    node.layer.save_snapshot().await.unwrap();
+    node.layer
+        .set_tags([("role".to_string(), "edge".to_string())].into());
+    node.layer.save_snapshot().await.unwrap();
+
+    let saved = Snapshot::load(&path).await.unwrap().unwrap();
+    assert_eq!(saved.incarnation, node.layer.local_member().incarnation);
+    let mut tmp = path.clone().into_os_string();
+    tmp.push(".tmp");
+    assert!(!Path::new(&tmp).exists());
+
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,153 @@
+mod common;
+
+use common::{eventually, gossip_config};
+use flux::backend::{Backend, BackendPool, SharedBackendPool};
+use flux::gossip::{HybridClock, MemberId};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+const TTL: Duration = Duration::from_secs(60);
+const MAX_PINS: usize = 100;
+
+fn client(n: u8) -> IpAddr {
+    IpAddr::from([10, 1, 1, n])
+}
+
+fn pool(name: &str, ttl: Duration) -> BackendPool {
+    let backends = (9..12)
+        .map(|port| Backend::new(SocketAddr::from(([127, 0, 0, 1], port)), 1))
+        .collect();
+    let mut pool = BackendPool::new(
+        backends,
+        MemberId::new(name.to_string()),
+        Arc::new(HybridClock::new()),
+    );
+    pool.enable_sticky_sessions(ttl, MAX_PINS);
+    pool
+}
+
+fn backend_for(pool: &BackendPool, client: IpAddr) -> SocketAddr {
+    pool.acquire_backend_for(client).unwrap().backend().addr
+}
+
+struct Node {
+    addr: SocketAddr,
+    backends: SharedBackendPool,
+}
+
+async fn start_node(name: &str, seed_nodes: &[SocketAddr]) -> Node {
+    let backends = Arc::new(RwLock::new(pool(name, TTL)));
+    let layer = common::start_node(name, &gossip_config(seed_nodes, ""), backends.clone()).await;
+    let addr = layer.local_member().addr;
+
+    let publisher = layer.clone();
+    tokio::spawn(async move { publisher.start_sticky_session_loop().await });
+    layer.join_cluster().await.unwrap();
+
+    Node { addr, backends }
+}
+
+#[test]
+fn clients_stay_on_their_backend_until_it_fails() {
+    let mut pool = pool("node-0", TTL);
+    let first = backend_for(&pool, client(1));
+    // round robin would have moved on
+    for _ in 0..5 {
+        assert_eq!(backend_for(&pool, client(1)), first);
+    }
+    assert_ne!(backend_for(&pool, client(2)), first);
+    assert_eq!(pool.sticky_pin(client(1)).unwrap().backend, first);
+
+    pool.update_health(first, false);
+    pool.update_health(first, false);
+    assert!(pool.sticky_pin(client(1)).is_none());
+    // our pin is deleted from the store too
+    assert!(pool.take_sticky_updates().contains(&(client(1), None)));
+
+    let second = backend_for(&pool, client(1));
+    assert_ne!(second, first);
+    assert_eq!(backend_for(&pool, client(1)), second);
+    assert_eq!(
+        pool.take_sticky_updates(),
+        vec![(client(1), pool.sticky_pin(client(1)))]
+    );
+}
+
+#[tokio::test]
+async fn pins_expire() {
+    let ttl = Duration::from_millis(200);
+    let pool = pool("node-0", ttl);
+    backend_for(&pool, client(1));
+    assert!(pool.sticky_pin(client(1)).is_some());
+
+    tokio::time::sleep(ttl + Duration::from_millis(50)).await;
+    assert!(pool.sticky_pin(client(1)).is_none());
+}
+
+#[tokio::test]
+async fn the_oldest_pin_makes_room_at_the_limit() {
+    let mut pool = pool("node-0", TTL);
+    pool.enable_sticky_sessions(TTL, 2);
+    for n in 1..3 {
+        backend_for(&pool, client(n));
+        // apart by a few milliseconds, so their expiry is ordered
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    // pins from other nodes don't count
+    let remote = pool.sticky_pin(client(1)).unwrap();
+    pool.apply_sticky_pin(
+        client(9),
+        Some(remote),
+        &MemberId::new("node-1".to_string()),
+    );
+    pool.take_sticky_updates();
+
+    backend_for(&pool, client(3));
+    assert!(pool.sticky_pin(client(1)).is_none());
+    for n in [2, 3, 9] {
+        assert!(pool.sticky_pin(client(n)).is_some());
+    }
+    // and its key is deleted from the store
+    let updates = pool.take_sticky_updates();
+    assert!(updates.contains(&(client(1), None)));
+    assert!(
+        updates
+            .iter()
+            .any(|(c, pin)| *c == client(3) && pin.is_some())
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn pins_follow_clients_across_nodes() {
+    let first = start_node("node-0", &[]).await;
+    let second = start_node("node-1", &[first.addr]).await;
+
+    let pinned = backend_for(&*first.backends.read().await, client(1));
+    eventually("the pin to reach the other node", || async {
+        second.backends.read().await.sticky_pin(client(1)).is_some()
+    })
+    .await;
+    for _ in 0..5 {
+        assert_eq!(
+            backend_for(&*second.backends.read().await, client(1)),
+            pinned
+        );
+    }
+
+    // the backend failing where the client isn't connected unpins it everywhere
+    {
+        let mut backends = first.backends.write().await;
+        backends.update_health(pinned, false);
+        backends.update_health(pinned, false);
+    }
+    eventually("the pin to be dropped", || async {
+        second.backends.read().await.sticky_pin(client(1)).is_none()
+    })
+    .await;
+    assert_ne!(
+        backend_for(&*second.backends.read().await, client(1)),
+        pinned
+    );
+}
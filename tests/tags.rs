@@ -0,0 +1,64 @@
+mod common;
+
+use common::{backend_pool, eventually, gossip_config, start_node};
+use flux::gossip::{MemberState, Tags};
+
+fn tags(pairs: &[(&str, &str)]) -> Tags {
+    pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[tokio::test]
+async fn tag_changes_reach_other_members() {
+    let tagged = "tags = { zone = \"a\" }";
+    let first = start_node(
+        "node-0",
+        &gossip_config(&[], tagged),
+        backend_pool("node-0", vec![]),
+    )
+    .await;
+    let first_id = first.local_member().id;
+    let first_addr = first.local_member().addr;
+
+    let second = start_node(
+        "node-1",
+        &gossip_config(&[first_addr], tagged),
+        backend_pool("node-1", vec![]),
+    )
+    .await;
+    second.join_cluster().await.unwrap();
+
+    eventually("the first member's tags to arrive", || async {
+        second
+            .member(&first_id)
+            .is_some_and(|m| m.state == MemberState::Alive && m.tag("zone") == Some("a"))
+    })
+    .await;
+    // the version tag is always there
+    assert_eq!(
+        first.local_member().tag("version"),
+        Some(env!("CARGO_PKG_VERSION"))
+    );
+    assert_eq!(first.local_member().incarnation, 0);
+
+    first.set_tags(tags(&[("zone", "b"), ("role", "lb")]));
+    // the new set only wins everywhere because it comes with a new incarnation
+    assert_eq!(first.local_member().incarnation, 1);
+    eventually("the changed tags to arrive", || async {
+        second
+            .member(&first_id)
+            .is_some_and(|m| m.incarnation == 1 && m.tag("zone") == Some("b"))
+    })
+    .await;
+    assert_eq!(second.member(&first_id).unwrap().tag("role"), Some("lb"));
+    assert_eq!(
+        second.with_members(|members| members.get_members_with_tag("role", "lb").len()),
+        1
+    );
+
+    // setting the same tags again changes nothing
+    first.set_tags(tags(&[("zone", "b"), ("role", "lb")]));
+    assert_eq!(first.local_member().incarnation, 1);
+}
@@ -0,0 +1,205 @@
+mod common;
+
+use common::{backend_pool, eventually, gossip_config, start_memory_node};
+use flux::config::VirtualIpConfig;
+use flux::gossip::{Member, MemberId, MemberState, MemoryNetwork, node_addr};
+use flux::vip::{VirtualIps, elect, vip_tag};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+const VIP: &str = "10.0.0.100";
+
+fn vip() -> IpAddr {
+    VIP.parse().unwrap()
+}
+
+fn member(name: &str, claim: &str) -> Member {
+    Member {
+        id: MemberId::new(name.to_string()),
+        addr: node_addr(0),
+        state: MemberState::Alive,
+        incarnation: 0,
+        tags: [(vip_tag(vip()), claim.to_string())].into(),
+    }
+}
+
+// hooks append "up" or "down" to a file per node
+fn hook_log(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("flux-vip-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn hook(log: &Path) -> Vec<String> {
+    vec![
+        "sh".to_string(),
+        "-c".to_string(),
+        format!("echo $FLUX_VIP_STATE >> {}", log.display()),
+    ]
+}
+
+fn hook_calls(log: &Path) -> Vec<String> {
+    std::fs::read_to_string(log)
+        .unwrap_or_default()
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
+
+struct Node {
+    vips: Arc<VirtualIps>,
+    log: PathBuf,
+}
+
+async fn start_node(network: &MemoryNetwork, index: usize, priority: u32, preempt: bool) -> Node {
+    let log = hook_log(&format!("node-{index}-{priority}-{preempt}"));
+    let vips = Arc::new(VirtualIps::new(vec![VirtualIpConfig {
+        addr: vip(),
+        priority,
+        preempt,
+        up_command: hook(&log),
+        down_command: hook(&log),
+    }]));
+    start_node_with(network, index, vips.clone()).await;
+    Node { vips, log }
+}
+
+async fn start_node_with(network: &MemoryNetwork, index: usize, vips: Arc<VirtualIps>) {
+    let seeds = if index > 0 {
+        vec![node_addr(0)]
+    } else {
+        vec![]
+    };
+    let config = gossip_config(&seeds, "");
+    let name = format!("node-{index}");
+    let layer = start_memory_node(network, index, &config, backend_pool(&name, vec![])).await;
+    layer.join_cluster().await.unwrap();
+    tokio::spawn(async move { layer.start_virtual_ip_loop(vips).await });
+}
+
+fn owners(nodes: &[&Node]) -> Vec<bool> {
+    nodes
+        .iter()
+        .map(|node| !node.vips.owned().is_empty())
+        .collect()
+}
+
+#[test]
+fn highest_priority_wins_unless_an_owner_keeps_it() {
+    let members = [
+        member("node-0", "100 owner"),
+        member("node-1", "200"),
+        member("node-2", "200"),
+    ];
+    let owner = |preempt| elect(vip(), preempt, &members).map(|id| id.0);
+
+    // ties go to the highest id
+    assert_eq!(owner(true).as_deref(), Some("node-2"));
+    assert_eq!(owner(false).as_deref(), Some("node-0"));
+    assert_eq!(elect(vip(), true, &members[..0]), None);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn backup_takes_over_when_the_owner_fails() {
+    let network = MemoryNetwork::new(7);
+    let master = start_node(&network, 0, 200, true).await;
+    let backup = start_node(&network, 1, 100, true).await;
+
+    eventually("the master to take the address", || async {
+        owners(&[&master, &backup]) == [true, false]
+    })
+    .await;
+    assert_eq!(hook_calls(&master.log), ["up"]);
+
+    network.disconnect(node_addr(0));
+    eventually("the backup to take over", || async {
+        owners(&[&backup]) == [true]
+    })
+    .await;
+    eventually("the up hook to run", || async {
+        hook_calls(&backup.log) == ["up"]
+    })
+    .await;
+
+    // preemption hands it back once the master is reachable again
+    network.reconnect(node_addr(0));
+    eventually("the master to take it back", || async {
+        owners(&[&master, &backup]) == [true, false]
+    })
+    .await;
+    eventually("the down hook to run", || async {
+        hook_calls(&backup.log) == ["up", "down"]
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn without_preemption_the_owner_keeps_it() {
+    let network = MemoryNetwork::new(7);
+    let first = start_node(&network, 0, 100, false).await;
+    eventually("the only node to take the address", || async {
+        owners(&[&first]) == [true]
+    })
+    .await;
+
+    let second = start_node(&network, 1, 200, false).await;
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert_eq!(owners(&[&first, &second]), [true, false]);
+    assert!(hook_calls(&second.log).is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn a_slow_hook_does_not_hold_up_other_addresses() {
+    let network = MemoryNetwork::new(7);
+    let log = hook_log("slow-and-fast");
+    let slow = vec!["sleep".to_string(), "5".to_string()];
+    let vips = Arc::new(VirtualIps::new(vec![
+        VirtualIpConfig {
+            addr: "10.0.0.101".parse().unwrap(),
+            priority: 100,
+            preempt: true,
+            up_command: slow.clone(),
+            down_command: slow,
+        },
+        VirtualIpConfig {
+            addr: vip(),
+            priority: 100,
+            preempt: true,
+            up_command: hook(&log),
+            down_command: hook(&log),
+        },
+    ]));
+    start_node_with(&network, 0, vips.clone()).await;
+
+    eventually("both addresses to be taken", || async {
+        vips.owned().len() == 2
+    })
+    .await;
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while hook_calls(&log) != ["up"] {
+        assert!(Instant::now() < deadline, "the fast hook waited");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn shutting_down_releases_the_address() {
+    let network = MemoryNetwork::new(7);
+    let node = start_node(&network, 0, 100, true).await;
+    eventually("the up hook to run", || async {
+        hook_calls(&node.log) == ["up"]
+    })
+    .await;
+
+    node.vips.release_all().await;
+    assert_eq!(hook_calls(&node.log), ["up", "down"]);
+    assert!(node.vips.owned().is_empty());
+
+    // and it isn't claimed again while the process winds down
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(node.vips.owned().is_empty());
+    assert_eq!(hook_calls(&node.log), ["up", "down"]);
+}
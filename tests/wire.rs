@@ -0,0 +1,93 @@
+mod common;
+
+use common::{backend_pool, gossip_config};
+use flux::gossip::{GossipLayer, GossipMessage, MemberId};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+fn indirect_ping(i: u16) -> GossipMessage {
+    GossipMessage::IndirectPing {
+        from: MemberId::new("a".to_string()),
+        from_addr: "127.0.0.1:7946".parse().unwrap(),
+        target_id: MemberId::new(format!("target-{i}")),
+        target_addr: SocketAddr::from(([127, 0, 0, 1], 8000 + i)),
+    }
+}
+
+#[test]
+fn small_messages_share_a_packet() {
+    let packed = GossipMessage::pack((0..10).map(indirect_ping).collect());
+    assert_eq!(packed.len(), 1);
+
+    let (bytes, _) = packed[0].encode("flux", true).unwrap();
+    assert!(bytes.len() <= 1400);
+    match GossipMessage::decode(&bytes).unwrap() {
+        (cluster, GossipMessage::Compound(messages)) => {
+            assert_eq!(cluster, "flux");
+            assert_eq!(messages.len(), 10);
+        }
+        other => panic!("expected a compound message, got {:?}", other),
+    }
+}
+
+#[test]
+fn packing_splits_at_packet_size() {
+    let packed = GossipMessage::pack((0..100).map(indirect_ping).collect());
+    assert!(packed.len() > 1);
+
+    let mut total = 0;
+    for message in &packed {
+        let (bytes, _) = message.encode("flux", false).unwrap();
+        assert!(bytes.len() <= 1400);
+        total += match message {
+            GossipMessage::Compound(messages) => messages.len(),
+            _ => 1,
+        };
+    }
+    assert_eq!(total, 100);
+}
+
+#[test]
+fn single_message_is_not_wrapped() {
+    let packed = GossipMessage::pack(vec![indirect_ping(0)]);
+    assert!(matches!(packed[..], [GossipMessage::IndirectPing { .. }]));
+}
+
+async fn recv_count(socket: &UdpSocket) -> usize {
+    let mut buf = vec![0u8; 65535];
+    let mut count = 0;
+    while let Ok(Ok((len, _))) =
+        tokio::time::timeout(Duration::from_millis(200), socket.recv_from(&mut buf)).await
+    {
+        GossipMessage::decode(&buf[..len]).unwrap();
+        count += 1;
+    }
+    count
+}
+
+#[tokio::test]
+async fn batch_reaches_every_peer() {
+    let config = gossip_config(&[], "gossip_interval_ms = 100");
+    let id = MemberId::new("sender".to_string());
+    let layer = GossipLayer::new(id, &config, backend_pool("sender", vec![]))
+        .await
+        .unwrap();
+
+    let a = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let b = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let (a_addr, b_addr) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+
+    // neither peer is a known member, so nothing gets packed into compounds
+    let mut outbox = Vec::new();
+    for i in 0..3 {
+        outbox.push((a_addr, indirect_ping(i)));
+    }
+    for i in 0..2 {
+        outbox.push((b_addr, indirect_ping(i)));
+    }
+    layer.send_batch(outbox).await;
+
+    assert_eq!(recv_count(&a).await, 3);
+    assert_eq!(recv_count(&b).await, 2);
+}
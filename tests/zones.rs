@@ -0,0 +1,76 @@
+use flux::backend::{Backend, BackendOverride, BackendPool, OverrideMode};
+use flux::gossip::{HybridClock, MemberId};
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+// two backends in each of zone a and zone b
+fn backend(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
+
+fn pool() -> BackendPool {
+    let backends = [(9, "a"), (10, "a"), (11, "b"), (12, "b")]
+        .into_iter()
+        .map(|(port, zone)| Backend {
+            zone: Some(zone.to_string()),
+            ..Backend::new(backend(port), 1)
+        })
+        .collect();
+    BackendPool::new(
+        backends,
+        MemberId::new("node-0".to_string()),
+        Arc::new(HybridClock::new()),
+    )
+}
+
+fn routed_to(pool: &BackendPool) -> BTreeSet<u16> {
+    (0..8)
+        .map(|_| pool.select_backend().unwrap().addr.port())
+        .collect()
+}
+
+fn mark_unhealthy(pool: &mut BackendPool, port: u16) {
+    pool.update_health(backend(port), false);
+    pool.update_health(backend(port), false);
+}
+
+#[test]
+fn without_a_zone_every_backend_gets_traffic() {
+    assert_eq!(routed_to(&pool()), BTreeSet::from([9, 10, 11, 12]));
+}
+
+#[test]
+fn traffic_stays_in_zone_while_it_has_capacity() {
+    let mut pool = pool();
+    pool.prefer_zone("a".to_string(), 50);
+    assert_eq!(routed_to(&pool), BTreeSet::from([9, 10]));
+
+    // half of the zone is still enough
+    mark_unhealthy(&mut pool, 9);
+    assert_eq!(routed_to(&pool), BTreeSet::from([10]));
+
+    pool.set_override(
+        backend(10),
+        Some(BackendOverride::new(OverrideMode::Drain, None)),
+    );
+    assert_eq!(routed_to(&pool), BTreeSet::from([11, 12]));
+
+    pool.set_override(backend(10), None);
+    assert_eq!(routed_to(&pool), BTreeSet::from([10]));
+}
+
+#[test]
+fn spills_over_below_the_threshold() {
+    let mut pool = pool();
+    pool.prefer_zone("a".to_string(), 75);
+    mark_unhealthy(&mut pool, 9);
+    assert_eq!(routed_to(&pool), BTreeSet::from([10, 11, 12]));
+}
+
+#[test]
+fn a_zone_without_backends_routes_everywhere() {
+    let mut pool = pool();
+    pool.prefer_zone("c".to_string(), 50);
+    assert_eq!(routed_to(&pool), BTreeSet::from([9, 10, 11, 12]));
+}